@@ -64,7 +64,7 @@ $ cargo asm --lib "cargo_show_asm::opts::focus::{{closure}}" 2
 Or start using full names with hex included:
 
 ```console,ignore
-$ cargo asm --lib --full-name
+$ cargo asm --lib --keep-hash
 # ...
 $ cargo asm --lib "once_cell::imp::OnceCell<T>::initialize::h9c5c7d5bd745000b"
 ```
@@ -83,6 +83,16 @@ generic parameters and if it is exported (in case of a library) and not inlined
 binary, example, test, etc). If your function takes a generic parameter - try making a monomorphic
 wrapper around it and make it `pub` and `#[inline(never)]`.
 
+# Code from dependencies
+
+Generic functions from dependencies are instantiated in your crate and can be found among its
+functions. To see the rest of the code of a dependency use `--dep CRATE`: `cargo-show-asm`
+builds the library of that dependency from the version in `Cargo.lock`, with the same profile,
+target and codegen flags as your crate. Features are the ones cargo enables for the dependency
+when building it on its own, they can differ from a full build if other packages ask for more.
+Inlining into your crate is not shown this way, use `--objdump` on a binary for that. The
+standard library comes prebuilt and can't be picked with `--dep`.
+
 # Include related functions?
 
 So suppose you have a function `foo` that calls some other function - `bar`. With `--context N`
@@ -102,6 +112,54 @@ This is done recursively up to N steps. See https://github.com/pacak/cargo-show-
 
 * `cargo-show-asm` also supports MIR (note that the formatting of human-readable MIR is not stable).
 
+# Default options
+
+Options you use all the time can be set in `Cargo.toml`, either for a package or for the whole
+workspace. Keys are long option names, use `true` for flags and arrays for options that can be
+repeated:
+
+```toml
+[workspace.metadata.show-asm]
+rust = true
+release = true
+features = ["simd"]
+syntax = "att"
+```
+
+`syntax` takes a syntax option name such as `att`, `intel` or `llvm`, misspelled names are
+reported with a suggestion.
+
+Options passed on a command line or set with environment variables take priority over the ones
+from `Cargo.toml`, invalid entries are reported and ignored.
+
+Syntax and formatting defaults can also come from environment variables: `CARGO_ASM_SYNTAX`
+takes a syntax option name such as `att` or `llvm`, `CARGO_ASM_FORMAT` takes comma separated
+option names with values after `=`:
+
+```console
+$ export CARGO_ASM_SYNTAX=att CARGO_ASM_FORMAT=rust,context=1
+```
+
+# Using your own LLVM flags
+
+For `--intel`, `--att` and the `llvm-mca` outputs on x86 `cargo-show-asm` passes
+`-Cllvm-args=-x86-asm-syntax=...` to rustc, after any `-C` flags from the command line. LLVM
+rejects options given more than once, so if `.cargo/config.toml` or `RUSTFLAGS` already set the
+assembly syntax, use `--no-syntax-flag` to leave the flag out and get the syntax your flags ask
+for. `--att` and `--intel` have no effect on the syntax in this case.
+
+# Exit codes
+
+Exit codes are stable and can be used in scripts:
+
+| Code | Meaning |
+|------|---------|
+| 0 | Success, `--compare` found no differences |
+| 1 | Any other error, `--compare` found differences |
+| 2 | Cargo failed to build the crate |
+| 3 | Nothing matches the requested function, package or target |
+| 4 | Several functions, packages or targets match and one needs to be picked |
+
 # Shell completion
 
 `cargo-asm` comes with shell completion generated by [`bpaf`](https://crates.io/crates/bpaf),
@@ -116,6 +174,9 @@ $ cargo-asm --bpaf-complete-style-elvish
 
 You'll need to use it as `cargo-asm` command rather than `cargo asm` to take advantage of it.
 
+Besides option names completion also suggests package names for `--package` and target names
+for `--bin`, `--example`, `--test` and `--bench` taken from the workspace in current directory.
+
 
 # Colorful line parser output
 
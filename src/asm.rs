@@ -3,15 +3,27 @@ use crate::asm::statements::{GenericDirective, Label};
 use crate::cached_lines::CachedLines;
 use crate::demangle::LabelKind;
 use crate::{
-    color, demangle, esafeprintln, get_context_for, get_dump_range, safeprintln, Item, RawLines,
+    color, dedup_items, demangle, esafeprintln, get_context_for, get_dump_range, get_neighbors_for,
+    list_sizes, mangled_header, markdown_end, markdown_start, print_summary, remarks::Remark,
+    safeprint, safeprintln, same_part, theme, Item, Parsed, RawLines, Rendered,
 };
 // TODO, use https://sourceware.org/binutils/docs/as/index.html
-use crate::opts::{Format, NameDisplay, RedundantLabels, SourcesFrom, ToDump};
+use crate::opts::{
+    Format, GroupBy, LabelRange, OutputStyle, RedundantLabels, SourceLocation, SourcesFrom, ToDump,
+};
 
+mod cfg;
+pub mod cost;
+mod frame;
 mod statements;
+mod stats;
 
+use cfg::Cfg;
+use frame::Frame;
 use owo_colors::OwoColorize;
-use statements::{parse_statement, Directive, Loc, Statement};
+use serde::Serialize;
+use statements::{parse_statement, Directive, Loc, Statement, Styled};
+use stats::Stats;
 use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet};
 use std::ops::Range;
@@ -19,7 +31,7 @@ use std::path::{Path, PathBuf};
 
 type SourceFile<'a> = (Cow<'a, Path>, Option<(Source, CachedLines)>);
 
-pub fn parse_file(input: &str) -> anyhow::Result<Vec<Statement>> {
+pub fn parse_file(input: &str) -> anyhow::Result<Vec<Statement<'_>>> {
     // eat all statements until the eof, so we can report the proper errors on failed parse
     match nom::multi::many0(parse_statement)(input) {
         Ok(("", stmts)) => Ok(stmts),
@@ -41,6 +53,26 @@ pub fn parse_file(input: &str) -> anyhow::Result<Vec<Statement>> {
     }
 }
 
+/// Every generated file numbers its source files from the start, give files from later `parts`
+/// numbers past the ones used before them so `.loc` directives keep pointing to the right files
+fn renumber_files(statements: &mut [Statement], parts: &[Range<usize>]) {
+    let mut offset = 0;
+    for part in parts {
+        let mut next = offset;
+        for stmt in &mut statements[part.clone()] {
+            match stmt {
+                Statement::Directive(Directive::File(file)) => {
+                    file.index += offset;
+                    next = next.max(file.index + 1);
+                }
+                Statement::Directive(Directive::Loc(loc)) => loc.file += offset,
+                _ => {}
+            }
+        }
+        offset = next;
+    }
+}
+
 #[must_use]
 pub fn find_items(lines: &[Statement]) -> BTreeMap<Item, Range<usize>> {
     let mut res = BTreeMap::new();
@@ -93,6 +125,7 @@ pub fn find_items(lines: &[Statement]) -> BTreeMap<Item, Range<usize>> {
                     index: *name_entry,
                     len: ix,
                     non_blank_len: 0,
+                    duplicates: 0,
                 });
                 *name_entry += 1;
             } else if matches!(label.kind, LabelKind::Unknown | LabelKind::Global) {
@@ -108,6 +141,63 @@ pub fn find_items(lines: &[Statement]) -> BTreeMap<Item, Range<usize>> {
     res
 }
 
+/// Section each statement belongs to, `None` before the first section directive
+fn sections<'a>(stmts: &[Statement<'a>]) -> Vec<Option<&'a str>> {
+    let mut section = None;
+    stmts
+        .iter()
+        .map(|stmt| {
+            match stmt {
+                Statement::Directive(Directive::SectionStart(name)) => section = Some(*name),
+                Statement::Directive(Directive::Generic(GenericDirective(dir)))
+                    if matches!(*dir, "text" | "data" | "bss") =>
+                {
+                    section = Some(*dir);
+                }
+                _ => {}
+            }
+            section
+        })
+        .collect()
+}
+
+/// Check if `section` is called `name` or is a subsection of it: `.text.foo` is in `.text`
+///
+/// Sections on Mac are given along with their segment, `__TEXT,__text` can be matched either by
+/// the segment or by the whole name
+fn section_matches(section: &str, name: &str) -> bool {
+    let section = section.trim();
+    let first = section.split(',').next().unwrap_or(section).trim();
+    first == name
+        || first
+            .strip_prefix(name)
+            .is_some_and(|rest| rest.starts_with('.'))
+        || (name.contains(',') && section.starts_with(name))
+}
+
+/// Keep only the items defined in sections picked by `--section`, all of them without it
+#[must_use]
+pub fn in_sections(
+    items: BTreeMap<Item, Range<usize>>,
+    stmts: &[Statement],
+    names: &[String],
+) -> BTreeMap<Item, Range<usize>> {
+    if names.is_empty() {
+        return items;
+    }
+    let sections = sections(stmts);
+    items
+        .into_iter()
+        .filter(|(_, range)| {
+            sections
+                .get(range.start)
+                .copied()
+                .flatten()
+                .is_some_and(|section| names.iter().any(|name| section_matches(section, name)))
+        })
+        .collect()
+}
+
 /// Handles the non-mangled labels found in the given lines of ASM statements.
 ///
 /// Returns item if the label is a valid function item, otherwise returns None.
@@ -169,6 +259,7 @@ fn get_item_in_section(
                 index: 0, // Written later in find_items
                 len: ix,
                 non_blank_len: 0,
+                duplicates: 0,
             });
         }
     }
@@ -196,24 +287,230 @@ fn used_labels<'a>(stmts: &'_ [Statement<'a>]) -> BTreeSet<&'a str> {
         .collect::<BTreeSet<_>>()
 }
 
+/// New names for local labels defined in `stmts` and used by instructions: `.L1`, `.L2`, etc in
+/// order of definition. Labels only used by debug info and such are kept as is
+fn label_names<'a>(stmts: &[Statement<'a>]) -> BTreeMap<&'a str, String> {
+    let targets = stmts
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::Instruction(i) => i.args,
+            _ => None,
+        })
+        .flat_map(demangle::local_labels)
+        .map(|m| m.as_str().trim_start_matches(|c| c != '.' && c != 'L'))
+        .collect::<BTreeSet<_>>();
+    let mut names = BTreeMap::new();
+    for stmt in stmts {
+        if let Statement::Label(Label {
+            id,
+            kind: LabelKind::Local,
+        }) = stmt
+        {
+            if !targets.contains(id) {
+                continue;
+            }
+            let name = format!(".L{}", names.len() + 1);
+            names.entry(*id).or_insert(name);
+        }
+    }
+    names
+}
+
+/// Text of statements with local labels renamed, `None` for statements without labels
+fn renamed_labels(stmts: &[Statement], names: &BTreeMap<&str, String>) -> Vec<Option<String>> {
+    let rename = |text: &str| match demangle::rename_local_labels(text, names) {
+        Cow::Borrowed(_) => None,
+        Cow::Owned(text) => Some(text),
+    };
+    stmts
+        .iter()
+        .map(|stmt| match stmt {
+            Statement::Label(label) => names.get(label.id).cloned(),
+            Statement::Instruction(i) => i.args.and_then(rename),
+            Statement::Directive(Directive::Generic(g)) => rename(g.0),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Statements with text replaced by [`renamed_labels`]
+fn with_renamed_labels<'a>(
+    stmts: &[Statement<'a>],
+    texts: &'a [Option<String>],
+) -> Vec<Statement<'a>> {
+    stmts
+        .iter()
+        .zip(texts)
+        .map(|(stmt, text)| match (stmt, text) {
+            (Statement::Label(label), Some(text)) => Statement::Label(Label {
+                id: text,
+                kind: label.kind,
+            }),
+            (Statement::Instruction(i), Some(text)) => {
+                let mut i = i.clone();
+                i.args = Some(text);
+                Statement::Instruction(i)
+            }
+            (Statement::Directive(Directive::Generic(_)), Some(text)) => {
+                Statement::Directive(Directive::Generic(GenericDirective(text)))
+            }
+            (stmt, _) => stmt.clone(),
+        })
+        .collect()
+}
+
+/// Statements between labels picked with `--range`, labels can be named with or without
+/// the leading dot
+fn label_range(stmts: &[Statement], part: &LabelRange) -> anyhow::Result<Range<usize>> {
+    let find = |name: &str| {
+        stmts
+            .iter()
+            .position(|stmt| {
+                matches!(stmt, Statement::Label(label)
+                    if label.id.trim_start_matches('.') == name.trim_start_matches('.'))
+            })
+            .ok_or_else(|| anyhow::anyhow!("Label {name} is not found in the selected function"))
+    };
+    let start = part.start.as_deref().map(find).transpose()?.unwrap_or(0);
+    let end = match part.end.as_deref() {
+        None => stmts.len(),
+        Some(name) if part.inclusive => {
+            let end = find(name)? + 1;
+            stmts[end..]
+                .iter()
+                .position(|stmt| {
+                    matches!(
+                        stmt,
+                        Statement::Label(Label {
+                            kind: LabelKind::Local,
+                            ..
+                        })
+                    )
+                })
+                .map_or(stmts.len(), |ix| end + ix)
+        }
+        Some(name) => find(name)?,
+    };
+    if start > end {
+        anyhow::bail!("--range ends before it starts");
+    }
+    Ok(start..end)
+}
+
 pub fn dump_range(
     files: &BTreeMap<u64, SourceFile>,
     fmt: &Format,
     stmts: &[Statement],
 ) -> anyhow::Result<()> {
+    dump_part(files, fmt, stmts, None)
+}
+
+/// File the function itself comes from: the first location is for its prologue
+fn own_file(stmts: &[Statement]) -> Option<u64> {
+    stmts.iter().find_map(|stmt| match stmt {
+        Statement::Directive(Directive::Loc(loc)) if loc.line != 0 => Some(loc.file),
+        _ => None,
+    })
+}
+
+/// Statements `--head` and `--tail` leave out, along with the number of instructions in them
+///
+/// Whatever comes before the first instruction stays so the function still has its label
+fn hidden_middle(
+    stmts: &[Statement],
+    head: Option<usize>,
+    tail: Option<usize>,
+) -> Option<(Range<usize>, usize)> {
+    if head.is_none() && tail.is_none() {
+        return None;
+    }
+    let (head, tail) = (head.unwrap_or(0), tail.unwrap_or(0));
+    let instructions = stmts
+        .iter()
+        .enumerate()
+        .filter(|(_, stmt)| matches!(stmt, Statement::Instruction(i) if !i.op.starts_with('#')))
+        .map(|(ix, _)| ix)
+        .collect::<Vec<_>>();
+    let hidden = instructions
+        .len()
+        .checked_sub(head + tail)
+        .filter(|n| *n > 0)?;
+    let start = match head {
+        0 => instructions[0],
+        n => instructions[n - 1] + 1,
+    };
+    let end = match tail {
+        0 => stmts.len(),
+        n => instructions[instructions.len() - n],
+    };
+    Some((start..end, hidden))
+}
+
+/// Dump statements, only the ones between labels picked by `part` if it's present
+fn dump_part(
+    files: &BTreeMap<u64, SourceFile>,
+    fmt: &Format,
+    stmts: &[Statement],
+    part: Option<&LabelRange>,
+) -> anyhow::Result<()> {
+    let texts;
+    let renamed;
+    let stmts = if fmt.rename_labels {
+        texts = renamed_labels(stmts, &label_names(stmts));
+        renamed = with_renamed_labels(stmts, &texts);
+        &renamed[..]
+    } else {
+        stmts
+    };
+
     let mut prev_loc = Loc::default();
+    // sources from other files are there because of inlining, they get an extra note
+    let own_file = own_file(stmts);
+    let mut prev_file = own_file;
 
     let used = if fmt.redundant_labels == RedundantLabels::Keep {
         BTreeSet::new()
     } else {
         used_labels(stmts)
     };
+    // labels are renamed and checked for uses in the whole function first
+    let stmts = match part {
+        Some(part) => &stmts[label_range(stmts, part)?],
+        None => stmts,
+    };
+
+    let hidden = hidden_middle(stmts, fmt.head, fmt.tail);
+    // with --rust the code after the hidden part still gets the source line it comes from
+    let kept_loc = hidden.as_ref().and_then(|(range, _)| {
+        if range.end == stmts.len() {
+            return None;
+        }
+        range
+            .clone()
+            .rev()
+            .find(|ix| matches!(stmts[*ix], Statement::Directive(Directive::Loc(_))))
+    });
 
     let mut empty_line = false;
-    for line in stmts {
+    let mut seen_lines = BTreeSet::new();
+    let mut index = 0;
+    for (ix, line) in stmts.iter().enumerate() {
         if fmt.verbosity > 2 {
             safeprintln!("{line:?}");
         }
+        if let Some((range, count)) = &hidden {
+            if ix == range.start {
+                let plural = if *count == 1 { "" } else { "s" };
+                let note = format!("\t... {count} instruction{plural} omitted");
+                safeprintln!("{}", color!(note, theme::comment));
+            }
+            if range.contains(&ix) && Some(ix) != kept_loc {
+                if matches!(line, Statement::Instruction(_)) {
+                    index += 1;
+                }
+                continue;
+            }
+        }
         if let Statement::Directive(Directive::File(_)) = &line {
         } else if let Statement::Directive(Directive::Loc(loc)) = &line {
             if !fmt.rust {
@@ -229,20 +526,28 @@ pub fn dump_range(
             match files.get(&loc.file) {
                 Some((fname, Some((source, file)))) => {
                     if source.show_for(fmt.sources_from) {
+                        if Some(loc.file) != own_file && Some(loc.file) != prev_file {
+                            let note = format!("\t\t// inlined from {}", fname.display());
+                            safeprintln!("{}", color!(note, theme::location));
+                        }
+                        prev_file = Some(loc.file);
                         let rust_line = &file[loc.line as usize - 1];
-                        let pos = format!("\t\t// {} : {}", fname.display(), loc.line);
-                        safeprintln!("{}", color!(pos, OwoColorize::cyan));
-                        safeprintln!(
-                            "\t\t{}",
-                            color!(rust_line.trim_start(), OwoColorize::bright_red)
-                        );
+                        let pos = if fmt.rust_numbers {
+                            format!("\t\t// {}:{}", fname.display(), loc.line)
+                        } else {
+                            format!("\t\t// {} : {}", fname.display(), loc.line)
+                        };
+                        safeprintln!("{}", color!(pos, theme::location));
+                        if !fmt.rust_numbers || seen_lines.insert((loc.file, loc.line)) {
+                            safeprintln!("\t\t{}", color!(rust_line.trim_start(), theme::source));
+                        }
                     }
                 }
                 Some((fname, None)) => {
                     if fmt.verbosity > 0 {
                         safeprintln!(
                             "\t\t{} {}",
-                            color!("//", OwoColorize::cyan),
+                            color!("//", theme::location),
                             color!(
                                 "Can't locate the file, please open a ticket with cargo-show-asm",
                                 OwoColorize::red
@@ -250,7 +555,7 @@ pub fn dump_range(
                         );
                     }
                     let pos = format!("\t\t// {} : {}", fname.display(), loc.line);
-                    safeprintln!("{}", color!(pos, OwoColorize::cyan));
+                    safeprintln!("{}", color!(pos, theme::location));
                 }
                 None => {
                     panic!("DWARF file refers to an undefined location {loc:?}");
@@ -278,21 +583,305 @@ pub fn dump_range(
                 RedundantLabels::Strip => {}
             }
         } else {
-            if fmt.simplify && matches!(line, Statement::Directive(_) | Statement::Dunno(_)) {
+            if (fmt.simplify || fmt.no_directives)
+                && matches!(line, Statement::Directive(_) | Statement::Dunno(_))
+            {
+                continue;
+            }
+            if fmt.no_directives
+                && matches!(line, Statement::Instruction(i) if i.op.starts_with('#'))
+            {
                 continue;
             }
 
             empty_line = false;
-            match fmt.name_display {
-                NameDisplay::Full => safeprintln!("{line:#}"),
-                NameDisplay::Short => safeprintln!("{line}"),
-                NameDisplay::Mangled => safeprintln!("{line:-}"),
+            let offset = match line {
+                Statement::Instruction(_) if fmt.offsets => {
+                    index += 1;
+                    color!(format!("{:>4}", index - 1), theme::location).to_string()
+                }
+                _ => String::new(),
+            };
+            let notes = [call_target(fmt, line), instruction_cost(fmt, line)]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>();
+            let width = fmt.operand_column();
+            if notes.is_empty() {
+                safeprintln!(
+                    "{offset}{:width$}",
+                    Styled(line, fmt.name_display, fmt.keep_hash)
+                );
+            } else {
+                safeprintln!(
+                    "{offset}{:width$}\t{}",
+                    Styled(line, fmt.name_display, fmt.keep_hash),
+                    color!(format!("# {}", notes.join(", ")), theme::comment)
+                );
             }
         }
     }
     Ok(())
 }
 
+/// Instructions by the source line they come from, instructions before the first location or
+/// at line 0 are unattributed
+#[allow(clippy::type_complexity)]
+fn group_by_line<'a>(
+    stmts: &'a [Statement<'a>],
+) -> (
+    BTreeMap<(u64, u64), Vec<&'a Statement<'a>>>,
+    Vec<&'a Statement<'a>>,
+) {
+    let mut groups = BTreeMap::<_, Vec<_>>::new();
+    let mut unattributed = Vec::new();
+    let mut current = None;
+    for stmt in stmts {
+        match stmt {
+            Statement::Directive(Directive::Loc(loc)) => {
+                current = (loc.line != 0).then_some((loc.file, loc.line));
+            }
+            Statement::Instruction(i) if !i.op.starts_with('#') => match current {
+                Some(key) => groups.entry(key).or_default().push(stmt),
+                None => unattributed.push(stmt),
+            },
+            _ => {}
+        }
+    }
+    (groups, unattributed)
+}
+
+/// Dump instructions grouped under the source lines they come from, for `--group-by line`
+fn dump_by_line(
+    files: &BTreeMap<u64, SourceFile>,
+    fmt: &Format,
+    stmts: &[Statement],
+    part: Option<&LabelRange>,
+) -> anyhow::Result<()> {
+    let stmts = match part {
+        Some(part) => &stmts[label_range(stmts, part)?],
+        None => stmts,
+    };
+    let (groups, unattributed) = group_by_line(stmts);
+    let width = fmt.operand_column();
+    let print_group = |header: String, source: Option<&str>, group: &[&Statement]| {
+        let count = match group.len() {
+            1 => "1 instruction".to_owned(),
+            n => format!("{n} instructions"),
+        };
+        safeprintln!(
+            "{}",
+            color!(format!("\t\t// {header}, {count}"), theme::location)
+        );
+        if let Some(source) = source {
+            safeprintln!("\t\t{}", color!(source.trim_start(), theme::source));
+        }
+        for stmt in group {
+            safeprintln!("{:width$}", Styled(stmt, fmt.name_display, fmt.keep_hash));
+        }
+        safeprintln!();
+    };
+    for ((file, line), group) in &groups {
+        match files.get(file) {
+            Some((fname, sources)) => {
+                let source = sources
+                    .as_ref()
+                    .and_then(|(_, lines)| lines.get(*line as usize - 1));
+                print_group(format!("{}:{line}", fname.display()), source, group);
+            }
+            None => print_group(format!("file #{file}:{line}"), None, group),
+        }
+    }
+    if !unattributed.is_empty() {
+        print_group("unattributed".to_owned(), None, &unattributed);
+    }
+    Ok(())
+}
+
+/// Demangled name of a symbol called or jumped to, for `--resolve-calls`
+///
+/// Targets that are not rust symbols, such as local labels or some PLT entries, are not resolved
+fn call_target(fmt: &Format, line: &Statement) -> Option<String> {
+    let Statement::Instruction(i) = line else {
+        return None;
+    };
+    if !fmt.resolve_calls || !stats::is_branch_or_call(i.op) {
+        return None;
+    }
+    let dem = demangle::demangled(demangle::global_reference(i.args?)?)?;
+    Some(if fmt.keep_hash {
+        format!("{dem}")
+    } else {
+        format!("{dem:#}")
+    })
+}
+
+/// Latency and throughput of an instruction, for `--annotate-cost`
+///
+/// Instructions missing from the table are left without a note rather than guessed
+fn instruction_cost(fmt: &Format, line: &Statement) -> Option<String> {
+    let Statement::Instruction(i) = line else {
+        return None;
+    };
+    if !fmt.annotate_cost || i.op.starts_with('#') {
+        return None;
+    }
+    let table = cost::table(fmt.cost_cpu.as_deref()?)?;
+    Some(cost::cost(table, &i.op.to_ascii_lowercase())?.to_string())
+}
+
+#[derive(Serialize)]
+struct JsonFunction<'a> {
+    name: Option<Cow<'a, str>>,
+    mangled_name: Option<&'a str>,
+    lines: Vec<JsonLine>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<Stats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frame: Option<Frame>,
+}
+
+#[derive(Serialize)]
+struct JsonLine {
+    kind: &'static str,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<JsonSource>,
+}
+
+#[derive(Serialize, Clone)]
+struct JsonSource {
+    file: String,
+    line: u64,
+}
+
+/// Dump statements as a single JSON document, source locations are included with `--rust`
+fn dump_json(
+    files: &BTreeMap<u64, SourceFile>,
+    fmt: &Format,
+    item: Option<&Item>,
+    stmts: &[Statement],
+) -> anyhow::Result<()> {
+    let mut source = None;
+    let mut lines = Vec::new();
+    for line in stmts {
+        let kind = match line {
+            Statement::Directive(Directive::File(_) | Directive::Loc(_)) if fmt.rust => {
+                if let Statement::Directive(Directive::Loc(loc)) = line {
+                    source = files
+                        .get(&loc.file)
+                        .filter(|_| loc.line != 0)
+                        .map(|(fname, _)| JsonSource {
+                            file: fname.display().to_string(),
+                            line: loc.line,
+                        });
+                }
+                continue;
+            }
+            Statement::Nothing => continue,
+            Statement::Label(_) => "label",
+            Statement::Directive(_) => "directive",
+            Statement::Instruction(i) if i.op.starts_with('#') => "comment",
+            Statement::Instruction(_) => "instruction",
+            Statement::Dunno(_) => "other",
+        };
+        let text = Styled(line, fmt.name_display, fmt.keep_hash).to_string();
+        lines.push(JsonLine {
+            kind,
+            text: text.trim_start().to_owned(),
+            source: if kind == "instruction" {
+                source.clone()
+            } else {
+                None
+            },
+        });
+    }
+
+    let function = JsonFunction {
+        name: item.map(|item| item.display_name(fmt.name_display, fmt.keep_hash)),
+        mangled_name: item.map(|item| item.mangled_name.as_str()),
+        lines,
+        stats: fmt.stats.then(|| Stats::collect(stmts)),
+        frame: fmt.frame.then(|| Frame::collect(stmts)).flatten(),
+    };
+    safeprintln!("{}", serde_json::to_string_pretty(&function)?);
+    Ok(())
+}
+
+/// Dump statements as a section of an HTML page, source locations are included with `--rust`
+fn dump_html(
+    files: &BTreeMap<u64, SourceFile>,
+    fmt: &Format,
+    item: Option<&Item>,
+    stmts: &[Statement],
+) {
+    use crate::html::{Kind, Line};
+
+    let used = used_labels(stmts);
+    let mut prev_loc = Loc::default();
+    let mut lines = Vec::new();
+    for stmt in stmts {
+        let text = || {
+            Styled(stmt, fmt.name_display, fmt.keep_hash)
+                .to_string()
+                .trim()
+                .to_owned()
+        };
+        let kind = match stmt {
+            Statement::Nothing | Statement::Directive(Directive::File(_)) => continue,
+            Statement::Directive(Directive::Loc(loc)) => {
+                if !fmt.rust || loc.line == 0 || *loc == prev_loc {
+                    continue;
+                }
+                prev_loc = *loc;
+                let Some((fname, source)) = files.get(&loc.file) else {
+                    continue;
+                };
+                let code = match source {
+                    Some((source, _)) if !source.show_for(fmt.sources_from) => continue,
+                    Some((_, file)) => file
+                        .get(loc.line as usize - 1)
+                        .map(|line| line.trim_start().to_owned()),
+                    None => None,
+                };
+                let location = format!("{} : {}", fname.display(), loc.line);
+                lines.push(Line::Source { location, code });
+                continue;
+            }
+            Statement::Label(Label {
+                kind: LabelKind::Local | LabelKind::Temp,
+                id,
+            }) if fmt.redundant_labels != RedundantLabels::Keep && !used.contains(id) => continue,
+            Statement::Label(_) => Kind::Label,
+            Statement::Directive(_) | Statement::Dunno(_) if fmt.simplify || fmt.no_directives => {
+                continue
+            }
+            Statement::Directive(_) => Kind::Directive,
+            Statement::Dunno(_) => Kind::Other,
+            Statement::Instruction(i) if i.op.starts_with('#') => {
+                if fmt.no_directives {
+                    continue;
+                }
+                Kind::Comment
+            }
+            Statement::Instruction(_) => {
+                let text = text();
+                let (op, args) = text.split_once(char::is_whitespace).unwrap_or((&text, ""));
+                lines.push(Line::Instruction {
+                    op: op.to_owned(),
+                    args: args.trim_start().to_owned(),
+                });
+                continue;
+            }
+        };
+        lines.push(Line::Other { kind, text: text() });
+    }
+    let title = item.map_or(Cow::Borrowed("file"), |item| {
+        item.display_name(fmt.name_display, fmt.keep_hash)
+    });
+    safeprint!("{}", crate::html::section(&title, &lines));
+}
+
 #[derive(Debug, Clone)]
 pub enum Source {
     Crate,
@@ -333,7 +922,11 @@ impl Source {
 // 4. rustc sources:
 //    /rustc/89e2160c4ca5808657ed55392620ed1dbbce78d1/compiler/rustc_span/src/span_encoding.rs
 //    $sysroot/lib/rustlib/rust-src/rust/compiler/rustc_span/src/span_encoding.rs
-fn locate_sources(sysroot: &Path, workspace: &Path, path: &Path) -> Option<(Source, PathBuf)> {
+fn locate_sources(
+    sysroot: &Path,
+    workspace: &Path,
+    path: &Path,
+) -> anyhow::Result<Option<(Source, PathBuf)>> {
     // a real file that simply exists
     if path.exists() {
         let source = if path.starts_with(workspace) {
@@ -342,15 +935,14 @@ fn locate_sources(sysroot: &Path, workspace: &Path, path: &Path) -> Option<(Sour
             Source::External
         };
 
-        return Some((source, path.into()));
+        return Ok(Some((source, path.into())));
     }
 
     let no_rust_src = || {
-        esafeprintln!(
+        anyhow::anyhow!(
             "You need to install rustc sources to be able to see the rust annotations, try\n\
                                        \trustup component add rust-src"
-        );
-        std::process::exit(1);
+        )
     };
 
     // /rustc/89e2160c4ca5808657ed55392620ed1dbbce78d1/compiler/rustc_span/src/span_encoding.rs
@@ -361,9 +953,9 @@ fn locate_sources(sysroot: &Path, workspace: &Path, path: &Path) -> Option<(Sour
         }
 
         if source.exists() {
-            return Some((Source::Rustc, source));
+            return Ok(Some((Source::Rustc, source)));
         } else {
-            no_rust_src();
+            return Err(no_rust_src());
         }
     }
 
@@ -374,9 +966,9 @@ fn locate_sources(sysroot: &Path, workspace: &Path, path: &Path) -> Option<(Sour
             source.push(part);
         }
         if source.exists() {
-            return Some((Source::Stdlib, source));
+            return Ok(Some((Source::Stdlib, source)));
         } else {
-            no_rust_src();
+            return Err(no_rust_src());
         }
     }
 
@@ -387,9 +979,9 @@ fn locate_sources(sysroot: &Path, workspace: &Path, path: &Path) -> Option<(Sour
             source.push(part);
         }
         if source.exists() {
-            return Some((Source::Stdlib, source));
+            return Ok(Some((Source::Stdlib, source)));
         } else {
-            no_rust_src();
+            return Err(no_rust_src());
         }
     }
 
@@ -409,7 +1001,7 @@ fn locate_sources(sysroot: &Path, workspace: &Path, path: &Path) -> Option<(Sour
             source.push(part);
         }
         if source.exists() {
-            return Some((Source::External, source));
+            return Ok(Some((Source::External, source)));
         } else {
             panic!(
                 "{path:?} looks like it can be a cargo registry reference but we failed to get it"
@@ -417,48 +1009,294 @@ fn locate_sources(sysroot: &Path, workspace: &Path, path: &Path) -> Option<(Sour
         }
     }
 
+    Ok(None)
+}
+
+/// Items with code coming from a given source location
+///
+/// Fails if there's no debug info to look at or nothing covers the location
+fn items_at(
+    statements: &[Statement],
+    items: &BTreeMap<Item, Range<usize>>,
+    location: &SourceLocation,
+) -> anyhow::Result<BTreeMap<Item, Range<usize>>> {
+    let file_ids = statements
+        .iter()
+        .filter_map(|s| match s {
+            Statement::Directive(Directive::File(f))
+                if f.path.as_full_path().ends_with(&location.file) =>
+            {
+                Some(f.index)
+            }
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+    let has_locs = statements
+        .iter()
+        .any(|s| matches!(s, Statement::Directive(Directive::Loc(_))));
+
+    if !has_locs {
+        anyhow::bail!("No debug info found, it is required to find functions by source location");
+    }
+    if file_ids.is_empty() {
+        return Err(crate::ExitCode::NoMatch.error(format!(
+            "No source file matching {}",
+            location.file.display()
+        )));
+    }
+
+    let found = items
+        .iter()
+        .filter(|(_item, range)| {
+            statements[(*range).clone()].iter().any(|s| {
+                matches!(s, Statement::Directive(Directive::Loc(loc))
+                    if loc.line == location.line && file_ids.contains(&loc.file))
+            })
+        })
+        .map(|(item, range)| (item.clone(), range.clone()))
+        .collect::<BTreeMap<_, _>>();
+    if found.is_empty() {
+        return Err(crate::ExitCode::NoMatch.error(format!(
+            "No functions contain code from {}:{}",
+            location.file.display(),
+            location.line
+        )));
+    }
+    Ok(found)
+}
+
+/// Lines of a function definition named `name` in `source`, 1 based and inclusive
+///
+/// Braces are counted naively, this is good enough for the usual formatting
+fn definition_lines(source: &str, name: &str) -> Option<(u64, u64)> {
+    let definition = regex::Regex::new(&format!(r"\bfn\s+{}\b", regex::escape(name))).ok()?;
+    let mut lines = source.lines().zip(1u64..);
+    let (first, start) = lines.find(|(line, _)| definition.is_match(line))?;
+    let mut depth = 0usize;
+    // brackets and parentheses in the signature, `;` inside of them is part of a type
+    let mut nested = 0usize;
+    let mut opened = false;
+    for (line, ix) in std::iter::once((first, start)).chain(lines) {
+        for c in line.chars() {
+            match c {
+                '{' => {
+                    depth += 1;
+                    opened = true;
+                }
+                '}' => depth = depth.saturating_sub(1),
+                '(' | '[' if !opened => nested += 1,
+                ')' | ']' if !opened => nested = nested.saturating_sub(1),
+                ';' if !opened && nested == 0 => return None,
+                _ => {}
+            }
+        }
+        if opened && depth == 0 {
+            return Some((start, ix));
+        }
+    }
     None
 }
 
+/// Last segment of a function path without generics: `foo` for `crate::Bar<T>::foo`
+fn last_segment(function: &str) -> Option<String> {
+    let name = demangle::strip_generics(function);
+    name.rsplit("::")
+        .next()
+        .filter(|name| !name.is_empty() && name.chars().all(|c| c == '_' || c.is_alphanumeric()))
+        .map(str::to_owned)
+}
+
+/// Definition of a function named `name` in rust sources of the workspace: file and line
+///
+/// `target` and hidden directories are skipped
+fn find_definition(workspace: &Path, name: &str) -> Option<(PathBuf, u64)> {
+    let mut pending = vec![workspace.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            if file_name.to_string_lossy().starts_with('.') || file_name == "target" {
+                continue;
+            }
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                if let Some((line, _)) = std::fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|source| definition_lines(&source, name))
+                {
+                    return Some((path, line));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Functions that contain code from a definition of `function`, for when nothing matches it
+///
+/// A small function often gets inlined everywhere and has no code of its own, debug info still
+/// points to its source lines from the functions it was inlined into
+fn inlined_into<'a>(
+    statements: &[Statement],
+    items: &'a BTreeMap<Item, Range<usize>>,
+    workspace: &Path,
+    function: &str,
+) -> Vec<&'a Item> {
+    let Some(name) = last_segment(function) else {
+        return Vec::new();
+    };
+    let name = name.as_str();
+
+    // file index and lines of every definition with a matching name
+    let mut definitions = Vec::new();
+    for stmt in statements {
+        let Statement::Directive(Directive::File(f)) = stmt else {
+            continue;
+        };
+        let path = f.path.as_full_path();
+        if !path.starts_with(workspace) {
+            continue;
+        }
+        if let Some(lines) = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|source| definition_lines(&source, name))
+        {
+            definitions.push((f.index, lines));
+        }
+    }
+    if definitions.is_empty() {
+        return Vec::new();
+    }
+
+    items
+        .iter()
+        .filter(|(_, range)| {
+            statements[(*range).clone()].iter().any(|s| {
+                matches!(s, Statement::Directive(Directive::Loc(loc))
+                if definitions.iter().any(|(file, (start, end))| {
+                    loc.file == *file && (*start..=*end).contains(&loc.line)
+                }))
+            })
+        })
+        .map(|(item, _)| item)
+        .collect()
+}
+
+/// Explain that `goal` matches nothing because the function was inlined or removed as dead
+/// code, if that's the case
+fn report_inlined(
+    goal: &ToDump,
+    statements: &[Statement],
+    items: &BTreeMap<Item, Range<usize>>,
+    workspace: &Path,
+    fmt: &Format,
+) {
+    let ToDump::Function {
+        function,
+        regex: false,
+        ignore_case,
+        ..
+    } = goal
+    else {
+        return;
+    };
+    let Ok(matcher) = crate::function_matcher(function, false, *ignore_case) else {
+        return;
+    };
+    if items
+        .keys()
+        .any(|item| matcher.is_match(item.search_name()))
+    {
+        return;
+    }
+    let inlined = inlined_into(statements, items, workspace, function);
+    if inlined.is_empty() {
+        report_eliminated(workspace, function);
+        return;
+    }
+    safeprintln!("{function:?} has no code of its own, it appears to have been inlined into:");
+    for item in inlined.iter().take(5) {
+        safeprintln!(
+            "\t{}",
+            color!(
+                item.display_name(fmt.name_display, fmt.keep_hash),
+                theme::name
+            )
+        );
+    }
+    if inlined.len() > 5 {
+        safeprintln!("\tand {} more", inlined.len() - 5);
+    }
+    safeprintln!("Add #[inline(never)] to it to see it as a separate function");
+}
+
+/// Explain that `function` exists in the sources but got no code, most likely because it's
+/// never used
+fn report_eliminated(workspace: &Path, function: &str) {
+    let Some((path, line)) =
+        last_segment(function).and_then(|name| find_definition(workspace, &name))
+    else {
+        return;
+    };
+    let path = path.strip_prefix(workspace).unwrap_or(&path);
+    safeprintln!(
+        "{function:?} is defined in {}:{line}, but no code was generated for it",
+        path.display()
+    );
+    safeprintln!(
+        "Functions that are never used get removed as dead code and generic functions only get \
+        code when used with concrete types. Make it pub, mark it with #[no_mangle] or call it \
+        from a binary or a test to keep it"
+    );
+}
+
 fn load_rust_sources<'a>(
     sysroot: &Path,
     workspace: &Path,
     statements: &'a [Statement],
     fmt: &Format,
     files: &mut BTreeMap<u64, SourceFile<'a>>,
-) {
+) -> anyhow::Result<()> {
     for line in statements {
         if let Statement::Directive(Directive::File(f)) = line {
-            files.entry(f.index).or_insert_with(|| {
-                let path = f.path.as_full_path();
-                if fmt.verbosity > 1 {
-                    safeprintln!("Reading file #{} {}", f.index, path.display());
-                }
+            if files.contains_key(&f.index) {
+                continue;
+            }
+            let path = f.path.as_full_path();
+            if fmt.verbosity > 1 {
+                safeprintln!("Reading file #{} {}", f.index, path.display());
+            }
 
-                if let Some((source, filepath)) = locate_sources(sysroot, workspace, &path) {
-                    if fmt.verbosity > 2 {
-                        safeprintln!("Resolved name is {filepath:?}");
-                    }
-                    let sources = std::fs::read_to_string(&filepath).expect("Can't read a file");
-                    if sources.is_empty() {
-                        safeprintln!("Ignoring empty file {filepath:?}!");
-                        (path, None)
-                    } else {
-                        if fmt.verbosity > 2 {
-                            safeprintln!("Got {} bytes", sources.len());
-                        }
-                        let lines = CachedLines::without_ending(sources);
-                        (path, Some((source, lines)))
-                    }
+            let file = if let Some((source, filepath)) = locate_sources(sysroot, workspace, &path)?
+            {
+                if fmt.verbosity > 2 {
+                    safeprintln!("Resolved name is {filepath:?}");
+                }
+                let sources = std::fs::read_to_string(&filepath).expect("Can't read a file");
+                if sources.is_empty() {
+                    safeprintln!("Ignoring empty file {filepath:?}!");
+                    (path, None)
                 } else {
-                    if fmt.verbosity > 0 {
-                        safeprintln!("File not found {}", path.display());
+                    if fmt.verbosity > 2 {
+                        safeprintln!("Got {} bytes", sources.len());
                     }
-                    (path, None)
+                    let lines = CachedLines::without_ending(sources);
+                    (path, Some((source, lines)))
                 }
-            });
+            } else {
+                if fmt.verbosity > 0 {
+                    safeprintln!("File not found {}", path.display());
+                }
+                (path, None)
+            };
+            files.insert(f.index, file);
         }
     }
+    Ok(())
 }
 
 impl RawLines for Statement<'_> {
@@ -470,37 +1308,198 @@ impl RawLines for Statement<'_> {
     }
 }
 
-/// try to print `goal` from `path`, collect available items otherwise
-pub fn dump_function(
-    goal: ToDump,
-    path: &Path,
-    workspace: &Path,
-    sysroot: &Path,
-    fmt: &Format,
-) -> anyhow::Result<()> {
+/// Demangle and highlight arbitrary assembly line by line, for `--stdin`
+///
+/// Input doesn't have to be in the format rustc produces so lines are not parsed,
+/// everything that looks like a symbol, register, label or comment is highlighted.
+pub fn filter(input: impl std::io::BufRead, fmt: &Format) -> anyhow::Result<()> {
+    for line in input.split(b'\n') {
+        let line = line?;
+        let line = String::from_utf8_lossy(&line);
+        let line = demangle::color_registers(&line);
+        let line = demangle::contents(&line, fmt.name_display, fmt.keep_hash);
+        let line = demangle::color_local_labels(&line);
+        let line = demangle::color_comment(&line);
+        safeprintln!("{}", theme::highlight(&line));
+    }
+    Ok(())
+}
+
+/// Print `goal` from the assembly in `lines`, `items` are the functions [`find_items`] found
+/// in it
+pub fn dump_function(goal: ToDump, parsed: &Parsed, fmt: &Format) -> anyhow::Result<Rendered> {
     if fmt.verbosity > 2 {
         safeprintln!("goal: {goal:?}");
     }
+    let Parsed {
+        generated,
+        lines,
+        items,
+        parts,
+        ..
+    } = parsed;
+    let (workspace, sysroot, remarks) =
+        (&generated.workspace, &generated.sysroot, &generated.remarks);
 
-    // For some reason llvm/rustc can produce non utf8 files...
-    let payload = std::fs::read(path)?;
-    let contents = String::from_utf8_lossy(&payload).into_owned();
-
-    let statements = parse_file(&contents)?;
-    let functions = find_items(&statements);
+    let mut statements = parse_file(&lines.content)?;
+    renumber_files(&mut statements, parts);
+    let functions = in_sections(items.clone(), &statements, &fmt.section);
 
     if fmt.verbosity > 2 {
         safeprintln!("{functions:?}");
     }
 
     let mut files = BTreeMap::new();
-    if fmt.rust {
-        load_rust_sources(sysroot, workspace, &statements, fmt, &mut files);
+    if fmt.rust || fmt.group_by.is_some() {
+        load_rust_sources(sysroot, workspace, &statements, fmt, &mut files)?;
     }
 
-    if let Some(range) = get_dump_range(goal, fmt, &functions) {
-        let context = get_context_for(fmt.context, &statements[..], range.clone(), &functions);
-        dump_range(&files, fmt, &statements[range])?;
+    // with --at selection happens among the functions covering the location
+    let (goal, selectable) = match goal {
+        ToDump::At { location, nth } => {
+            let location = match location.parse::<SourceLocation>() {
+                Ok(location) => location,
+                Err(err) => anyhow::bail!("Can't use {location:?} with --at: {err}"),
+            };
+            (
+                nth.map_or(ToDump::Unspecified, |value| ToDump::ByIndex { value }),
+                Cow::Owned(items_at(&statements, &functions, &location)?),
+            )
+        }
+        goal => (goal, Cow::Borrowed(&functions)),
+    };
+    let selectable = if fmt.dedup {
+        Cow::Owned(dedup_items(&selectable, |range| {
+            statements[range]
+                .iter()
+                .map(|stmt| format!("{stmt:-}\n"))
+                .collect()
+        }))
+    } else {
+        selectable
+    };
+
+    if let ToDump::Sizes = goal {
+        let sizes = selectable
+            .iter()
+            .map(|(item, range)| {
+                (
+                    item,
+                    Stats::collect(&statements[range.clone()]).instructions,
+                )
+            })
+            .collect();
+        list_sizes(fmt, sizes, "instructions");
+        return Ok(Rendered::Summary);
+    }
+
+    if print_summary(&goal, fmt, &selectable)? {
+        return Ok(Rendered::Summary);
+    }
+
+    if (fmt.rust || fmt.group_by.is_some())
+        && !fmt.quiet
+        && !statements
+            .iter()
+            .any(|s| matches!(s, Statement::Directive(Directive::Loc(_))))
+    {
+        esafeprintln!(
+            "Warning: no debug info found, --rust and --group-by need it to show the source code. \
+            Try --profile dev or set `debug = true` for the profile in use"
+        );
+    }
+
+    if !matches!(fmt.style, OutputStyle::Json | OutputStyle::Html) && !fmt.count {
+        report_inlined(&goal, &statements, &selectable, workspace, fmt);
+    }
+
+    if matches!(fmt.style, OutputStyle::Json | OutputStyle::Html) {
+        let range = get_dump_range(goal, fmt, &selectable)?;
+        let item = range
+            .as_ref()
+            .and_then(|range| functions.iter().find(|(_, r)| *r == range))
+            .map(|(item, _)| item);
+        let stmts = range.map_or(&statements[..], |range| &statements[range]);
+        if fmt.style == OutputStyle::Json {
+            dump_json(&files, fmt, item, stmts)?;
+        } else {
+            dump_html(&files, fmt, item, stmts);
+        }
+        return Ok(Rendered::Code);
+    }
+
+    if fmt.offsets && !fmt.quiet {
+        esafeprintln!(
+            "Instruction sizes are not known until the code is assembled, --offsets shows instruction indices instead of byte offsets"
+        );
+    }
+    let range = get_dump_range(goal, fmt, &selectable)?;
+    let item = range
+        .as_ref()
+        .and_then(|range| functions.iter().find(|(_, r)| *r == range))
+        .map(|(item, _)| item);
+    if fmt.cfg_dot {
+        let title = item.map_or_else(
+            || "file".to_owned(),
+            |item| {
+                item.display_name(fmt.name_display, fmt.keep_hash)
+                    .into_owned()
+            },
+        );
+        let stmts = range.map_or(&statements[..], |range| &statements[range]);
+        safeprint!("{}", Cfg::collect(stmts).dot(&title));
+        return Ok(Rendered::Code);
+    }
+    let markdown = fmt.style == OutputStyle::Markdown;
+    if markdown {
+        markdown_start(fmt, item, "asm");
+    } else {
+        mangled_header(fmt, item);
+    }
+    if let Some(range) = range {
+        // local labels and file numbers are only unique within one generated file
+        let nearby = same_part(&functions, parts, &range);
+        let context = get_context_for(fmt.context, &statements[..], range.clone(), &nearby);
+        let (before, after) = get_neighbors_for(fmt.neighbors, &range, &nearby);
+        if !before.is_empty() {
+            safeprintln!("======================= Preceding functions ========================");
+            for range in before {
+                safeprintln!("\n");
+                dump_range(&files, fmt, &statements[range])?;
+            }
+            safeprintln!(
+                "\n\n======================= Selected function =========================="
+            );
+        }
+        if fmt.group_by == Some(GroupBy::Line) {
+            dump_by_line(&files, fmt, &statements[range.clone()], fmt.range.as_ref())?;
+        } else {
+            dump_part(&files, fmt, &statements[range.clone()], fmt.range.as_ref())?;
+        }
+        if fmt.stats {
+            print_stats(&statements[range.clone()]);
+        }
+        if fmt.frame {
+            print_frame(&statements[range.clone()]);
+        }
+        if fmt.cfg {
+            print_cfg(&statements[range.clone()]);
+        }
+        if fmt.remarks {
+            print_remarks(remarks, &statements, range.clone());
+        }
+        if fmt.data {
+            print_data(fmt, &statements, range);
+        }
+        if !after.is_empty() {
+            safeprintln!(
+                "\n\n======================= Following functions ========================"
+            );
+            for range in after {
+                safeprintln!("\n");
+                dump_range(&files, fmt, &statements[range])?;
+            }
+        }
         if !context.is_empty() {
             safeprintln!(
                 "\n\n======================= Additional context ========================="
@@ -515,6 +1514,447 @@ pub fn dump_function(
             safeprintln!("Going to print the whole file");
         }
         dump_range(&files, fmt, &statements)?;
+        if fmt.stats {
+            print_stats(&statements);
+        }
+    }
+    if markdown {
+        markdown_end();
+    }
+    Ok(Rendered::Code)
+}
+
+fn print_stats(stmts: &[Statement]) {
+    safeprintln!("\n======================= Statistics =================================");
+    safeprintln!("{}", Stats::collect(stmts));
+}
+
+/// Directives that define data, with the leading dot stripped
+const DATA_DIRECTIVES: &[&str] = &[
+    "byte", "2byte", "4byte", "8byte", "short", "hword", "value", "word", "int", "long", "quad",
+    "xword", "octa", "float", "double", "ascii", "asciz", "string", "zero", "space",
+];
+
+fn is_data(stmt: &Statement) -> bool {
+    matches!(stmt, Statement::Directive(Directive::Generic(GenericDirective(dir)))
+        if DATA_DIRECTIVES.contains(&dir.split_whitespace().next().unwrap_or_default()))
+}
+
+/// Data blocks referenced by instructions in `range`: constant pools, jump tables, strings and
+/// anything else defined by a label followed only by data directives, along with their sections
+fn referenced_data<'a>(
+    stmts: &[Statement<'a>],
+    range: &Range<usize>,
+) -> Vec<(Option<&'a str>, Range<usize>)> {
+    let names = stmts[range.clone()]
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::Instruction(i) => i.args,
+            _ => None,
+        })
+        .flat_map(|args| {
+            demangle::local_labels(args)
+                .map(|m| m.as_str().trim_start_matches(|c| c != '.' && c != 'L'))
+                .chain(demangle::global_reference(args))
+        })
+        .collect::<BTreeSet<_>>();
+
+    let sections = sections(stmts);
+    let mut res = Vec::new();
+    for (ix, stmt) in stmts.iter().enumerate() {
+        let Statement::Label(label) = stmt else {
+            continue;
+        };
+        if range.contains(&ix) || !names.contains(label.id) {
+            continue;
+        }
+        let body = &stmts[ix + 1..];
+        let len = body
+            .iter()
+            .take_while(|s| is_data(s) || matches!(s, Statement::Nothing))
+            .count();
+        if body[..len].iter().any(is_data) {
+            res.push((sections[ix], ix..ix + 1 + len));
+        }
+    }
+    res
+}
+
+fn print_data(fmt: &Format, stmts: &[Statement], range: Range<usize>) {
+    let data = referenced_data(stmts, &range);
+    if data.is_empty() {
+        return;
+    }
+    safeprintln!("\n\n======================= Referenced data ============================");
+    for (section, range) in data {
+        safeprintln!();
+        if let Some(section) = section {
+            let section = section.split(',').next().unwrap_or(section);
+            safeprintln!("{}", color!(format!("# section {section}"), theme::comment));
+        }
+        for stmt in &stmts[range] {
+            let styled = Styled(stmt, fmt.name_display, fmt.keep_hash);
+            match stmt {
+                Statement::Directive(Directive::Generic(GenericDirective(dir))) => {
+                    match decode_data(dir) {
+                        Some(value) => {
+                            safeprintln!(
+                                "{styled}  {}",
+                                color!(format!("# {value}"), theme::comment)
+                            );
+                        }
+                        None => safeprintln!("{styled}"),
+                    }
+                }
+                _ => safeprintln!("{styled}"),
+            }
+        }
+    }
+}
+
+/// Integer operand of a data directive, decimal or hex, symbols and expressions are not values
+fn parse_int(arg: &str) -> Option<i128> {
+    let arg = arg.trim();
+    let (negative, arg) = match arg.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, arg),
+    };
+    let value = match arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+        Some(hex) => i128::from_str_radix(hex, 16).ok()?,
+        None => arg.parse::<i128>().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Bytes of a string literal from `.ascii` and friends, with assembler escapes decoded
+fn unescape(literal: &str) -> Option<Vec<u8>> {
+    let mut res = Vec::new();
+    let mut bytes = literal.bytes().peekable();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            res.push(b);
+            continue;
+        }
+        let escaped = bytes.next()?;
+        res.push(match escaped {
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'r' => b'\r',
+            b'b' => 8,
+            b'f' => 12,
+            b'0'..=b'7' => {
+                let mut value = u32::from(escaped - b'0');
+                for _ in 0..2 {
+                    match bytes.peek() {
+                        Some(d @ b'0'..=b'7') => {
+                            value = value * 8 + u32::from(d - b'0');
+                            bytes.next();
+                        }
+                        _ => break,
+                    }
+                }
+                u8::try_from(value).ok()?
+            }
+            b'x' => {
+                let mut value = 0u32;
+                while let Some(d) = bytes.peek().and_then(|d| char::from(*d).to_digit(16)) {
+                    value = (value * 16 + d) & 0xff;
+                    bytes.next();
+                }
+                u8::try_from(value).ok()?
+            }
+            other => other,
+        });
+    }
+    Some(res)
+}
+
+/// Values a data directive defines in a readable form: strings with escapes decoded, bytes as
+/// characters, floats if the bits look like one and other integers in the other base
+fn decode_data(dir: &str) -> Option<String> {
+    let (name, args) = dir.split_once(char::is_whitespace)?;
+    let args = args.trim();
+    let width = match name {
+        "ascii" | "asciz" | "string" => {
+            let mut bytes = unescape(args.strip_prefix('"')?.strip_suffix('"')?)?;
+            if name != "ascii" {
+                bytes.push(0);
+            }
+            return Some(format!("{:?}", String::from_utf8_lossy(&bytes)));
+        }
+        "byte" => 1,
+        "short" | "2byte" | "hword" | "value" => 2,
+        "long" | "4byte" | "int" => 4,
+        "quad" | "8byte" | "xword" => 8,
+        _ => return None,
+    };
+    let values = args
+        .split(',')
+        .map(|arg| Some((parse_int(arg)?, arg.trim().starts_with("0x"))))
+        .collect::<Option<Vec<_>>>()?;
+    let decoded = values
+        .into_iter()
+        .map(|(value, is_hex)| {
+            // two's complement of the directive width, negative values wrap the same way
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let bits = (value as u128 & (u128::MAX >> (128 - width * 8))) as u64;
+            match width {
+                1 => match char::from(bits as u8) {
+                    c if c.is_ascii_graphic() || c == ' ' => format!("{bits:#04x} {c:?}"),
+                    _ => format!("{bits:#04x}"),
+                },
+                4 if f32::from_bits(bits as u32).is_normal() => {
+                    format!("{:?}f32", f32::from_bits(bits as u32))
+                }
+                8 if f64::from_bits(bits).is_normal() => format!("{:?}f64", f64::from_bits(bits)),
+                _ if is_hex => value.to_string(),
+                _ => format!("{bits:#0w$x}", w = width * 2 + 2),
+            }
+        })
+        .collect::<Vec<_>>();
+    Some(decoded.join(", "))
+}
+
+fn print_cfg(stmts: &[Statement]) {
+    safeprintln!("\n======================= Control flow ===============================");
+    safeprintln!("{}", Cfg::collect(stmts));
+}
+
+fn print_frame(stmts: &[Statement]) {
+    safeprintln!("\n======================= Stack frame ================================");
+    match Frame::collect(stmts) {
+        Some(frame) => safeprintln!("{frame}"),
+        None => safeprintln!("unknown, prologue doesn't follow a known pattern"),
+    }
+}
+
+/// Remarks for source lines the function's code comes from
+fn print_remarks(remarks: &[Remark], statements: &[Statement], range: Range<usize>) {
+    let paths = statements
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::Directive(Directive::File(f)) => Some((f.index, f.path.as_full_path())),
+            _ => None,
+        })
+        .collect::<BTreeMap<_, _>>();
+    let locations = statements[range]
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::Directive(Directive::Loc(loc)) if loc.line != 0 => {
+                Some((&**paths.get(&loc.file)?, loc.line))
+            }
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+    crate::remarks::print_at(remarks, locations.iter().copied());
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        decode_data, definition_lines, group_by_line, hidden_middle, label_range, last_segment,
+        own_file, parse_file, referenced_data, renumber_files, section_matches, Directive,
+        GenericDirective, Label, LabelKind, Loc, Statement,
+    };
+    use crate::opts::LabelRange;
+
+    #[test]
+    fn data_blocks_are_found() {
+        use super::statements::Instruction;
+        let stmts = [
+            Statement::Directive(Directive::SectionStart(".rodata.cst8,\"aM\",@progbits,8")),
+            Statement::Label(Label {
+                id: ".LCPI0_0",
+                kind: LabelKind::Local,
+            }),
+            Statement::Directive(Directive::Generic(GenericDirective(
+                "quad\t0x3ff0000000000000",
+            ))),
+            Statement::Directive(Directive::Generic(GenericDirective("text"))),
+            Statement::Label(Label {
+                id: "foo",
+                kind: LabelKind::Global,
+            }),
+            Statement::Instruction(Instruction {
+                op: "movsd",
+                args: Some("xmm0, qword ptr [rip + .LCPI0_0]"),
+            }),
+            Statement::Instruction(Instruction {
+                op: "ret",
+                args: None,
+            }),
+        ];
+        let data = referenced_data(&stmts, &(4..7));
+        assert_eq!(data.len(), 1);
+        assert_eq!(data[0].0, Some(".rodata.cst8,\"aM\",@progbits,8"));
+        assert_eq!(data[0].1, 1..3);
+    }
+
+    #[test]
+    fn data_is_decoded() {
+        assert_eq!(
+            decode_data("asciz\t\"caf\\303\\251\\n\""),
+            Some("\"café\\n\\0\"".to_owned())
+        );
+        assert_eq!(decode_data("byte\t65,0"), Some("0x41 'A', 0x00".to_owned()));
+        assert_eq!(decode_data("long\t1065353216"), Some("1.0f32".to_owned()));
+        assert_eq!(decode_data("long\t-1"), Some("0xffffffff".to_owned()));
+        assert_eq!(decode_data("short\t0x10"), Some("16".to_owned()));
+        assert_eq!(
+            decode_data("quad\t0x3ff0000000000000"),
+            Some("1.0f64".to_owned())
+        );
+        assert_eq!(decode_data("long\t.LBB0_2-.LJTI0_0"), None);
+        assert_eq!(decode_data("p2align\t3"), None);
+    }
+
+    #[test]
+    fn own_file_is_from_first_location() {
+        let loc = |file, line| {
+            Statement::Directive(Directive::Loc(Loc {
+                file,
+                line,
+                column: 0,
+                extra: None,
+            }))
+        };
+        let stmts = [Statement::Nothing, loc(3, 0), loc(2, 10), loc(5, 1)];
+        assert_eq!(own_file(&stmts), Some(2));
+        assert_eq!(own_file(&stmts[..2]), None);
+    }
+
+    #[test]
+    fn label_range_is_resolved() {
+        let label = |id| {
+            Statement::Label(Label {
+                id,
+                kind: LabelKind::Local,
+            })
+        };
+        let stmts = [
+            Statement::Nothing,
+            label(".LBB0_1"),
+            Statement::Nothing,
+            label(".LBB0_2"),
+            Statement::Nothing,
+            label(".LBB0_3"),
+        ];
+        let range = |s: &str| label_range(&stmts, &s.parse::<LabelRange>().unwrap()).ok();
+        assert_eq!(range(".LBB0_1...LBB0_2"), Some(1..3));
+        assert_eq!(range("LBB0_1..=LBB0_2"), Some(1..5));
+        assert_eq!(range("..LBB0_2"), Some(0..3));
+        assert_eq!(range("LBB0_3.."), Some(5..6));
+        assert_eq!(range("LBB0_3..LBB0_1"), None);
+        assert_eq!(range("LBB0_9.."), None);
+    }
+
+    #[test]
+    fn instructions_are_grouped_by_line() {
+        let asm = "\tpushq\t%rbp\n\
+            \t.loc\t1 3 5 prologue_end\n\
+            \timull\t%esi, %edi\n\
+            \t.loc\t1 4 9\n\
+            \tleal\t1(%rdi), %eax\n\
+            \t.loc\t1 3 5\n\
+            \taddl\t%edi, %eax\n\
+            \t.loc\t1 0 5\n\
+            \tretq\n";
+        let stmts = parse_file(asm).unwrap();
+        let (groups, unattributed) = group_by_line(&stmts);
+        fn ops<'a>(group: &[&Statement<'a>]) -> Vec<&'a str> {
+            group
+                .iter()
+                .map(|stmt| match stmt {
+                    Statement::Instruction(i) => i.op,
+                    _ => "?",
+                })
+                .collect()
+        }
+        assert_eq!(groups.keys().copied().collect::<Vec<_>>(), [(1, 3), (1, 4)]);
+        assert_eq!(ops(&groups[&(1, 3)]), ["imull", "addl"]);
+        assert_eq!(ops(&groups[&(1, 4)]), ["leal"]);
+        assert_eq!(ops(&unattributed), ["pushq", "retq"]);
+    }
+
+    #[test]
+    fn middle_is_hidden() {
+        let asm = "foo:\n\
+            \tpushq\t%rbp\n\
+            \t.loc\t1 3 5\n\
+            \timull\t%esi, %edi\n\
+            \tleal\t1(%rdi), %eax\n\
+            \t.loc\t1 4 5\n\
+            \tpopq\t%rbp\n\
+            \tretq\n";
+        let stmts = parse_file(asm).unwrap();
+        assert_eq!(hidden_middle(&stmts, Some(1), None), Some((2..8, 4)));
+        assert_eq!(hidden_middle(&stmts, None, Some(2)), Some((1..6, 3)));
+        assert_eq!(hidden_middle(&stmts, Some(1), Some(1)), Some((2..7, 3)));
+        assert_eq!(hidden_middle(&stmts, Some(3), Some(2)), None);
+        assert_eq!(hidden_middle(&stmts, None, None), None);
+    }
+
+    #[test]
+    fn definitions_are_found() {
+        let source = "use x;\n\nfn helper(a: u32) -> u32 {\n    if a > 1 {\n        a\n    } else { 0 }\n}\n\nfn other() {}\n";
+        assert_eq!(definition_lines(source, "helper"), Some((3, 7)));
+        assert_eq!(definition_lines(source, "other"), Some((9, 9)));
+        assert_eq!(definition_lines(source, "help"), None);
+        assert_eq!(definition_lines("trait T {\n    fn f();\n}\n", "f"), None);
+        assert_eq!(
+            definition_lines("fn f(x: [u8; 4]) -> u8 {\n    x[0]\n}\n", "f"),
+            Some((1, 3))
+        );
+        assert_eq!(
+            definition_lines("trait T {\n    fn f(x: [u8; 4]);\n}\n", "f"),
+            None
+        );
+    }
+
+    #[test]
+    fn sections_are_matched_by_prefix() {
+        assert!(section_matches(
+            ".text._ZN3foo3bar17h0123456789abcdefE,\"ax\",@progbits",
+            ".text"
+        ));
+        assert!(section_matches(".ram_code,\"ax\",@progbits", ".ram_code"));
+        assert!(!section_matches(".textual", ".text"));
+        assert!(section_matches(
+            "__TEXT,__text,regular,pure_instructions",
+            "__TEXT"
+        ));
+        assert!(section_matches(
+            "__TEXT,__text,regular,pure_instructions",
+            "__TEXT,__text"
+        ));
+        assert!(!section_matches("__DATA,__const", "__TEXT"));
+    }
+
+    #[test]
+    fn last_segment_is_a_plain_name() {
+        assert_eq!(last_segment("foo::Bar<T>::baz").as_deref(), Some("baz"));
+        assert_eq!(last_segment("baz").as_deref(), Some("baz"));
+        assert_eq!(last_segment("foo::").as_deref(), None);
+        assert_eq!(last_segment("<T as Trait>").as_deref(), None);
+    }
+
+    #[test]
+    fn files_are_renumbered_in_later_parts() {
+        let asm = "\t.file\t1 \"/a\" \"main.rs\"\n\
+            \t.loc\t1 2 3\n\
+            \t.file\t1 \"/a\" \"lib.rs\"\n\
+            \t.file\t2 \"/a\" \"other.rs\"\n\
+            \t.loc\t2 4 5\n";
+        let mut stmts = parse_file(asm).unwrap();
+        renumber_files(&mut stmts, &[0..2, 2..5]);
+        let files = stmts
+            .iter()
+            .filter_map(|s| match s {
+                Statement::Directive(Directive::File(f)) => Some(f.index),
+                Statement::Directive(Directive::Loc(l)) => Some(l.file),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(files, [1, 1, 3, 4, 4]);
     }
-    Ok(())
 }
@@ -0,0 +1,310 @@
+//! Basic blocks of a function and jumps between them, for `--cfg` and `--cfg-dot`
+//!
+//! Blocks start at local labels branches or jump table entries point to and after branches,
+//! calls don't end a block.
+//! Other labels such as `.Ltmp` ones only mark places for debug info. Successors are
+//! branch targets within the function plus the next block unless the block ends with an
+//! unconditional jump or a return.
+use super::statements::{Directive, GenericDirective, Instruction, Label, Statement};
+use super::stats::is_branch;
+use crate::demangle::{self, LabelKind};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// How control leaves a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+    /// Into the next block, possibly after a conditional branch
+    FallThrough,
+    /// Unconditional jump to a label
+    Jump,
+    /// Jump to an address computed at runtime: jump tables, tail calls through a register
+    Indirect,
+    /// Return or a trap
+    Return,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub name: String,
+    pub instructions: usize,
+    pub successors: Vec<String>,
+    pub end: End,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cfg(pub Vec<Block>);
+
+/// Branches that never fall through to the next instruction, x86, ARM and WASM
+fn is_unconditional(op: &str) -> bool {
+    matches!(op, "jmp" | "jmpq" | "b" | "br" | "bx" | "br_table")
+}
+
+fn is_return(op: &str) -> bool {
+    op.starts_with("ret") || matches!(op, "ud2" | "unreachable" | "end_function")
+}
+
+/// Label a jump table entry points to: `.long .LBB0_3-.LJTI0_0` on x86, `.quad .LBB0_3` without
+/// PIC or `.byte (.LBB0_3-.LBB0_1)>>2` on ARM
+fn jump_table_entry(directive: &str) -> Option<&str> {
+    let (kind, value) = directive.split_once(char::is_whitespace)?;
+    let data = [
+        "byte", "short", "hword", "word", "long", "int", "quad", "xword", "2byte", "4byte", "8byte",
+    ];
+    if !data.contains(&kind) {
+        return None;
+    }
+    // the match includes a character before the label
+    let label = demangle::local_labels(value).next()?.as_str();
+    Some(label.trim_start_matches(|c: char| !c.is_alphanumeric() && c != '.'))
+}
+
+impl Block {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            instructions: 0,
+            successors: Vec::new(),
+            end: End::FallThrough,
+        }
+    }
+}
+
+impl Cfg {
+    #[must_use]
+    pub fn collect(stmts: &[Statement]) -> Self {
+        let labels = stmts
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Statement::Label(Label {
+                    id,
+                    kind: LabelKind::Local | LabelKind::Temp,
+                }) => Some(*id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // only labels something jumps to start blocks, the rest mark places for debug info
+        let mut targeted = stmts
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Statement::Instruction(Instruction { op, args }) if is_branch(op) => *args,
+                _ => None,
+            })
+            .flat_map(demangle::local_labels)
+            .map(|m| m.as_str())
+            .collect::<BTreeSet<_>>();
+        targeted.extend(stmts.iter().filter_map(|stmt| match stmt {
+            Statement::Directive(Directive::Generic(GenericDirective(dir))) => {
+                jump_table_entry(dir)
+            }
+            _ => None,
+        }));
+
+        let mut blocks = vec![Block::new("entry".to_owned())];
+        // instruction after a branch starts a new block even without a label
+        let mut split = false;
+        for stmt in stmts {
+            match stmt {
+                Statement::Label(Label {
+                    id,
+                    kind: LabelKind::Local | LabelKind::Temp,
+                }) if targeted.contains(id) => {
+                    match blocks.last_mut() {
+                        Some(block) if block.instructions == 0 => block.name = (*id).to_owned(),
+                        _ => blocks.push(Block::new((*id).to_owned())),
+                    }
+                    split = false;
+                }
+                Statement::Instruction(Instruction { op, args }) if !op.starts_with('#') => {
+                    if split {
+                        blocks.push(Block::new(format!("bb{}", blocks.len())));
+                        split = false;
+                    }
+                    let Some(block) = blocks.last_mut() else {
+                        continue;
+                    };
+                    block.instructions += 1;
+                    if is_return(op) {
+                        block.end = End::Return;
+                        split = true;
+                    } else if is_branch(op) {
+                        let targets = args
+                            .iter()
+                            .copied()
+                            .flat_map(demangle::local_labels)
+                            .map(|m| m.as_str())
+                            .filter(|target| labels.contains(target))
+                            .collect::<Vec<_>>();
+                        if is_unconditional(op) {
+                            block.end = if targets.is_empty() {
+                                End::Indirect
+                            } else {
+                                End::Jump
+                            };
+                        }
+                        for target in targets {
+                            if !block.successors.iter().any(|s| s == target) {
+                                block.successors.push(target.to_owned());
+                            }
+                        }
+                        split = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        blocks.retain(|block| block.instructions > 0);
+
+        let next = blocks
+            .iter()
+            .skip(1)
+            .map(|block| Some(block.name.clone()))
+            .chain([None])
+            .collect::<Vec<_>>();
+        for (block, next) in blocks.iter_mut().zip(next) {
+            if let (End::FallThrough, Some(next)) = (block.end, next) {
+                if !block.successors.contains(&next) {
+                    block.successors.push(next);
+                }
+            }
+        }
+        Self(blocks)
+    }
+
+    /// Graph in Graphviz DOT format
+    #[must_use]
+    pub fn dot(&self, title: &str) -> String {
+        let mut out = format!("digraph {title:?} {{\n    node [shape=box, fontname=monospace];\n");
+        for block in &self.0 {
+            let shape = match block.end {
+                End::Return => ", peripheries=2",
+                End::FallThrough | End::Jump | End::Indirect => "",
+            };
+            let _ = writeln!(
+                out,
+                "    {:?} [label=\"{}\\n{}\"{shape}];",
+                block.name,
+                block.name,
+                instructions(block.instructions)
+            );
+            for succ in &block.successors {
+                let _ = writeln!(out, "    {:?} -> {succ:?};", block.name);
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn instructions(n: usize) -> String {
+    if n == 1 {
+        "1 instruction".to_owned()
+    } else {
+        format!("{n} instructions")
+    }
+}
+
+impl std::fmt::Display for Cfg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for block in &self.0 {
+            let mut exits = block.successors.clone();
+            match block.end {
+                End::Return => exits.push("return".to_owned()),
+                End::Indirect => exits.push("indirect".to_owned()),
+                End::FallThrough | End::Jump => {}
+            }
+            writeln!(
+                f,
+                "{} ({}) -> {}",
+                block.name,
+                instructions(block.instructions),
+                exits.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::parse_file;
+
+    #[test]
+    fn blocks_are_split() {
+        let asm = "\ttestl\t%edi, %edi\n\
+            \tje\t.LBB0_2\n\
+            \tmovl\t$1, %eax\n\
+            \tjmp\t.LBB0_3\n\
+            .LBB0_2:\n\
+            \txorl\t%eax, %eax\n\
+            .LBB0_3:\n\
+            \tretq\n";
+        let cfg = Cfg::collect(&parse_file(asm).unwrap());
+        assert_eq!(
+            cfg.to_string(),
+            "entry (2 instructions) -> .LBB0_2, bb1\n\
+            bb1 (2 instructions) -> .LBB0_3\n\
+            .LBB0_2 (1 instruction) -> .LBB0_3\n\
+            .LBB0_3 (1 instruction) -> return\n"
+        );
+        assert!(cfg.dot("f").contains("    \"entry\" -> \".LBB0_2\";\n"));
+    }
+
+    #[test]
+    fn jump_table_entries_start_blocks() {
+        let asm = "\tmovl\t%edi, %eax\n\
+            \tleaq\t.LJTI0_0(%rip), %rcx\n\
+            \tmovslq\t(%rcx,%rax,4), %rax\n\
+            \taddq\t%rcx, %rax\n\
+            \tjmpq\t*%rax\n\
+            .LBB0_2:\n\
+            \tmovl\t$7, %eax\n\
+            \tretq\n\
+            .LBB0_3:\n\
+            \tmovl\t$9, %eax\n\
+            \tretq\n\
+            \t.section\t.rodata,\"a\",@progbits\n\
+            .LJTI0_0:\n\
+            \t.long\t.LBB0_2-.LJTI0_0\n\
+            \t.long\t.LBB0_3-.LJTI0_0\n";
+        let cfg = Cfg::collect(&parse_file(asm).unwrap());
+        assert_eq!(
+            cfg.to_string(),
+            "entry (5 instructions) -> indirect\n\
+            .LBB0_2 (2 instructions) -> return\n\
+            .LBB0_3 (2 instructions) -> return\n"
+        );
+        assert_eq!(jump_table_entry("quad\t.LBB0_3"), Some(".LBB0_3"));
+        assert_eq!(
+            jump_table_entry("byte\t(.LBB0_3-.LBB0_1)>>2"),
+            Some(".LBB0_3")
+        );
+        assert_eq!(jump_table_entry("p2align\t2"), None);
+    }
+
+    #[test]
+    fn debug_labels_are_not_blocks() {
+        let asm = ".Ltmp0:\n\
+            \t.loc\t1 3 5 prologue_end\n\
+            \tcmpl\t$1, %edi\n\
+            .Ltmp1:\n\
+            \t.loc\t1 4 9\n\
+            \tjbe\t.LBB0_2\n\
+            .Ltmp2:\n\
+            \tleal\t(%rdi,%rsi), %eax\n\
+            .Ltmp3:\n\
+            \tretq\n\
+            .LBB0_2:\n\
+            \t.loc\t1 6 5\n\
+            \txorl\t%eax, %eax\n\
+            \tretq\n";
+        let cfg = Cfg::collect(&parse_file(asm).unwrap());
+        assert_eq!(
+            cfg.to_string(),
+            "entry (2 instructions) -> .LBB0_2, bb1\n\
+            bb1 (2 instructions) -> return\n\
+            .LBB0_2 (2 instructions) -> return\n"
+        );
+    }
+}
@@ -0,0 +1,278 @@
+//! Approximate instruction costs for `--annotate-cost`
+//!
+//! Numbers are typical register forms taken from public measurements and rounded, they are meant
+//! to give an idea of what's cheap and what's not, llvm-mca gives a much better picture.
+
+/// Instruction latency and reciprocal throughput, both in cycles
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cost {
+    pub latency: f32,
+    pub throughput: f32,
+}
+
+impl std::fmt::Display for Cost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "lat {}, tput {}", self.latency, self.throughput)
+    }
+}
+
+type Table = &'static [(&'static str, f32, f32)];
+
+const SKYLAKE: Table = &[
+    ("mov", 1.0, 0.25),
+    ("movzx", 1.0, 0.25),
+    ("movsx", 1.0, 0.25),
+    ("movsxd", 1.0, 0.25),
+    ("lea", 1.0, 0.5),
+    ("add", 1.0, 0.25),
+    ("sub", 1.0, 0.25),
+    ("and", 1.0, 0.25),
+    ("or", 1.0, 0.25),
+    ("xor", 1.0, 0.25),
+    ("cmp", 1.0, 0.25),
+    ("test", 1.0, 0.25),
+    ("inc", 1.0, 0.25),
+    ("dec", 1.0, 0.25),
+    ("neg", 1.0, 0.25),
+    ("not", 1.0, 0.25),
+    ("shl", 1.0, 0.5),
+    ("shr", 1.0, 0.5),
+    ("sar", 1.0, 0.5),
+    ("rol", 1.0, 0.5),
+    ("ror", 1.0, 0.5),
+    ("imul", 3.0, 1.0),
+    ("mul", 3.0, 1.0),
+    ("div", 26.0, 6.0),
+    ("idiv", 26.0, 6.0),
+    ("cmov", 1.0, 0.5),
+    ("set", 1.0, 0.5),
+    ("jcc", 1.0, 0.5),
+    ("jmp", 1.0, 1.0),
+    ("call", 3.0, 1.0),
+    ("ret", 1.0, 1.0),
+    ("push", 3.0, 1.0),
+    ("pop", 2.0, 0.5),
+    ("nop", 0.0, 0.25),
+    ("popcnt", 3.0, 1.0),
+    ("lzcnt", 3.0, 1.0),
+    ("tzcnt", 3.0, 1.0),
+    ("bsf", 3.0, 1.0),
+    ("bsr", 3.0, 1.0),
+    ("bswap", 1.0, 0.5),
+    ("xchg", 2.0, 1.0),
+    ("movaps", 1.0, 0.33),
+    ("movups", 1.0, 0.33),
+    ("movdqa", 1.0, 0.33),
+    ("movdqu", 1.0, 0.33),
+    ("movss", 1.0, 0.33),
+    ("movsd", 1.0, 0.33),
+    ("pxor", 1.0, 0.33),
+    ("paddd", 1.0, 0.33),
+    ("paddq", 1.0, 0.33),
+    ("pmulld", 10.0, 1.0),
+    ("pshufd", 1.0, 1.0),
+    ("shufps", 1.0, 1.0),
+    ("addss", 4.0, 0.5),
+    ("addsd", 4.0, 0.5),
+    ("addps", 4.0, 0.5),
+    ("addpd", 4.0, 0.5),
+    ("mulss", 4.0, 0.5),
+    ("mulsd", 4.0, 0.5),
+    ("mulps", 4.0, 0.5),
+    ("mulpd", 4.0, 0.5),
+    ("divss", 11.0, 3.0),
+    ("divsd", 14.0, 4.0),
+    ("divps", 11.0, 5.0),
+    ("divpd", 14.0, 8.0),
+    ("sqrtss", 12.0, 3.0),
+    ("sqrtsd", 18.0, 6.0),
+    ("fmadd", 4.0, 0.5),
+];
+
+const ZNVER3: Table = &[
+    ("mov", 1.0, 0.25),
+    ("movzx", 1.0, 0.25),
+    ("movsx", 1.0, 0.25),
+    ("movsxd", 1.0, 0.25),
+    ("lea", 1.0, 0.25),
+    ("add", 1.0, 0.25),
+    ("sub", 1.0, 0.25),
+    ("and", 1.0, 0.25),
+    ("or", 1.0, 0.25),
+    ("xor", 1.0, 0.25),
+    ("cmp", 1.0, 0.25),
+    ("test", 1.0, 0.25),
+    ("inc", 1.0, 0.25),
+    ("dec", 1.0, 0.25),
+    ("neg", 1.0, 0.25),
+    ("not", 1.0, 0.25),
+    ("shl", 1.0, 0.5),
+    ("shr", 1.0, 0.5),
+    ("sar", 1.0, 0.5),
+    ("rol", 1.0, 0.5),
+    ("ror", 1.0, 0.5),
+    ("imul", 3.0, 1.0),
+    ("mul", 3.0, 1.0),
+    ("div", 14.0, 7.0),
+    ("idiv", 14.0, 7.0),
+    ("cmov", 1.0, 0.25),
+    ("set", 1.0, 0.5),
+    ("jcc", 1.0, 0.5),
+    ("jmp", 1.0, 0.5),
+    ("call", 1.0, 0.5),
+    ("ret", 1.0, 0.5),
+    ("push", 1.0, 0.5),
+    ("pop", 1.0, 0.33),
+    ("nop", 0.0, 0.17),
+    ("popcnt", 1.0, 0.25),
+    ("lzcnt", 1.0, 0.25),
+    ("tzcnt", 2.0, 0.5),
+    ("bsf", 3.0, 3.0),
+    ("bsr", 4.0, 4.0),
+    ("bswap", 1.0, 0.25),
+    ("xchg", 1.0, 0.5),
+    ("movaps", 1.0, 0.25),
+    ("movups", 1.0, 0.25),
+    ("movdqa", 1.0, 0.25),
+    ("movdqu", 1.0, 0.25),
+    ("movss", 1.0, 0.25),
+    ("movsd", 1.0, 0.25),
+    ("pxor", 1.0, 0.25),
+    ("paddd", 1.0, 0.25),
+    ("paddq", 1.0, 0.25),
+    ("pmulld", 3.0, 0.5),
+    ("pshufd", 1.0, 0.5),
+    ("shufps", 1.0, 0.5),
+    ("addss", 3.0, 0.5),
+    ("addsd", 3.0, 0.5),
+    ("addps", 3.0, 0.5),
+    ("addpd", 3.0, 0.5),
+    ("mulss", 3.0, 0.5),
+    ("mulsd", 3.0, 0.5),
+    ("mulps", 3.0, 0.5),
+    ("mulpd", 3.0, 0.5),
+    ("divss", 10.0, 3.5),
+    ("divsd", 13.0, 4.5),
+    ("divps", 10.0, 3.5),
+    ("divpd", 13.0, 4.5),
+    ("sqrtss", 14.0, 4.5),
+    ("sqrtsd", 20.0, 4.5),
+    ("fmadd", 4.0, 0.5),
+];
+
+const NEOVERSE_N1: Table = &[
+    ("mov", 1.0, 0.33),
+    ("add", 1.0, 0.33),
+    ("adds", 1.0, 0.33),
+    ("sub", 1.0, 0.33),
+    ("subs", 1.0, 0.33),
+    ("and", 1.0, 0.33),
+    ("ands", 1.0, 0.33),
+    ("orr", 1.0, 0.33),
+    ("eor", 1.0, 0.33),
+    ("cmp", 1.0, 0.33),
+    ("tst", 1.0, 0.33),
+    ("lsl", 1.0, 0.33),
+    ("lsr", 1.0, 0.33),
+    ("asr", 1.0, 0.33),
+    ("csel", 1.0, 0.33),
+    ("cset", 1.0, 0.33),
+    ("mul", 2.0, 1.0),
+    ("madd", 2.0, 1.0),
+    ("msub", 2.0, 1.0),
+    ("umulh", 4.0, 3.0),
+    ("smulh", 4.0, 3.0),
+    ("udiv", 12.0, 12.0),
+    ("sdiv", 12.0, 12.0),
+    ("ldr", 4.0, 0.5),
+    ("ldp", 4.0, 1.0),
+    ("str", 1.0, 0.5),
+    ("stp", 1.0, 1.0),
+    ("b", 1.0, 1.0),
+    ("b.cond", 1.0, 1.0),
+    ("cbz", 1.0, 1.0),
+    ("cbnz", 1.0, 1.0),
+    ("bl", 1.0, 1.0),
+    ("ret", 1.0, 1.0),
+    ("nop", 0.0, 0.25),
+    ("fadd", 2.0, 0.5),
+    ("fsub", 2.0, 0.5),
+    ("fmul", 3.0, 0.5),
+    ("fmadd", 4.0, 0.5),
+    ("fdiv", 10.0, 7.0),
+    ("fsqrt", 17.0, 14.0),
+];
+
+/// Tables that can be picked with `--cost-cpu`
+pub const CPUS: &[(&str, Table)] = &[
+    ("skylake", SKYLAKE),
+    ("znver3", ZNVER3),
+    ("neoverse-n1", NEOVERSE_N1),
+];
+
+/// Cost table for a CPU, `None` if there's no such table
+#[must_use]
+pub fn table(cpu: &str) -> Option<Table> {
+    CPUS.iter()
+        .find(|(name, _)| *name == cpu)
+        .map(|(_, table)| *table)
+}
+
+/// Names instructions with variants go by in the tables: condition codes, AT&T size suffixes and
+/// VEX prefixes are dropped
+fn candidates(op: &str) -> impl Iterator<Item = &str> {
+    let family = if op.starts_with('j') && op != "jmp" && op != "jmpq" {
+        Some("jcc")
+    } else if op.starts_with("cmov") {
+        Some("cmov")
+    } else if op.starts_with("set") {
+        Some("set")
+    } else if op.starts_with("b.") {
+        Some("b.cond")
+    } else if op.starts_with("vfmadd") || op.starts_with("fmadd") {
+        Some("fmadd")
+    } else {
+        None
+    };
+    let unsuffixed = op.strip_suffix(['q', 'l', 'w', 'b']);
+    let unprefixed = op.strip_prefix('v');
+    std::iter::once(op)
+        .chain(family)
+        .chain(unsuffixed)
+        .chain(unprefixed)
+}
+
+/// Cost of an instruction, `None` if it's not in the table
+#[must_use]
+pub fn cost(table: Table, op: &str) -> Option<Cost> {
+    candidates(op).find_map(|name| {
+        table
+            .iter()
+            .find(|(n, _, _)| *n == name)
+            .map(|&(_, latency, throughput)| Cost {
+                latency,
+                throughput,
+            })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn variants_are_found() {
+        let skylake = table("skylake").unwrap();
+        let cost_of = |op| cost(skylake, op).map(|c| c.to_string());
+        assert_eq!(cost_of("imul").as_deref(), Some("lat 3, tput 1"));
+        assert_eq!(cost_of("imulq").as_deref(), Some("lat 3, tput 1"));
+        assert_eq!(cost_of("jne").as_deref(), Some("lat 1, tput 0.5"));
+        assert_eq!(cost_of("vaddps").as_deref(), Some("lat 4, tput 0.5"));
+        assert_eq!(cost_of("vfmadd231ps").as_deref(), Some("lat 4, tput 0.5"));
+        assert_eq!(cost_of("cpuid"), None);
+        let n1 = table("neoverse-n1").unwrap();
+        let b_ne = cost(n1, "b.ne").map(|c| c.to_string());
+        assert_eq!(b_ne.as_deref(), Some("lat 1, tput 1"));
+        assert!(table("pentium").is_none());
+    }
+}
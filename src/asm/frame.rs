@@ -0,0 +1,168 @@
+//! Stack frame summary from the function prologue: stack pointer adjustments and saved registers
+use super::statements::{Instruction, Statement};
+use super::stats::is_branch_or_call;
+use serde::Serialize;
+
+/// Prologue ends at the first control transfer, but don't look too far into the function
+const PROLOGUE_LIMIT: usize = 24;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Frame {
+    /// Bytes the stack pointer moves by in the prologue, saved registers included
+    pub stack: u64,
+    /// Callee saved registers stored in the prologue, in order
+    pub saved: Vec<String>,
+}
+
+/// Parse a number in `40`, `$40`, `#40`, `0x28` or `#-32` form, sign is ignored
+fn number(s: &str) -> Option<u64> {
+    let s = s
+        .trim()
+        .trim_start_matches(['$', '#'])
+        .trim_start_matches('-');
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Stack pointer as a destination in Intel, AT&T and ARM syntax
+fn writes_sp(args: &str) -> bool {
+    let args = args.trim();
+    ["rsp,", "esp,", "sp,"]
+        .iter()
+        .any(|sp| args.starts_with(sp))
+        || args.ends_with("%rsp")
+        || args.ends_with("%esp")
+}
+
+/// Registers in an ARM register pair store: `x29, x30, [sp, #-16]!`
+fn stored_registers(args: &str) -> Option<impl Iterator<Item = &str>> {
+    let (regs, addr) = args.split_once('[')?;
+    addr.starts_with("sp")
+        .then(|| regs.split(',').map(str::trim).filter(|reg| !reg.is_empty()))
+}
+
+impl Frame {
+    /// Summary of the prologue, `None` if it manipulates the stack in a way that is not understood
+    #[must_use]
+    pub fn collect(stmts: &[Statement]) -> Option<Self> {
+        let mut frame = Self::default();
+        let instructions = stmts
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Statement::Instruction(i) if !i.op.starts_with('#') => Some(i),
+                _ => None,
+            })
+            .take_while(|i| !is_branch_or_call(i.op))
+            .take(PROLOGUE_LIMIT);
+
+        for Instruction { op, args } in instructions {
+            let args = args.unwrap_or_default();
+            match *op {
+                "push" | "pushq" | "pushl" => {
+                    let reg = args.trim().trim_start_matches('%');
+                    frame.stack += if *op == "pushl" || reg.starts_with('e') {
+                        4
+                    } else {
+                        8
+                    };
+                    frame.saved.push(reg.to_owned());
+                }
+                "sub" | "subq" | "subl" if writes_sp(args) => {
+                    let amount = if args.trim_start().starts_with('$') {
+                        args.split(',').next()
+                    } else {
+                        args.rsplit(',').next()
+                    };
+                    frame.stack += number(amount?)?;
+                }
+                "stp" | "str" => {
+                    if let Some(regs) = stored_registers(args) {
+                        frame.saved.extend(regs.map(str::to_owned));
+                        // pre-indexed store moves the stack pointer: `[sp, #-16]!`
+                        if args.trim_end().ends_with('!') {
+                            let offset = args.rsplit(',').next()?.trim_end_matches(['!', ']']);
+                            frame.stack += number(offset)?;
+                        }
+                    }
+                }
+                "global.set" if args.contains("__stack_pointer") => return None,
+                "mov" | "movq" | "movl" | "add" | "addq" | "and" | "andq" if writes_sp(args) => {
+                    return None;
+                }
+                _ => {}
+            }
+        }
+        Some(frame)
+    }
+}
+
+impl std::fmt::Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "stack:        {} bytes", self.stack)?;
+        if self.saved.is_empty() {
+            write!(f, "saved:        none")
+        } else {
+            write!(f, "saved:        {}", self.saved.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn instructions<'a>(lines: &[(&'a str, &'a str)]) -> Vec<Statement<'a>> {
+        lines
+            .iter()
+            .map(|&(op, args)| {
+                Statement::Instruction(Instruction {
+                    op,
+                    args: Some(args),
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn x86_prologue_is_summarized() {
+        let intel = instructions(&[
+            ("push", "r14"),
+            ("push", "rbx"),
+            ("sub", "rsp, 40"),
+            ("mov", "rbx, rdi"),
+            ("call", "foo"),
+            ("push", "r15"),
+        ]);
+        let att = instructions(&[("pushq", "%r14"), ("pushq", "%rbx"), ("subq", "$40, %rsp")]);
+        let expected = Frame {
+            stack: 56,
+            saved: vec!["r14".to_owned(), "rbx".to_owned()],
+        };
+        assert_eq!(Frame::collect(&intel), Some(expected.clone()));
+        assert_eq!(Frame::collect(&att), Some(expected));
+    }
+
+    #[test]
+    fn arm_prologue_is_summarized() {
+        let stmts = instructions(&[
+            ("stp", "x29, x30, [sp, #-32]!"),
+            ("str", "x19, [sp, #16]"),
+            ("sub", "sp, sp, #64"),
+        ]);
+        let expected = Frame {
+            stack: 96,
+            saved: vec!["x29".to_owned(), "x30".to_owned(), "x19".to_owned()],
+        };
+        assert_eq!(Frame::collect(&stmts), Some(expected));
+    }
+
+    #[test]
+    fn unknown_prologue_is_reported() {
+        let wasm = instructions(&[("global.set", "__stack_pointer")]);
+        assert_eq!(Frame::collect(&wasm), None);
+        let aligned = instructions(&[("push", "rbp"), ("and", "rsp, -32")]);
+        assert_eq!(Frame::collect(&aligned), None);
+    }
+}
@@ -9,11 +9,10 @@ use nom::combinator::{map, opt, recognize, value, verify};
 use nom::multi::count;
 use nom::sequence::{delimited, pair, preceded, terminated, tuple};
 use nom::{AsChar, IResult};
-use owo_colors::OwoColorize;
 
 use crate::demangle::LabelKind;
 use crate::opts::NameDisplay;
-use crate::{color, demangle};
+use crate::{color, demangle, theme};
 
 #[derive(Clone, Debug)]
 pub enum Statement<'a> {
@@ -52,19 +51,27 @@ impl<'a> Instruction<'a> {
 
 impl std::fmt::Display for Instruction<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let display = NameDisplay::from(&*f);
+        use std::fmt::Write;
+        let (display, keep_hash) = name_style(f);
+        let mut line = String::new();
         if self.op.starts_with("#DEBUG_VALUE:") {
-            write!(f, "{}", color!(self.op, OwoColorize::blue))?;
+            write!(line, "{}", color!(self.op, theme::comment))?;
         } else {
-            write!(f, "{}", color!(self.op, OwoColorize::bright_blue))?;
+            write!(line, "{}", color!(self.op, theme::instruction))?;
         }
         if let Some(args) = self.args {
-            let args = demangle::contents(args, display);
+            let args = demangle::color_registers(args);
+            let args = demangle::contents(&args, display, keep_hash);
             let w_label = demangle::color_local_labels(&args);
             let w_comment = demangle::color_comment(&w_label);
-            write!(f, " {w_comment}")?;
+            // with a width operands start at the same column, or right after a longer mnemonic
+            let pad = match f.width() {
+                Some(width) if width > 0 => width.saturating_sub(self.op.len()).max(1),
+                _ => 1,
+            };
+            write!(line, "{:pad$}{w_comment}", "")?;
         }
-        Ok(())
+        f.write_str(&theme::highlight(&line))
     }
 }
 
@@ -73,20 +80,13 @@ impl std::fmt::Display for Statement<'_> {
         match self {
             Statement::Label(l) => l.fmt(f),
             Statement::Directive(d) => {
-                if f.alternate() {
-                    write!(f, "{d:#}")
-                } else {
-                    write!(f, "{d}")
-                }
+                let (display, keep_hash) = name_style(f);
+                write!(f, "{}", Styled(d, display, keep_hash))
             }
             Statement::Instruction(i) => {
-                if f.sign_minus() {
-                    write!(f, "\t{i:-#}")
-                } else if f.alternate() {
-                    write!(f, "\t{i:#}")
-                } else {
-                    write!(f, "\t{i}")
-                }
+                let (display, keep_hash) = name_style(f);
+                let width = f.width().unwrap_or(0);
+                write!(f, "\t{:width$}", Styled(i, display, keep_hash))
             }
             Statement::Nothing => Ok(()),
             Statement::Dunno(l) => write!(f, "{}", demangle::color_comment(l)),
@@ -96,25 +96,19 @@ impl std::fmt::Display for Statement<'_> {
 
 impl std::fmt::Display for Directive<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let display = NameDisplay::from(&*f);
+        let (display, keep_hash) = name_style(f);
         match self {
             Directive::File(ff) => ff.fmt(f),
             Directive::Loc(l) => l.fmt(f),
             Directive::Generic(g) => g.fmt(f),
-            Directive::Set(g) => {
-                f.write_str(&format!(".set {}", color!(g, OwoColorize::bright_cyan)))
-            }
+            Directive::Set(g) => f.write_str(&format!(".set {}", color!(g, theme::constant))),
             Directive::SectionStart(s) => {
-                let dem = demangle::contents(s, display);
-                f.write_str(&format!(
-                    "{} {}",
-                    color!(".section", OwoColorize::bright_red),
-                    dem
-                ))
+                let dem = demangle::contents(s, display, keep_hash);
+                f.write_str(&format!("{} {}", color!(".section", theme::section), dem))
             }
             Directive::SubsectionsViaSym => f.write_str(&format!(
                 ".{}",
-                color!("subsections_via_symbols", OwoColorize::bright_red)
+                color!("subsections_via_symbols", theme::section)
             )),
         }
     }
@@ -138,13 +132,13 @@ impl std::fmt::Display for File<'_> {
 
 impl std::fmt::Display for GenericDirective<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let display = NameDisplay::from(&*f);
+        let (display, keep_hash) = name_style(f);
         write!(
             f,
             "\t.{}",
             color!(
-                demangle::contents(self.0, display),
-                OwoColorize::bright_magenta
+                demangle::contents(self.0, display, keep_hash),
+                theme::directive
             )
         )
     }
@@ -171,27 +165,46 @@ impl std::fmt::Display for Loc<'_> {
     }
 }
 
-impl From<&std::fmt::Formatter<'_>> for NameDisplay {
-    fn from(f: &std::fmt::Formatter) -> Self {
-        if f.sign_minus() {
-            NameDisplay::Mangled
-        } else if f.alternate() {
-            NameDisplay::Full
-        } else {
-            NameDisplay::Short
+/// Name style is passed around with formatting flags: `-` keeps names mangled, `#` shows full
+/// names, no flag strips generic arguments and `+` keeps the hash
+fn name_style(f: &std::fmt::Formatter) -> (NameDisplay, bool) {
+    let display = if f.sign_minus() {
+        NameDisplay::Mangled
+    } else if f.alternate() {
+        NameDisplay::Full
+    } else {
+        NameDisplay::Short
+    };
+    (display, f.sign_plus())
+}
+
+/// Display a value with names in a given style
+pub struct Styled<'a, T>(pub &'a T, pub NameDisplay, pub bool);
+
+impl<T: std::fmt::Display> std::fmt::Display for Styled<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let item = self.0;
+        // width is passed along for instructions to align operands
+        let width = f.width().unwrap_or(0);
+        match (self.1, self.2) {
+            (NameDisplay::Mangled, _) => write!(f, "{item:-width$}"),
+            (NameDisplay::Full, false) => write!(f, "{item:#width$}"),
+            (NameDisplay::Full, true) => write!(f, "{item:+#width$}"),
+            (NameDisplay::Short, false) => write!(f, "{item:width$}"),
+            (NameDisplay::Short, true) => write!(f, "{item:+width$}"),
         }
     }
 }
 
 impl std::fmt::Display for Label<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let display = NameDisplay::from(&*f);
+        let (display, keep_hash) = name_style(f);
         write!(
             f,
             "{}:",
             color!(
-                demangle::contents(self.id, display),
-                OwoColorize::bright_yellow
+                demangle::contents(self.id, display, keep_hash),
+                theme::label
             )
         )
     }
@@ -655,7 +668,7 @@ pub enum Directive<'a> {
 #[derive(Clone, Debug)]
 pub struct GenericDirective<'a>(pub &'a str);
 
-pub fn parse_statement(input: &str) -> IResult<&str, Statement> {
+pub fn parse_statement(input: &str) -> IResult<&str, Statement<'_>> {
     let label = map(Label::parse, Statement::Label);
 
     let file = map(File::parse, Directive::File);
@@ -723,3 +736,15 @@ impl Statement<'_> {
         }
     }
 }
+
+#[test]
+fn test_operands_are_aligned() {
+    let (_, stmt) = parse_statement("\tmovq\t%rax, %rbx\n").unwrap();
+    assert_eq!(stmt.to_string(), "\tmovq %rax, %rbx");
+    assert_eq!(format!("{stmt:8}"), "\tmovq    %rax, %rbx");
+    assert_eq!(format!("{stmt:3}"), "\tmovq %rax, %rbx");
+    assert_eq!(
+        format!("{:8}", Styled(&stmt, NameDisplay::Full, false)),
+        "\tmovq    %rax, %rbx"
+    );
+}
@@ -0,0 +1,205 @@
+//! Coarse instruction statistics, a cheap complexity signal rather than a real analysis
+use super::statements::{Instruction, Statement};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    pub instructions: usize,
+    pub branches: usize,
+    pub calls: usize,
+    pub memory: usize,
+    pub simd: usize,
+    /// Labels used as branch targets
+    pub labels: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Category {
+    Branch,
+    Call,
+    Other,
+}
+
+/// Mnemonic prefixes for control flow, x86, ARM and WASM
+const PREFIXES: &[(&str, Category)] = &[
+    ("call", Category::Call),
+    ("bl", Category::Call),
+    ("j", Category::Branch),
+    ("loop", Category::Branch),
+    ("b", Category::Branch),
+    ("cb", Category::Branch),
+    ("tb", Category::Branch),
+    ("br", Category::Branch),
+];
+
+// x86 vector registers and ARM vector registers with or without arrangement
+static SIMD: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b[xyz]mm\d|\bv\d+\.|\bq\d+\b").expect("regexp should be valid"));
+
+/// Memory operands in Intel `[...]` and AT&T `(...)` syntax, stack operations, ARM loads
+/// and stores. `lea` and multibyte `nop` only look like they access memory
+fn is_memory(op: &str, args: &str) -> bool {
+    if op.starts_with("lea") || op.starts_with("nop") || matches!(op, "stc" | "std" | "sti") {
+        return false;
+    }
+    ["ld", "st", "push", "pop"]
+        .iter()
+        .any(|prefix| op.starts_with(prefix))
+        || args.contains(['[', '('])
+}
+
+fn category(op: &str) -> Category {
+    for (prefix, category) in PREFIXES {
+        let Some(rest) = op.strip_prefix(prefix) else {
+            continue;
+        };
+        // `bl` and `b` are full mnemonics on ARM, anything longer is something else
+        // unless it's a condition: `b.ne`, `bne`, `blr`, `jne`, etc
+        let matches = match *prefix {
+            "bl" => matches!(rest, "" | "r" | "x"),
+            "b" => rest.is_empty() || rest.starts_with('.') || is_condition(rest),
+            "br" => rest.is_empty() || rest.starts_with("_if") || rest.starts_with("_table"),
+            "call" | "j" | "loop" => true,
+            _ => rest.starts_with('z') || rest.starts_with("nz"),
+        };
+        if matches {
+            return *category;
+        }
+    }
+    Category::Other
+}
+
+/// Check if instruction transfers control: calls, jumps and branches
+pub(super) fn is_branch_or_call(op: &str) -> bool {
+    category(op) != Category::Other
+}
+
+/// Check if instruction is a jump or a branch, calls are not included
+pub(super) fn is_branch(op: &str) -> bool {
+    category(op) == Category::Branch
+}
+
+fn is_condition(s: &str) -> bool {
+    const CONDITIONS: &[&str] = &[
+        "eq", "ne", "cs", "hs", "cc", "lo", "mi", "pl", "vs", "vc", "hi", "ls", "ge", "lt", "gt",
+        "le", "al",
+    ];
+    CONDITIONS.contains(&s)
+}
+
+impl Stats {
+    #[must_use]
+    pub fn collect(stmts: &[Statement]) -> Self {
+        let targets = stmts
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Statement::Instruction(i) => i.args,
+                _ => None,
+            })
+            .flat_map(crate::demangle::local_labels)
+            .map(|m| m.as_str())
+            .collect::<BTreeSet<_>>();
+
+        let mut stats = Self::default();
+        for stmt in stmts {
+            match stmt {
+                Statement::Label(label) if targets.contains(label.id) => stats.labels += 1,
+                Statement::Instruction(Instruction { op, args }) if !op.starts_with('#') => {
+                    stats.instructions += 1;
+                    match category(op) {
+                        Category::Branch => stats.branches += 1,
+                        Category::Call => stats.calls += 1,
+                        Category::Other => {}
+                    }
+                    let args = args.unwrap_or_default();
+                    if is_memory(op, args) {
+                        stats.memory += 1;
+                    }
+                    if op.starts_with("vzero") || SIMD.is_match(args) {
+                        stats.simd += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        stats
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            instructions,
+            branches,
+            calls,
+            memory,
+            simd,
+            labels,
+        } = self;
+        writeln!(f, "instructions: {instructions}")?;
+        writeln!(f, "branches:     {branches}")?;
+        writeln!(f, "calls:        {calls}")?;
+        writeln!(f, "memory:       {memory}")?;
+        writeln!(f, "simd:         {simd}")?;
+        write!(f, "labels:       {labels}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{asm::statements::Label, demangle::LabelKind};
+
+    #[test]
+    fn mnemonics_are_categorized() {
+        for op in [
+            "jne", "jmp", "b", "b.ne", "bne", "cbz", "tbnz", "br", "br_if",
+        ] {
+            assert_eq!(category(op), Category::Branch, "{op}");
+        }
+        for op in ["call", "callq", "bl", "blr"] {
+            assert_eq!(category(op), Category::Call, "{op}");
+        }
+        for op in ["bic", "bsf", "bswap", "lea", "ret", "blsr"] {
+            assert_eq!(category(op), Category::Other, "{op}");
+        }
+    }
+
+    #[test]
+    fn instructions_are_counted() {
+        let stmts = [
+            Statement::Label(Label {
+                id: ".LBB0_1",
+                kind: LabelKind::Local,
+            }),
+            Statement::Instruction(Instruction {
+                op: "vmovups",
+                args: Some("ymm0, ymmword ptr [rdi]"),
+            }),
+            Statement::Instruction(Instruction {
+                op: "jne",
+                args: Some(".LBB0_1"),
+            }),
+            Statement::Label(Label {
+                id: ".Ltmp0",
+                kind: LabelKind::Temp,
+            }),
+            Statement::Instruction(Instruction {
+                op: "# comment",
+                args: None,
+            }),
+        ];
+        let expected = Stats {
+            instructions: 2,
+            branches: 1,
+            calls: 0,
+            memory: 1,
+            simd: 1,
+            labels: 1,
+        };
+        assert_eq!(Stats::collect(&stmts), expected);
+    }
+}
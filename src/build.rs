@@ -0,0 +1,1540 @@
+//! Running cargo to produce the generated code and locating the resulting file
+#![allow(clippy::missing_errors_doc)]
+use crate::{
+    cache, esafeprint, esafeprintln, opts, opts::Options, remarks::Remark, safeprintln, ExitCode,
+};
+use anyhow::Context;
+use cargo_metadata::{
+    diagnostic::DiagnosticLevel, Artifact, Message, Metadata, MetadataCommand, Package,
+};
+use once_cell::sync::Lazy;
+use std::{
+    ffi::{OsStr, OsString},
+    io::{BufReader, IsTerminal},
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+static CARGO_PATH: Lazy<PathBuf> =
+    Lazy::new(|| std::env::var_os("CARGO").map_or_else(|| "cargo".into(), PathBuf::from));
+static RUSTC_PATH: Lazy<PathBuf> =
+    Lazy::new(|| std::env::var_os("RUSTC").map_or_else(|| "rustc".into(), PathBuf::from));
+
+/// Arguments for `cargo rustc` that affect the generated code
+#[must_use]
+pub fn cargo_args(
+    cargo: &opts::Cargo,
+    format: &opts::Format,
+    syntax: opts::Syntax,
+    target_cpu: Option<&str>,
+    focus_package: &Package,
+    focus_artifact: &opts::Focus,
+) -> Vec<OsString> {
+    let mut cmd = CargoArgs::default();
+    // dependencies from registries and git can come in several versions
+    let package = match focus_package.source {
+        Some(_) => format!("{}@{}", focus_package.name, focus_package.version),
+        None => focus_package.name.clone(),
+    };
+
+    // Cargo flags.
+    cmd
+        // Workspace location.
+        .arg("--manifest-path")
+        .arg(&cargo.manifest_path)
+        // Artifact selectors.
+        .args(["--package", &package])
+        .args(focus_artifact.as_cargo_args())
+        // Compile options.
+        .args(cargo.frozen.then_some("--frozen"))
+        .args(cargo.locked.then_some("--locked"))
+        .args(cargo.offline.then_some("--offline"))
+        .args(cargo.target.iter().flat_map(|t| ["--target", t]))
+        .args(cargo.crate_type.iter().flat_map(|t| ["--crate-type", t]))
+        .args(cargo.unstable.iter().flat_map(|z| ["-Z", z]))
+        .args(
+            (syntax == opts::Syntax::Wasm && cargo.target.is_empty())
+                .then_some("--target=wasm32-unknown-unknown"),
+        )
+        .args(
+            cargo
+                .target_dir
+                .iter()
+                .flat_map(|t| [OsStr::new("--target-dir"), t.as_ref()]),
+        )
+        .args(
+            cargo
+                .cli_features
+                .no_default_features
+                .then_some("--no-default-features"),
+        )
+        .args(cargo.cli_features.all_features.then_some("--all-features"))
+        .args(
+            cargo
+                .cli_features
+                .merged_features()
+                .into_iter()
+                .flat_map(|feat| ["--features".to_owned(), feat]),
+        );
+    match &cargo.compile_mode {
+        opts::CompileMode::Dev => {}
+        opts::CompileMode::Release => {
+            cmd.arg("--release");
+        }
+        opts::CompileMode::Custom(profile) => {
+            cmd.args(["--profile", profile]);
+        }
+    }
+
+    // Cargo flags terminator.
+    cmd.arg("--");
+
+    // Rustc flags.
+    if format.verbosity > 0 && !cargo.codegen.is_empty() {
+        esafeprintln!("Extra codegen flags: {}", cargo.codegen.join(" "));
+    }
+    let target = cargo.target.first().map(String::as_str);
+    if matches!(syntax, opts::Syntax::Att | opts::Syntax::McaAtt)
+        && !opts::is_x86(target)
+        && !format.quiet
+    {
+        esafeprintln!(
+            "Warning: AT&T syntax is only available for x86 targets, --att is ignored for {}",
+            target.unwrap_or(std::env::consts::ARCH)
+        );
+    }
+    // users who configure llvm-args themselves can keep us from adding a conflicting one
+    let syntax_flag = syntax
+        .format_for(target)
+        .filter(|flag| !(cargo.no_syntax_flag && flag.contains("-x86-asm-syntax")));
+    if format.verbosity > 0 {
+        if let Some(effective) = syntax.effective_syntax(target, !cargo.no_syntax_flag) {
+            esafeprintln!("Assembly syntax: {effective}");
+        }
+    }
+    if let Some(ours) = syntax_flag.filter(|_| !format.quiet) {
+        for flag in &cargo.codegen {
+            if flag.contains("-x86-asm-syntax") && flag != ours {
+                esafeprintln!("-C {flag} is overridden by -C {ours} from the output format");
+            }
+        }
+    }
+    cmd
+        // Start with the user-supplied codegen flags, which we might need to override.
+        .args(cargo.codegen.iter().flat_map(|c| ["-C", c]))
+        // Next, we care about asm/wasm/llvm-ir/llvm-mac.
+        .args(["--emit", syntax.emit()])
+        // So only one file gets created, linked executables are inspected as they are.
+        .args(
+            match syntax {
+                opts::Syntax::Objdump => cargo.codegen_units,
+                _ => Some(1),
+            }
+            .map(|n| format!("-Ccodegen-units={n}")),
+        )
+        .args(syntax_flag.iter().flat_map(|s| ["-C", s]))
+        .args(target_cpu.iter().map(|cpu| format!("-Ctarget-cpu={cpu}")))
+        .args(format.remarks.then_some("-Cremark=all"));
+
+    {
+        #[allow(clippy::enum_glob_use)]
+        use opts::Syntax::*;
+        // Debug info is needed to detect function boundaries in asm (Windows/Mac), and to map asm/wasm
+        // output to rust source.
+        if matches!(syntax, Intel | Att | Wasm | McaAtt | McaIntel) {
+            cmd.arg("-Cdebuginfo=2");
+        }
+    }
+
+    cmd.0
+}
+
+/// Collects arguments with the same interface as [`std::process::Command`]
+#[derive(Default)]
+struct CargoArgs(Vec<OsString>);
+
+impl CargoArgs {
+    fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.0.push(arg.as_ref().to_owned());
+        self
+    }
+
+    fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.0
+            .extend(args.into_iter().map(|a| a.as_ref().to_owned()));
+        self
+    }
+}
+
+/// Command line as it could be typed in a shell, arguments with special characters are quoted
+fn shell_words(cmd: &std::process::Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            if !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_=/.,:+@%".contains(c))
+            {
+                arg.into_owned()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn spawn_cargo(format: &opts::Format, args: &[OsString]) -> std::io::Result<std::process::Child> {
+    // remarks need to be picked out of the diagnostics, so cargo can't print them on its own
+    let message_format = if !format.remarks {
+        "--message-format=json-render-diagnostics"
+    } else if format
+        .color
+        .unwrap_or_else(|| std::io::stderr().is_terminal())
+    {
+        "--message-format=json-diagnostic-rendered-ansi"
+    } else {
+        "--message-format=json"
+    };
+    let mut cmd = std::process::Command::new(&*CARGO_PATH);
+    cmd.arg("rustc")
+        // General.
+        .args([
+            message_format,
+            "--color",
+            match format.color {
+                Some(true) => "always",
+                Some(false) => "never",
+                None => "auto",
+            },
+        ])
+        .args(std::iter::repeat_n("-v", format.verbosity))
+        .args(format.quiet.then_some("--quiet"))
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    if format.verbosity > 0 {
+        esafeprintln!("Running {}", shell_words(&cmd));
+    }
+    cmd.spawn()
+}
+
+/// Sysroot of the toolchain used to build the code
+pub fn sysroot() -> anyhow::Result<PathBuf> {
+    let output = std::process::Command::new(&*RUSTC_PATH)
+        .arg("--print=sysroot")
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to get sysroot. '{RUSTC_PATH:?} --print=sysroot' exited with {}",
+            output.status,
+        );
+    }
+    // `rustc` prints a trailing newline.
+    Ok(PathBuf::from(
+        std::str::from_utf8(&output.stdout)?.trim_end(),
+    ))
+}
+
+/// Output of `rustc -vV`
+fn rustc_info() -> anyhow::Result<String> {
+    let output = std::process::Command::new(&*RUSTC_PATH)
+        .arg("-vV")
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to get rustc version. '{RUSTC_PATH:?} -vV' exited with {}",
+            output.status,
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Field from `rustc -vV` output
+fn info_field<'a>(info: &'a str, name: &str) -> &'a str {
+    info.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+        .unwrap_or("unknown")
+}
+
+/// Can this rustc release use unstable features
+fn is_nightly(release: &str) -> bool {
+    release.contains("nightly")
+        || release.contains("dev")
+        || std::env::var_os("RUSTC_BOOTSTRAP").is_some()
+}
+
+/// Print versions of cargo-show-asm and rustc it uses along with what this rustc can emit
+///
+/// Information comes from `rustc -vV`
+pub fn version_verbose() -> anyhow::Result<()> {
+    let info = rustc_info()?;
+    let field = |name: &str| info_field(&info, name);
+    let release = field("release");
+    let nightly = is_nightly(release);
+
+    safeprintln!("cargo-show-asm: {}", env!("CARGO_PKG_VERSION"));
+    safeprintln!("rustc: {RUSTC_PATH:?}");
+    safeprintln!("{}", info.lines().next().unwrap_or("unknown"));
+    safeprintln!("host: {}", field("host"));
+    safeprintln!("LLVM version: {}", field("LLVM version"));
+    safeprintln!("nightly: {}", if nightly { "yes" } else { "no" });
+    safeprintln!(
+        "emit: asm, llvm-ir, mir{}",
+        if nightly {
+            ""
+        } else {
+            " (-Z options need a nightly toolchain)"
+        }
+    );
+    Ok(())
+}
+
+/// Make cargo and rustc invocations use the rustup toolchain picked with `--toolchain`
+///
+/// Rustup proxies pick the toolchain from `RUSTUP_TOOLCHAIN`, but `CARGO` and `RUSTC` set by
+/// `cargo asm` point to binaries of the current toolchain and would bypass them. Must be called
+/// before running anything else.
+///
+/// # Errors
+/// If the toolchain is not installed or can't handle `-Z` flags
+pub fn select_toolchain(opts: &Options) -> anyhow::Result<()> {
+    let Some(toolchain) = &opts.cargo.toolchain else {
+        return Ok(());
+    };
+    std::env::set_var("RUSTUP_TOOLCHAIN", toolchain);
+    std::env::remove_var("CARGO");
+    std::env::remove_var("RUSTC");
+    let info = rustc_info().with_context(|| {
+        format!(
+            "Toolchain {toolchain} is not available, try\n\trustup toolchain install {toolchain}"
+        )
+    })?;
+    let release = info_field(&info, "release");
+    if !opts.cargo.unstable.is_empty() && !is_nightly(release) {
+        anyhow::bail!("-Z flags need a nightly toolchain, but {toolchain} is rustc {release}");
+    }
+    if opts.format.verbosity > 0 {
+        esafeprintln!(
+            "Using toolchain {toolchain}: {}",
+            info.lines().next().unwrap_or("unknown")
+        );
+    }
+    Ok(())
+}
+
+/// Read metadata of the workspace `--manifest-path` points to
+///
+/// With `--file` or `--stdin` there's nothing to build so a missing project is not an error
+pub fn metadata(opts: &Options) -> anyhow::Result<Option<Metadata>> {
+    let unstable = opts
+        .cargo
+        .unstable
+        .iter()
+        .flat_map(|x| ["-Z".to_owned(), x.clone()])
+        .collect::<Vec<_>>();
+
+    let mut cmd = MetadataCommand::new();
+    cmd.cargo_path(&*CARGO_PATH)
+        .manifest_path(&opts.cargo.manifest_path)
+        .other_options(unstable);
+    // resolving dependencies is slow and only --dep needs them
+    if opts.select_fragment.dep.is_none() {
+        cmd.no_deps();
+    }
+    match cmd.exec() {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(_) if opts.file.is_some() || opts.stdin => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Index of the innermost of `dirs` containing `path`
+fn innermost<'a>(dirs: impl IntoIterator<Item = &'a Path>, path: &Path) -> Option<usize> {
+    dirs.into_iter()
+        .enumerate()
+        .filter(|(_, dir)| path.starts_with(dir))
+        .max_by_key(|(_, dir)| dir.components().count())
+        .map(|(ix, _)| ix)
+}
+
+/// Workspace member the current directory belongs to, same as cargo picks it
+fn package_at_current_dir(metadata: &Metadata) -> Option<&Package> {
+    let cwd = std::env::current_dir().ok()?;
+    let cwd = cwd.canonicalize().unwrap_or(cwd);
+    let dirs = metadata
+        .packages
+        .iter()
+        .map(|p| {
+            let dir = p.manifest_path.parent().map_or_else(
+                || p.manifest_path.as_std_path().to_owned(),
+                |dir| dir.as_std_path().to_owned(),
+            );
+            dir.canonicalize().unwrap_or(dir)
+        })
+        .collect::<Vec<_>>();
+    let ix = innermost(dirs.iter().map(PathBuf::as_path), &cwd)?;
+    Some(&metadata.packages[ix])
+}
+
+/// Package picked with `--package`, the only one in the workspace, the one containing the
+/// current directory or the first one with `--first-match`
+///
+/// # Errors
+/// If the package can't be found or there's several to pick from, candidates are printed
+pub fn select_package<'a>(opts: &Options, metadata: &'a Metadata) -> anyhow::Result<&'a Package> {
+    if let Some(spec) = &opts.select_fragment.dep {
+        return select_dep(metadata, spec);
+    }
+    if let Some(name) = &opts.select_fragment.package {
+        if name.contains(['*', '?']) {
+            return select_by_glob(opts, metadata, name);
+        }
+        return metadata
+            .packages
+            .iter()
+            .find(|p| p.name == *name)
+            .ok_or_else(|| ExitCode::NoMatch.error(format!("Package '{name}' is not found")));
+    }
+    if let [package] = &metadata.packages[..] {
+        return Ok(package);
+    }
+    if let Some(package) = package_at_current_dir(metadata) {
+        if opts.format.verbosity > 0 {
+            esafeprintln!("Using {} from the current directory", package.name);
+        }
+        return Ok(package);
+    }
+    if let Some(focus) = opts.select_fragment.focus.as_ref() {
+        if let (kind, Some(name)) = focus.as_parts() {
+            let defining = metadata
+                .packages
+                .iter()
+                .filter(|p| defines_focus(p, focus))
+                .collect::<Vec<_>>();
+            match &defining[..] {
+                [] => {}
+                [package] => {
+                    if opts.format.verbosity > 0 {
+                        esafeprintln!("Using {} that defines {kind} {name}", package.name);
+                    }
+                    return Ok(*package);
+                }
+                [package, ..] if opts.select_fragment.first_match => {
+                    if !opts.format.quiet {
+                        esafeprintln!(
+                            "{kind} {name} is defined in multiple packages, using {} because of \
+                            --first-match",
+                            package.name
+                        );
+                    }
+                    return Ok(*package);
+                }
+                _ => {
+                    esafeprintln!(
+                        "{kind} {name} is defined in multiple packages, you need to specify which one to use"
+                    );
+                    for package in &defining {
+                        // doctests can't be picked with the `PACKAGE/NAME` form
+                        if focus.is_doctest() {
+                            esafeprintln!("\t-p {} --{kind} {name}", package.name);
+                        } else {
+                            esafeprintln!("\t--{kind} {}/{name}", package.name);
+                        }
+                    }
+                    return Err(ExitCode::Ambiguous
+                        .error(format!("Multiple packages define {kind} {name}")));
+                }
+            }
+        }
+    }
+    if opts.select_fragment.first_match {
+        let package = metadata
+            .packages
+            .iter()
+            .min_by(|a, b| a.name.cmp(&b.name))
+            .context("No packages found")?;
+        if !opts.format.quiet {
+            esafeprintln!(
+                "{:?} refers to multiple packages, using {} because of --first-match",
+                opts.cargo.manifest_path,
+                package.name
+            );
+        }
+        return Ok(package);
+    }
+    esafeprintln!(
+        "{:?} refers to multiple packages, you need to specify which one to use",
+        opts.cargo.manifest_path
+    );
+    for package in &metadata.packages {
+        esafeprintln!("\t-p {}", package.name);
+    }
+    Err(ExitCode::Ambiguous.error("Multiple packages found"))
+}
+
+/// Dependency picked with `--dep`, by name or by `name@version` if several versions are in use
+fn select_dep<'a>(metadata: &'a Metadata, spec: &str) -> anyhow::Result<&'a Package> {
+    let (name, version) = match spec.split_once('@') {
+        Some((name, version)) => (name, Some(version)),
+        None => (spec, None),
+    };
+    if matches!(name, "std" | "core" | "alloc") {
+        anyhow::bail!(
+            "{name} comes prebuilt with the toolchain, its generic code shows up in functions of \
+            your crate that use it"
+        );
+    }
+    let found = metadata
+        .packages
+        .iter()
+        .filter(|p| !metadata.workspace_members.contains(&p.id))
+        .filter(|p| p.name.replace('-', "_") == name.replace('-', "_"))
+        .filter(|p| version.is_none_or(|v| p.version.to_string() == v))
+        .collect::<Vec<_>>();
+    match &found[..] {
+        [] => Err(ExitCode::NoMatch.error(format!(
+            "Dependency '{spec}' is not found, it needs to be in Cargo.lock"
+        ))),
+        [package] => Ok(*package),
+        _ => {
+            esafeprintln!(
+                "'{spec}' matches multiple versions, you need to specify which one to use"
+            );
+            for package in &found {
+                esafeprintln!("\t--dep {}@{}", package.name, package.version);
+            }
+            Err(ExitCode::Ambiguous.error("Multiple versions found"))
+        }
+    }
+}
+
+/// Does the package define the target `focus` refers to, any target of the same kind if the
+/// name is not specified
+#[must_use]
+pub fn defines_focus(package: &Package, focus: &opts::Focus) -> bool {
+    if focus.is_doctest() {
+        return defines_focus(package, &opts::Focus::Lib);
+    }
+    let (want_kind, want_name) = focus.as_parts();
+    package
+        .targets
+        .iter()
+        .filter_map(|t| opts::Focus::try_from(t).ok())
+        .any(|f| {
+            let (kind, name) = f.as_parts();
+            kind == want_kind && (want_name.is_none() || name == want_name)
+        })
+}
+
+/// Match a name against a pattern where `*` stands for any number of characters and `?`
+/// for a single one
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut pattern_chars = pattern.chars();
+    match pattern_chars.next() {
+        None => name.is_empty(),
+        Some('*') => {
+            let rest = pattern_chars.as_str();
+            name.char_indices()
+                .map(|(ix, _)| ix)
+                .chain([name.len()])
+                .any(|ix| glob_match(rest, &name[ix..]))
+        }
+        Some(c) => {
+            let mut name_chars = name.chars();
+            name_chars.next().is_some_and(|n| c == '?' || c == n)
+                && glob_match(pattern_chars.as_str(), name_chars.as_str())
+        }
+    }
+}
+
+/// Pick one of the packages matching `-p` with a glob, the artifact narrows the choice down
+/// to packages that define it
+fn select_by_glob<'a>(
+    opts: &Options,
+    metadata: &'a Metadata,
+    pattern: &str,
+) -> anyhow::Result<&'a Package> {
+    let mut candidates = metadata
+        .packages
+        .iter()
+        .filter(|p| glob_match(pattern, &p.name))
+        .collect::<Vec<_>>();
+    if let (Some(focus), [_, _, ..]) = (&opts.select_fragment.focus, &candidates[..]) {
+        candidates.retain(|p| defines_focus(p, focus));
+    }
+    candidates.sort_by(|a, b| a.name.cmp(&b.name));
+    match &candidates[..] {
+        [] => Err(ExitCode::NoMatch.error(format!("No packages match '{pattern}'"))),
+        [package] => Ok(*package),
+        [package, ..] if opts.select_fragment.first_match => {
+            if !opts.format.quiet {
+                esafeprintln!(
+                    "'{pattern}' matches multiple packages, using {} because of --first-match",
+                    package.name
+                );
+            }
+            Ok(*package)
+        }
+        _ => {
+            esafeprintln!(
+                "'{pattern}' matches multiple packages, you need to specify which one to use"
+            );
+            for package in &candidates {
+                esafeprintln!("\t-p {}", package.name);
+            }
+            Err(ExitCode::Ambiguous.error("Multiple packages found"))
+        }
+    }
+}
+
+/// Target picked with `--lib`, `--bin`, etc or the only one the package defines
+///
+/// # Errors
+/// If there's several targets to pick from, candidates are printed
+pub fn select_focus(opts: &Options, package: &Package) -> anyhow::Result<opts::Focus> {
+    if let Some(focus) = &opts.select_fragment.focus {
+        return Ok(focus.clone());
+    }
+    if opts.select_fragment.dep.is_some() {
+        return Ok(opts::Focus::Lib);
+    }
+    // proc macros and build scripts run inside of the compiler, there's usually nothing
+    // interesting to see in them
+    let has_kind =
+        |target: &cargo_metadata::Target, kind: &str| target.kind.iter().any(|k| k == kind);
+    let targets = package
+        .targets
+        .iter()
+        .filter(|t| !has_kind(t, "custom-build") && !has_kind(t, "proc-macro"))
+        .collect::<Vec<_>>();
+    if targets.is_empty() && !package.targets.is_empty() {
+        if package.targets.iter().any(|t| has_kind(t, "proc-macro")) {
+            anyhow::bail!(
+                "{} is a proc-macro crate: its code runs inside of the compiler at build time and \
+                doesn't end up in the programs using it. Pass --lib to see its code anyway",
+                package.name
+            );
+        }
+        anyhow::bail!(
+            "{} only has a build script: it runs at build time and its code doesn't end up in \
+            the final artifact, showing code of build scripts is not supported",
+            package.name
+        );
+    }
+    match targets.len() {
+        0 => Err(ExitCode::NoMatch.error("No targets found")),
+        1 => opts::Focus::try_from(targets[0]),
+        _ => {
+            esafeprintln!(
+                "{} defines multiple targets, you need to specify which one to use:",
+                package.name
+            );
+            for target in targets {
+                if let Ok(focus) = opts::Focus::try_from(target) {
+                    let args = focus.as_cargo_args().collect::<Vec<_>>().join(" ");
+                    if target.kind == ["lib"] || !matches!(focus, opts::Focus::Lib) {
+                        esafeprintln!("\t{args}");
+                    } else {
+                        esafeprintln!("\t{args} ({})", target.kind.join(", "));
+                    }
+                }
+            }
+            Err(ExitCode::Ambiguous.error("Multiple targets found"))
+        }
+    }
+}
+
+/// File with the generated code along with everything needed to render it
+#[derive(Debug, Clone)]
+pub struct Generated {
+    /// `.s`, `.ll` or `.mir` file, or an executable for `--objdump`
+    pub path: PathBuf,
+    /// Code generated for the library of the same package with `--include-lib`, it is parsed
+    /// on its own and shown along with [`Generated::path`]
+    pub lib: Option<PathBuf>,
+    /// Target triple the code is built for, `None` for the host
+    pub target: Option<String>,
+    /// Workspace root, sources inside of it belong to the crate
+    pub workspace: PathBuf,
+    /// Sysroot of the toolchain, standard library sources are looked up there
+    pub sysroot: PathBuf,
+    /// Target directory used by cargo, `None` for `--file`
+    pub target_dir: Option<PathBuf>,
+    /// Optimization remarks reported while building, only collected with `--remarks`
+    pub remarks: Vec<Remark>,
+}
+
+/// Package, target and cargo options to build, shared by [`generate`] and [`plan`]
+struct Selection<'a> {
+    package: &'a Package,
+    focus: opts::Focus,
+    cargo: opts::Cargo,
+    target_dir: PathBuf,
+    /// Target triple to build for, `None` for the host
+    target: Option<String>,
+}
+
+impl Selection<'_> {
+    /// Build the library along with the focused target for `--include-lib`
+    fn include_lib(&self, opts: &Options) -> bool {
+        // linked executable already contains all the library code it uses
+        opts.select_fragment.include_lib
+            && !matches!(self.focus, opts::Focus::Lib)
+            && opts.syntax != opts::Syntax::Objdump
+    }
+}
+
+fn select<'a>(
+    opts: &Options,
+    metadata: &'a Metadata,
+    sysroot: &Path,
+) -> anyhow::Result<Selection<'a>> {
+    let target = match (opts.cargo.target.first(), opts.syntax) {
+        (Some(target), _) => Some(target.as_str()),
+        (None, opts::Syntax::Wasm) => Some("wasm32-unknown-unknown"),
+        (None, _) => None,
+    };
+    if let Some(target) = target {
+        check_target_installed(sysroot, target, &opts.cargo.unstable)?;
+    }
+
+    let focus_package = select_package(opts, metadata)?;
+    let focus_artifact = select_focus(opts, focus_package)?;
+
+    // --target-dir or CARGO_TARGET_DIR, otherwise whatever cargo uses for this workspace,
+    // passed to cargo explicitly so the build and the lookup agree on the location
+    let target_dir = opts
+        .cargo
+        .target_dir
+        .clone()
+        .unwrap_or_else(|| metadata.target_directory.clone().into_std_path_buf());
+    if opts.format.verbosity > 1 {
+        esafeprintln!("Target directory: {}", target_dir.display());
+    }
+    if opts.format.verbosity > 0 {
+        report_profile(&opts.cargo.compile_mode);
+    }
+    let cargo = opts::Cargo {
+        target_dir: Some(target_dir.clone()),
+        ..opts.cargo.clone()
+    };
+    Ok(Selection {
+        package: focus_package,
+        focus: focus_artifact,
+        cargo,
+        target_dir,
+        target: target.map(str::to_owned),
+    })
+}
+
+/// Build the code picked by `opts` or reuse the file from a previous run with the same options,
+/// `--file` is used as is
+pub fn generate(opts: &Options, metadata: Option<&Metadata>) -> anyhow::Result<Generated> {
+    let sysroot = sysroot()?;
+    if opts.format.verbosity > 0 {
+        esafeprintln!("Found sysroot: {}", sysroot.display());
+    }
+    let workspace = match metadata {
+        Some(metadata) => metadata.workspace_root.clone().into_std_path_buf(),
+        None => std::env::current_dir()?,
+    };
+
+    if let Some(file) = &opts.file {
+        return Ok(Generated {
+            path: file.clone(),
+            lib: None,
+            target: opts.cargo.target.first().cloned(),
+            workspace,
+            sysroot,
+            target_dir: None,
+            remarks: Vec::new(),
+        });
+    }
+
+    let metadata = metadata.context("No cargo metadata")?;
+    let selection = select(opts, metadata, &sysroot)?;
+    if selection.focus.is_doctest() {
+        let opts::Focus::Doctest(wanted) = &selection.focus else {
+            anyhow::bail!("--doc lists doctests, use list_doctests for it");
+        };
+        return Ok(Generated {
+            path: build_doctest(opts, selection.package, wanted, &selection.target_dir)?,
+            lib: None,
+            target: selection.target,
+            workspace,
+            sysroot,
+            target_dir: Some(selection.target_dir),
+            remarks: Vec::new(),
+        });
+    }
+    let include_lib = selection.include_lib(opts);
+    let Selection {
+        package: focus_package,
+        focus: focus_artifact,
+        cargo,
+        target_dir,
+        target,
+    } = selection;
+
+    let mut cache = cache::Cache::load(&target_dir);
+    let rustc_info = rustc_info()?;
+    let mut remarks = Vec::new();
+    let mut produce = |focus_artifact: &opts::Focus| -> anyhow::Result<PathBuf> {
+        let cargo_args = cargo_args(
+            &cargo,
+            &opts.format,
+            opts.syntax,
+            opts.target_cpu.as_deref(),
+            focus_package,
+            focus_artifact,
+        );
+
+        let cache_key = cache::key(&cargo_args, &sysroot, &rustc_info);
+        let roots = metadata
+            .packages
+            .iter()
+            .filter_map(|p| p.manifest_path.parent())
+            .map(|p| p.as_std_path());
+        if opts.cargo.no_build {
+            return match cache.lookup(&cache_key, metadata.workspace_root.as_std_path(), roots) {
+                Some(path) => Ok(path.to_path_buf()),
+                None => match cache.get(&cache_key) {
+                    Some(path) => anyhow::bail!(
+                        "{} is older than its sources, --no-build doesn't rebuild it",
+                        path.display()
+                    ),
+                    None => anyhow::bail!(
+                        "Nothing was generated with these options yet, --no-build only uses files \
+                        produced by a previous run without it"
+                    ),
+                },
+            };
+        }
+        // remarks are only reported by an actual build
+        let cached = (!opts.cargo.no_cache && !opts.format.remarks)
+            .then(|| cache.lookup(&cache_key, metadata.workspace_root.as_std_path(), roots))
+            .flatten()
+            .map(Path::to_path_buf);
+
+        if let Some(path) = cached {
+            if opts.format.verbosity > 0 {
+                esafeprintln!("Reusing asm file from a previous run: {}", path.display());
+            }
+            return Ok(path);
+        }
+        let started = std::time::SystemTime::now();
+        let path = build(
+            &opts.format,
+            opts.syntax,
+            &cargo_args,
+            focus_artifact,
+            &mut remarks,
+        )?;
+        cache.store(cache_key, path.clone(), started);
+        Ok(path)
+    };
+
+    let path = produce(&focus_artifact)?;
+    if let Some(dir) = &opts.save_artifacts {
+        save_artifact(
+            dir,
+            focus_package,
+            &focus_artifact,
+            target.as_deref(),
+            &path,
+            opts.format.quiet,
+        )?;
+    }
+    let lib = if include_lib {
+        let has_lib = focus_package
+            .targets
+            .iter()
+            .any(|t| t.kind.iter().any(|k| opts::LIB_KINDS.contains(&k.as_str())));
+        if !has_lib {
+            anyhow::bail!("{} has no library target to include", focus_package.name);
+        }
+        let lib = produce(&opts::Focus::Lib)?;
+        if let Some(dir) = &opts.save_artifacts {
+            save_artifact(
+                dir,
+                focus_package,
+                &opts::Focus::Lib,
+                target.as_deref(),
+                &lib,
+                opts.format.quiet,
+            )?;
+        }
+        Some(lib)
+    } else {
+        None
+    };
+    Ok(Generated {
+        path,
+        lib,
+        target,
+        workspace,
+        sysroot,
+        target_dir: Some(target_dir),
+        remarks,
+    })
+}
+
+/// Describe the `cargo rustc` calls [`generate`] would make without running them, for `--dry`
+///
+/// The result is a JSON object in the spirit of cargo's `--build-plan`
+pub fn plan(opts: &Options, metadata: Option<&Metadata>) -> anyhow::Result<serde_json::Value> {
+    let metadata = metadata.context("No cargo metadata")?;
+    let sysroot = sysroot()?;
+    let selection = select(opts, metadata, &sysroot)?;
+    if selection.focus.is_doctest() {
+        anyhow::bail!("--dry doesn't support doctests, they are built by rustdoc");
+    }
+    let mut focuses = vec![selection.focus.clone()];
+    if selection.include_lib(opts) {
+        focuses.push(opts::Focus::Lib);
+    }
+    let invocations = focuses
+        .iter()
+        .map(|focus| {
+            let args = cargo_args(
+                &selection.cargo,
+                &opts.format,
+                opts.syntax,
+                opts.target_cpu.as_deref(),
+                selection.package,
+                focus,
+            );
+            plan_invocation(
+                selection.package,
+                focus,
+                selection.cargo.target.first(),
+                &args,
+                metadata.workspace_root.as_std_path(),
+            )
+        })
+        .collect::<Vec<_>>();
+    Ok(serde_json::json!({
+        "invocations": invocations,
+        "inputs": [metadata.workspace_root.join("Cargo.toml")],
+    }))
+}
+
+/// Copy a generated file for `--save-artifacts`, names don't depend on hashes cargo picks so
+/// files from later runs replace the earlier ones
+fn save_artifact(
+    dir: &Path,
+    package: &Package,
+    focus: &opts::Focus,
+    target: Option<&str>,
+    path: &Path,
+    quiet: bool,
+) -> anyhow::Result<()> {
+    let (kind, name) = focus.as_parts();
+    // custom targets are paths to json specs
+    let target = target.and_then(|t| Path::new(t).file_stem()?.to_str());
+    let mut file = format!("{}-{kind}", package.name);
+    for part in name.into_iter().chain(target) {
+        file.push('-');
+        file.push_str(part);
+    }
+    if let Some(ext) = path.extension() {
+        file.push('.');
+        file.push_str(&ext.to_string_lossy());
+    }
+    std::fs::create_dir_all(dir).with_context(|| format!("Can't create {}", dir.display()))?;
+    let dest = dir.join(file);
+    std::fs::copy(path, &dest)
+        .with_context(|| format!("Can't copy {} to {}", path.display(), dest.display()))?;
+    if !quiet {
+        esafeprintln!("Saved {}", dest.display());
+    }
+    Ok(())
+}
+
+/// A single `cargo rustc` call for `--dry`, similar to an entry of cargo's `--build-plan`
+fn plan_invocation(
+    package: &Package,
+    focus: &opts::Focus,
+    target: Option<&String>,
+    cargo_args: &[OsString],
+    cwd: &Path,
+) -> serde_json::Value {
+    let mut args = vec!["rustc".to_owned()];
+    args.extend(cargo_args.iter().map(|a| a.to_string_lossy().into_owned()));
+    let rustc_args = cargo_args
+        .iter()
+        .skip_while(|a| *a != "--")
+        .skip(1)
+        .map(|a| a.to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    let (target_kind, target_name) = focus.as_parts();
+    serde_json::json!({
+        "package_name": package.name,
+        "package_version": package.version.to_string(),
+        "target_kind": [target_kind],
+        "target_name": target_name,
+        "kind": target,
+        "compile_mode": "build",
+        "program": CARGO_PATH.to_string_lossy(),
+        "args": args,
+        "rustc_args": rustc_args,
+        "cwd": cwd,
+    })
+}
+
+/// Print the profile used for the build along with `CARGO_PROFILE_*` variables that override it
+///
+/// Settings from manifests and cargo config files are not shown: cargo merges them together
+/// with inherited profiles and defaults, and reading them here would only approximate that
+fn report_profile(compile_mode: &opts::CompileMode) {
+    let profile = compile_mode.profile();
+    esafeprintln!(
+        "Building with profile {profile}, see [profile.{profile}] in Cargo.toml and cargo config \
+        files for its settings"
+    );
+    let prefix = format!(
+        "CARGO_PROFILE_{}_",
+        profile.to_uppercase().replace('-', "_")
+    );
+    for (var, value) in std::env::vars() {
+        if let Some(key) = var.strip_prefix(&prefix) {
+            let key = key.to_lowercase().replace('_', "-");
+            esafeprintln!("{key} = {value} (from {var})");
+        }
+    }
+    esafeprintln!("codegen-units = 1 is always used so all the code ends up in a single file");
+}
+
+/// Make sure standard library for a target is available, unless it's going to be built
+/// from sources or target is a custom one
+pub fn check_target_installed(
+    sysroot: &Path,
+    target: &str,
+    unstable: &[String],
+) -> anyhow::Result<()> {
+    if target.ends_with(".json") || unstable.iter().any(|z| z.starts_with("build-std")) {
+        return Ok(());
+    }
+    if !sysroot.join("lib/rustlib").join(target).exists() {
+        anyhow::bail!(
+            "Target {target} is not installed in {}, try\n\trustup target add {target}",
+            sysroot.display()
+        );
+    }
+    Ok(())
+}
+
+/// Print names of the doctests [`generate`] can pick with `--doctest`, for `--doc`
+///
+/// # Errors
+/// If the doctests fail to build
+pub fn list_doctests(opts: &Options, metadata: Option<&Metadata>) -> anyhow::Result<()> {
+    let metadata = metadata.context("No cargo metadata")?;
+    let sysroot = sysroot()?;
+    let selection = select(opts, metadata, &sysroot)?;
+    let (_, names) = build_doctests(opts, selection.package, &selection.target_dir)?;
+    let (merged, names) = names
+        .into_iter()
+        .partition::<Vec<_>, _>(|name| is_merged_doctest(name));
+    if names.is_empty() && merged.is_empty() {
+        safeprintln!("{} has no doctests", selection.package.name);
+    }
+    for name in &names {
+        safeprintln!("--doctest {name}");
+    }
+    if !merged.is_empty() && !opts.format.quiet {
+        esafeprintln!("{MERGED_DOCTESTS}");
+    }
+    Ok(())
+}
+
+/// Explains why doctests of edition 2024 crates are missing from `--doc`
+const MERGED_DOCTESTS: &str = "Edition 2024 merges doctests into a single binary that can't be \
+    picked by name, only doctests marked as standalone_crate can be shown";
+
+/// Edition 2024 doctests are compiled together into binaries named `merged_doctest_*`
+fn is_merged_doctest(name: &str) -> bool {
+    name.starts_with("merged_doctest_")
+}
+
+/// Build the doctest `wanted` of a package and get the binary rustdoc made for it
+fn build_doctest(
+    opts: &Options,
+    package: &Package,
+    wanted: &str,
+    target_dir: &Path,
+) -> anyhow::Result<PathBuf> {
+    let (dir, names) = build_doctests(opts, package, target_dir)?;
+    let (merged, names) = names
+        .into_iter()
+        .partition::<Vec<_>, _>(|name| is_merged_doctest(name));
+    let exe = |name: &str| {
+        dir.join(name)
+            .join("rust_out")
+            .with_extension(std::env::consts::EXE_EXTENSION)
+    };
+    let matching = names
+        .iter()
+        .filter(|name| name.contains(wanted))
+        .collect::<Vec<_>>();
+    match &matching[..] {
+        [] if !merged.is_empty() => {
+            Err(ExitCode::NoMatch.error(format!("No doctests match '{wanted}'. {MERGED_DOCTESTS}")))
+        }
+        [] => Err(ExitCode::NoMatch.error(format!(
+            "No doctests match '{wanted}', use --doc to list them"
+        ))),
+        [name] => Ok(exe(name)),
+        _ if names.iter().any(|name| name == wanted) => Ok(exe(wanted)),
+        _ => {
+            esafeprintln!(
+                "'{wanted}' matches multiple doctests, you need to specify which one to use"
+            );
+            for name in matching {
+                esafeprintln!("\t--doctest {name}");
+            }
+            Err(ExitCode::Ambiguous.error("Multiple doctests found"))
+        }
+    }
+}
+
+/// Build doctests of a package and keep the binaries rustdoc makes, get the directory they are
+/// kept in and their names
+///
+/// Doctest binaries are named after the file and the line the doctest starts at:
+/// `src_lib_rs_12_0`. Keeping them needs unstable rustdoc flags and so a nightly toolchain.
+fn build_doctests(
+    opts: &Options,
+    package: &Package,
+    target_dir: &Path,
+) -> anyhow::Result<(PathBuf, Vec<String>)> {
+    let release = info_field(&rustc_info()?, "release").to_owned();
+    if !is_nightly(&release) {
+        anyhow::bail!(
+            "Doctests can only be kept with a nightly toolchain, this is rustc {release}"
+        );
+    }
+    let dir = target_dir
+        .join("cargo-show-asm-doctests")
+        .join(&package.name);
+    // doctests that are gone from the sources shouldn't show up
+    let _ = std::fs::remove_dir_all(&dir);
+
+    // encoded flags keep arguments with spaces such as the directory intact
+    let mut rustdoc_flags = match std::env::var("CARGO_ENCODED_RUSTDOCFLAGS") {
+        Ok(flags) => flags
+            .split('\x1f')
+            .filter(|f| !f.is_empty())
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>(),
+        Err(_) => std::env::var("RUSTDOCFLAGS")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(ToOwned::to_owned)
+            .collect(),
+    };
+    rustdoc_flags
+        .extend(["-Zunstable-options", "--no-run", "--persist-doctests"].map(ToOwned::to_owned));
+    rustdoc_flags.push(dir.to_string_lossy().into_owned());
+    rustdoc_flags.extend(opts.cargo.codegen.iter().map(|flag| format!("-C{flag}")));
+
+    let mut cmd = std::process::Command::new(&*CARGO_PATH);
+    cmd.args(["test", "--doc", "--manifest-path"])
+        .arg(&opts.cargo.manifest_path)
+        .args(["--package", &package.name])
+        .arg("--target-dir")
+        .arg(target_dir)
+        .args(opts.cargo.target.iter().flat_map(|t| ["--target", t]))
+        .args(opts.cargo.frozen.then_some("--frozen"))
+        .args(opts.cargo.locked.then_some("--locked"))
+        .args(opts.cargo.offline.then_some("--offline"))
+        .args(
+            opts.cargo
+                .cli_features
+                .no_default_features
+                .then_some("--no-default-features"),
+        )
+        .args(
+            opts.cargo
+                .cli_features
+                .all_features
+                .then_some("--all-features"),
+        )
+        .args(
+            opts.cargo
+                .cli_features
+                .merged_features()
+                .into_iter()
+                .flat_map(|feat| ["--features".to_owned(), feat]),
+        )
+        .args(opts.format.quiet.then_some("--quiet"))
+        .env("CARGO_ENCODED_RUSTDOCFLAGS", rustdoc_flags.join("\x1f"))
+        .env_remove("RUSTDOCFLAGS")
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .stdout(Stdio::piped());
+    match &opts.cargo.compile_mode {
+        opts::CompileMode::Dev => {}
+        opts::CompileMode::Release => {
+            cmd.arg("--release");
+        }
+        opts::CompileMode::Custom(profile) => {
+            cmd.args(["--profile", profile]);
+        }
+    }
+    if opts.format.verbosity > 0 {
+        esafeprintln!("Running {}", shell_words(&cmd));
+    }
+    let output = cmd.output()?;
+    if !output.status.success() {
+        // doctest harness reports compilation errors on stdout
+        esafeprint!("{}", String::from_utf8_lossy(&output.stdout));
+        return Err(ExitCode::BuildFailed.error(format!("Cargo failed with {}", output.status)));
+    }
+
+    let mut names = std::fs::read_dir(&dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|e| e.path().is_dir())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    names.sort();
+    Ok((dir, names))
+}
+
+/// Run cargo and locate the file it generated, optimization remarks are added to `remarks`
+pub fn build(
+    format: &opts::Format,
+    syntax: opts::Syntax,
+    cargo_args: &[OsString],
+    focus_artifact: &opts::Focus,
+    remarks: &mut Vec<Remark>,
+) -> anyhow::Result<PathBuf> {
+    let mut cargo_child = spawn_cargo(format, cargo_args)?;
+
+    let mut result_artifact = None;
+    let mut success = false;
+    for msg in Message::parse_stream(BufReader::new(cargo_child.stdout.take().unwrap())) {
+        match msg? {
+            Message::CompilerArtifact(artifact) if focus_artifact.matches_artifact(&artifact) => {
+                result_artifact = Some(artifact);
+            }
+            Message::CompilerMessage(msg) => {
+                let remark = (msg.message.level == DiagnosticLevel::Note)
+                    .then(|| Remark::parse(&msg.message.message))
+                    .flatten();
+                match (remark, msg.message.rendered) {
+                    (Some(remark), _) => remarks.push(remark),
+                    (None, Some(rendered)) => esafeprint!("{rendered}"),
+                    (None, None) => {}
+                }
+            }
+            Message::BuildFinished(fin) => {
+                success = fin.success;
+                break;
+            }
+            _ => {}
+        }
+    }
+    // add some spacing between cargo's output and ours
+    if !format.quiet {
+        esafeprintln!();
+    }
+    if !success {
+        let status = cargo_child.wait()?;
+        return Err(ExitCode::BuildFailed.error(format!("Cargo failed with {status}")));
+    }
+    let artifact = result_artifact.context("No artifact found")?;
+
+    if format.verbosity > 1 {
+        esafeprintln!("Artifact files: {:?}", artifact.filenames);
+    }
+
+    if syntax == opts::Syntax::Objdump {
+        return artifact
+            .executable
+            .map(cargo_metadata::camino::Utf8PathBuf::into_std_path_buf)
+            .with_context(|| {
+                format!(
+                    "{} doesn't produce an executable, --objdump needs a binary target such as \
+                    --bin or --example",
+                    artifact.target.name
+                )
+            });
+    }
+
+    let asm_path = locate_asm_path_via_artifact(&artifact, syntax.ext())?;
+    if format.verbosity > 1 {
+        esafeprintln!("Asm file: {}", asm_path.display());
+    }
+    Ok(asm_path)
+}
+
+fn locate_asm_path_via_artifact(artifact: &Artifact, expect_ext: &str) -> anyhow::Result<PathBuf> {
+    // For lib, test, bench, lib-type example, `filenames` hint the file stem of the asm file.
+    // We could locate asm files precisely.
+    //
+    // `filenames`:
+    // [..]/target/debug/deps/libfoo-01234567.rmeta         # lib by-product
+    // [..]/target/debug/deps/foo-01234567                  # test & bench
+    // [..]/target/debug/deps/example/libfoo-01234567.rmeta # lib-type example by-product
+    // Asm files:
+    // [..]/target/debug/deps/foo-01234567.s
+    // [..]/target/debug/deps/example/foo-01234567.s
+    if let Some(path) = artifact
+        .filenames
+        .iter()
+        .filter(|path| {
+            matches!(
+                path.parent().unwrap().file_name(),
+                Some("deps" | "examples")
+            )
+        })
+        .find_map(|path| {
+            let path = path.with_extension(expect_ext);
+            if path.exists() {
+                return Some(path);
+            }
+            let path = path.with_file_name(path.file_name()?.strip_prefix("lib")?);
+            if path.exists() {
+                return Some(path);
+            }
+            None
+        })
+    {
+        return Ok(path.into_std_path_buf());
+    }
+
+    // then there's rlib with filenames as following:
+    // `filenames`:
+    // [..]/target/debug/libfoo.a              <+
+    // [..]/target/debug/libfoo.rlib            | <+ Hard linked.
+    // Asm files:                               |  | Or same contents at least
+    // [..]/target/debug/libfoo-01234567.a     <+  |
+    // [..]/target/debug/libfoo-01234567.rlib     <+
+    // [..]/target/debug/foo-01234567.s
+
+    //
+    // staticlib is the same, with libfoo.a on unix and foo.lib on windows
+    if artifact
+        .target
+        .kind
+        .iter()
+        .any(|k| k == "rlib" || k == "staticlib")
+    {
+        let rlib_path = artifact
+            .filenames
+            .iter()
+            .find(|f| {
+                f.extension()
+                    .is_some_and(|e| ["rlib", "a", "lib"].contains(&e))
+            })
+            .expect("No rlib?");
+        let deps_dir = rlib_path.with_file_name("deps");
+
+        for entry in deps_dir.read_dir()? {
+            let maybe_origin = entry?.path();
+            if same_contents(&rlib_path, &maybe_origin)? {
+                let name = maybe_origin.file_name().unwrap().to_str().unwrap();
+                let name = name.strip_prefix("lib").unwrap_or(name);
+                let asm_file = maybe_origin.with_file_name(name).with_extension(expect_ext);
+                if asm_file.exists() {
+                    return Ok(asm_file);
+                }
+            }
+        }
+    }
+
+    // for cdylib we have
+    // [..]/target/debug/deps/xx.d
+    // [..]/target/debug/deps/libxx.so <+ Hard linked/same contents
+    // [..]/target/debug/deps/xx.s      | <- asm file
+    // [..]/target/debug/libxx.d        |
+    // [..]/target/debug/libxx.so      <+ <- artifact
+    //
+    // on windows it's xx.dll / xx.s, on MacOS it's libxx.dylib / xx.s...
+    // rust dylib and proc-macro crates are the same
+    if artifact
+        .target
+        .kind
+        .iter()
+        .any(|k| ["cdylib", "dylib", "proc-macro"].contains(&k.as_str()))
+    {
+        let cdylib_path = artifact
+            .filenames
+            .iter()
+            .find(|f| {
+                f.extension()
+                    .is_some_and(|e| ["so", "dylib", "dll"].contains(&e))
+            })
+            .expect("No cdylib?");
+        let deps_dir = cdylib_path.with_file_name("deps");
+        for entry in deps_dir.read_dir()? {
+            let entry = entry?;
+            let maybe_origin = entry.path();
+            if same_contents(cdylib_path, &maybe_origin)? {
+                let Some(name) = maybe_origin.file_name() else {
+                    continue;
+                };
+                let Some(name) = name.to_str() else { continue };
+                let name = name.strip_prefix("lib").unwrap_or(name);
+                // on windows this is xx.dll -> xx.s, no lib....
+                let asm_file = maybe_origin.with_file_name(name).with_extension(expect_ext);
+                if asm_file.exists() {
+                    return Ok(asm_file);
+                }
+            }
+        }
+    }
+
+    // For bin or bin-type example artifacts, `filenames` provide hard-linked paths
+    // without extra-filename.
+    // We scans all possible original artifacts by checking hard links,
+    // in order to retrieve the correct extra-filename, and then locate asm files.
+    //
+    // `filenames`, also `executable`:
+    // [..]/target/debug/foobin                    <+
+    // [..]/target/debug/examples/fooexample        | <+ Hard linked.
+    // Origins:                                     |  |
+    // [..]/target/debug/deps/foobin-01234567      <+  |
+    // [..]/target/debug/examples/fooexample-01234567 <+
+    // Asm files:
+    // [..]/target/debug/deps/foobin-01234567.s
+    // [..]/target/debug/examples/fooexample-01234567.s
+    if let Some(exe_path) = &artifact.executable {
+        let parent = exe_path.parent().unwrap();
+        let deps_dir = if parent.file_name() == Some("examples") {
+            parent.to_owned()
+        } else {
+            exe_path.with_file_name("deps")
+        };
+
+        for entry in deps_dir.read_dir()? {
+            let maybe_origin = entry?.path();
+            if same_contents(&exe_path, &maybe_origin)? {
+                let asm_file = maybe_origin.with_extension(expect_ext);
+                if asm_file.exists() {
+                    return Ok(asm_file);
+                }
+            }
+        }
+    }
+
+    anyhow::bail!("Cannot locate the path to the asm file");
+}
+
+fn same_contents<A: AsRef<Path>, B: AsRef<Path>>(a: &A, b: &B) -> anyhow::Result<bool> {
+    Ok(same_file::is_same_file(a, b)?
+        || (std::fs::metadata(a)?.len() == std::fs::metadata(b)?.len()
+            && std::fs::read(a)? == std::fs::read(b)?))
+}
+
+/// Print targets of workspace packages, or of a single package picked with `-p`, with options
+/// to select them
+///
+/// # Errors
+/// If the package is not found or a glob matches several of them
+pub fn list_targets(opts: &Options, metadata: &cargo_metadata::Metadata) -> anyhow::Result<()> {
+    let json = opts.format.style == opts::OutputStyle::Json;
+    let packages = match opts.select_fragment.package.as_deref() {
+        Some(name) if name.contains(['*', '?']) => vec![select_by_glob(opts, metadata, name)?],
+        Some(name) => {
+            let packages = metadata
+                .packages
+                .iter()
+                .filter(|p| p.name == name)
+                .collect::<Vec<_>>();
+            if packages.is_empty() {
+                return Err(ExitCode::NoMatch.error(format!("Package '{name}' is not found")));
+            }
+            packages
+        }
+        None => metadata.packages.iter().collect(),
+    };
+
+    let mut targets = Vec::new();
+    for package in packages {
+        for target in &package.targets {
+            // build scripts and such can't be selected
+            let Ok(focus) = opts::Focus::try_from(target) else {
+                continue;
+            };
+            let mut args = vec!["-p".to_owned(), package.name.clone()];
+            args.extend(focus.as_cargo_args());
+            targets.push((package, target, args));
+        }
+    }
+
+    if json {
+        let targets = targets
+            .iter()
+            .map(|(package, target, args)| {
+                serde_json::json!({
+                    "package": package.name,
+                    "name": target.name,
+                    "kind": target.kind,
+                    "args": args,
+                })
+            })
+            .collect::<Vec<_>>();
+        safeprintln!("{}", serde_json::to_string_pretty(&targets)?);
+    } else {
+        for (_, target, args) in targets {
+            safeprintln!(
+                "{}\t{}\t{}",
+                target.name,
+                target.kind.join(","),
+                args.join(" ")
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{glob_match, innermost};
+    use std::path::Path;
+
+    #[test]
+    fn globs_match_names() {
+        assert!(glob_match("isin-*", "isin-core"));
+        assert!(glob_match("isin-*", "isin-"));
+        assert!(glob_match("*-core", "isin-core"));
+        assert!(glob_match("is?n", "isin"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("isin-*", "isin"));
+        assert!(!glob_match("is?n", "isn"));
+        assert!(!glob_match("*-core", "isin-cores"));
+    }
+
+    #[test]
+    fn innermost_dir_is_picked() {
+        let dirs = [
+            Path::new("/ws"),
+            Path::new("/ws/crates/a"),
+            Path::new("/ws/crates/b"),
+        ];
+        assert_eq!(innermost(dirs, Path::new("/ws/crates/a/src")), Some(1));
+        assert_eq!(innermost(dirs, Path::new("/ws/crates")), Some(0));
+        assert_eq!(innermost(dirs, Path::new("/ws/crates/bb")), Some(0));
+        assert_eq!(innermost(dirs, Path::new("/elsewhere")), None);
+    }
+}
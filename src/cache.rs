@@ -0,0 +1,196 @@
+//! Remember which file cargo produced for a given set of build options
+//!
+//! Cache lives in the target directory and maps a key derived from cargo arguments, toolchain
+//! version, cargo config files and relevant environment variables to the generated file. Cached entry is reused if the file
+//! is still present and no source file it depends on is newer than it is.
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+const CACHE_FILE: &str = "cargo-show-asm-cache.json";
+
+/// Environment variables that change the generated code without changing cargo arguments
+const ENV_VARS: &[&str] = &[
+    "RUSTFLAGS",
+    "CARGO_ENCODED_RUSTFLAGS",
+    "CARGO_BUILD_RUSTFLAGS",
+    "RUSTC",
+    "RUSTC_WRAPPER",
+    "RUSTUP_TOOLCHAIN",
+];
+
+/// Prefixes of environment variables cargo reads config values from: profile settings,
+/// per target rustflags and build settings
+const ENV_PREFIXES: &[&str] = &["CARGO_PROFILE_", "CARGO_TARGET_", "CARGO_BUILD_"];
+
+pub struct Cache {
+    path: PathBuf,
+    entries: BTreeMap<String, Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    path: PathBuf,
+    /// When cargo was started to produce the file, cargo doesn't touch files it considers fresh
+    built: SystemTime,
+}
+
+/// Cargo config files that apply to a build started in the current directory, they can
+/// change rustflags and profile settings
+fn cargo_configs() -> Vec<PathBuf> {
+    let mut dirs = std::env::current_dir()
+        .map(|dir| {
+            dir.ancestors()
+                .map(|d| d.join(".cargo"))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    if let Some(home) = std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::home_dir().map(|home| home.join(".cargo")))
+    {
+        dirs.push(home);
+    }
+    dirs.iter()
+        .flat_map(|dir| [dir.join("config.toml"), dir.join("config")])
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// Key that identifies a build, `rustc_info` is the output of `rustc -vV`
+#[must_use]
+pub fn key(args: &[OsString], sysroot: &Path, rustc_info: &str) -> String {
+    let mut key = sysroot.display().to_string();
+    let mut push = |part: &str| {
+        key.push('\x1f');
+        key.push_str(part);
+    };
+    push(rustc_info.trim_end());
+    for arg in args {
+        push(&arg.to_string_lossy());
+    }
+    for var in ENV_VARS {
+        if let Some(val) = std::env::var_os(var) {
+            push(&format!("{var}={}", val.to_string_lossy()));
+        }
+    }
+    let mut vars = std::env::vars_os()
+        .filter_map(|(var, val)| Some((var.into_string().ok()?, val)))
+        .filter(|(var, _)| ENV_PREFIXES.iter().any(|p| var.starts_with(p)))
+        .collect::<Vec<_>>();
+    vars.sort();
+    for (var, val) in vars {
+        push(&format!("{var}={}", val.to_string_lossy()));
+    }
+    // contents are only needed to notice changes, keep the key short
+    for path in cargo_configs() {
+        let mut hasher = DefaultHasher::new();
+        std::fs::read(&path).unwrap_or_default().hash(&mut hasher);
+        push(&format!("{}={:x}", path.display(), hasher.finish()));
+    }
+    key
+}
+
+fn mtime(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Check if any file in `dir` that can affect the build is newer than `time`
+#[must_use]
+pub fn newer_sources(dir: &Path, time: SystemTime) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            if name.starts_with('.') || name == "target" {
+                continue;
+            }
+            if newer_sources(&path, time) {
+                return true;
+            }
+        } else if (name.ends_with(".rs") || name == "Cargo.toml" || name == "Cargo.lock")
+            && mtime(&path).is_some_and(|m| m > time)
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// Files listed in dep-info file cargo places next to the generated one,
+/// relative paths are relative to the workspace root
+fn dep_info_sources(path: &Path, workspace_root: &Path) -> Vec<PathBuf> {
+    let Ok(deps) = std::fs::read_to_string(path.with_extension("d")) else {
+        return Vec::new();
+    };
+    deps.lines()
+        .filter_map(|line| line.split_once(": "))
+        .flat_map(|(_, deps)| deps.split_whitespace())
+        .map(|dep| workspace_root.join(dep))
+        .collect()
+}
+
+impl Cache {
+    /// Load cache from a target directory, missing or broken cache is treated as empty
+    #[must_use]
+    pub fn load(target_dir: &Path) -> Self {
+        let path = target_dir.join(CACHE_FILE);
+        let entries = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        Self { path, entries }
+    }
+
+    /// Generated file for `key` if it is still present, fresh or not
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&Path> {
+        let path = &self.entries.get(key)?.path;
+        mtime(path).map(|_| path.as_path())
+    }
+
+    /// Generated file for `key`, as long as it is still fresh
+    ///
+    /// `roots` are directories with sources that file depends on: workspace packages
+    #[must_use]
+    pub fn lookup<'a>(
+        &self,
+        key: &str,
+        workspace_root: &Path,
+        roots: impl IntoIterator<Item = &'a Path>,
+    ) -> Option<&Path> {
+        let Entry { path, built: time } = self.entries.get(key)?;
+        mtime(path)?;
+        let time = *time;
+        if dep_info_sources(path, workspace_root)
+            .iter()
+            .any(|src| mtime(src).is_none_or(|m| m > time))
+        {
+            return None;
+        }
+        if roots.into_iter().any(|root| newer_sources(root, time)) {
+            return None;
+        }
+        Some(path)
+    }
+
+    /// Remember a generated file, failing to save the cache is not an error
+    pub fn store(&mut self, key: String, path: PathBuf, built: SystemTime) {
+        self.entries.insert(key, Entry { path, built });
+        if let Ok(bytes) = serde_json::to_vec_pretty(&self.entries) {
+            let _ = std::fs::write(&self.path, bytes);
+        }
+    }
+}
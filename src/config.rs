@@ -0,0 +1,307 @@
+//! Default options taken from `[package.metadata.show-asm]` or `[workspace.metadata.show-asm]`
+//!
+//! Every key is a long option name, values are `true` for flags, strings or numbers for
+//! arguments and arrays for options that can be repeated:
+//!
+//! ```toml
+//! [workspace.metadata.show-asm]
+//! rust = true
+//! release = true
+//! features = ["simd"]
+//! syntax = "att"
+//! ```
+//!
+//! `syntax` takes a syntax option name, same as `CARGO_ASM_SYNTAX`.
+//!
+//! `CARGO_ASM_SYNTAX` picks the output syntax by its option name, such as `att` or `llvm`, and
+//! `CARGO_ASM_FORMAT` takes comma separated option names, with values after `=`: `rust,context=1`.
+//! They take priority over the config.
+//!
+//! Defaults are added to the command line one by one, anything that conflicts with options
+//! given explicitly is dropped so command line and environment variables take priority.
+use crate::{edit_distance, esafeprintln, opts::Options};
+use cargo_metadata::Metadata;
+use serde_json::Value;
+use std::ffi::OsString;
+
+const SECTION: &str = "show-asm";
+
+/// Options that can also be set with an environment variable, env takes priority over config
+const ENV_OPTIONS: &[(&str, &[&str])] = &[
+    ("CARGO_SHOW_ASM_PROFILE", &["release", "dev", "profile"]),
+    ("CARGO_TARGET_DIR", &["target-dir"]),
+];
+
+/// Options that make no sense in config, by the time it is read they are already used
+const IGNORED: &[&str] = &["manifest-path"];
+
+/// Options that pick the output syntax, any of them can be used as a value for `syntax`
+const SYNTAXES: &[&str] = &[
+    "intel",
+    "asm",
+    "att",
+    "llvm",
+    "llvm-input",
+    "mir",
+    "wasm",
+    "mca-intel",
+    "mca",
+    "mca-att",
+    "objdump",
+];
+
+/// Command line arguments equivalent to a single config entry
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    /// Config section or environment variable the entry comes from
+    origin: String,
+    key: String,
+    args: Vec<String>,
+}
+
+fn to_entry(place: &'static str, key: &str, value: &Value) -> Result<Option<Entry>, &'static str> {
+    fn scalar(value: &Value) -> Option<String> {
+        match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+
+    if key.starts_with('-') || key.is_empty() {
+        return Err("keys should be long option names without leading dashes");
+    }
+    let name = format!("--{key}");
+    let args = match value {
+        Value::Bool(true) => vec![name],
+        Value::Bool(false) => return Ok(None),
+        Value::String(_) | Value::Number(_) => vec![name, scalar(value).unwrap_or_default()],
+        Value::Array(items) => {
+            let mut args = Vec::new();
+            for item in items {
+                args.push(name.clone());
+                args.push(scalar(item).ok_or("arrays can only contain strings or numbers")?);
+            }
+            args
+        }
+        Value::Null | Value::Object(_) => {
+            return Err("values should be booleans, strings, numbers or arrays")
+        }
+    };
+    Ok(Some(Entry {
+        origin: format!("[{place}.{SECTION}]"),
+        key: key.to_owned(),
+        args,
+    }))
+}
+
+/// Entry for `syntax = "att"`, typos get a suggestion
+fn syntax_entry(place: &'static str, value: &Value) -> Result<Entry, String> {
+    let Some(syntax) = value.as_str() else {
+        return Err("expected a syntax name such as \"att\"".to_owned());
+    };
+    if !SYNTAXES.contains(&syntax) {
+        let closest = SYNTAXES
+            .iter()
+            .map(|known| (edit_distance(syntax, known), known))
+            .filter(|(distance, _)| *distance <= 2)
+            .min();
+        return Err(match closest {
+            Some((_, known)) => format!("unknown syntax {syntax:?}, did you mean {known:?}?"),
+            None => format!(
+                "unknown syntax {syntax:?}, known syntaxes are: {}",
+                SYNTAXES.join(", ")
+            ),
+        });
+    }
+    Ok(Entry {
+        origin: format!("[{place}.{SECTION}]"),
+        key: "syntax".to_owned(),
+        args: vec![format!("--{syntax}")],
+    })
+}
+
+/// Entries from `CARGO_ASM_SYNTAX` and `CARGO_ASM_FORMAT`, looked up with `var`
+fn env_entries(var: impl Fn(&str) -> Option<String>) -> Vec<Entry> {
+    let mut res = Vec::new();
+    if let Some(syntax) = var("CARGO_ASM_SYNTAX") {
+        let syntax = syntax.trim();
+        if !syntax.is_empty() {
+            res.push(Entry {
+                origin: "CARGO_ASM_SYNTAX".to_owned(),
+                key: syntax.to_owned(),
+                args: vec![format!("--{syntax}")],
+            });
+        }
+    }
+    if let Some(format) = var("CARGO_ASM_FORMAT") {
+        for item in format.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let args = match item.split_once('=') {
+                Some((key, value)) => vec![format!("--{key}"), value.to_owned()],
+                None => vec![format!("--{item}")],
+            };
+            res.push(Entry {
+                origin: "CARGO_ASM_FORMAT".to_owned(),
+                key: item.to_owned(),
+                args,
+            });
+        }
+    }
+    res
+}
+
+/// Collect config entries, package level settings come before workspace level ones
+fn entries(metadata: &Metadata) -> Vec<Entry> {
+    let sources = [
+        (
+            "package.metadata",
+            metadata.root_package().map(|p| &p.metadata),
+        ),
+        ("workspace.metadata", Some(&metadata.workspace_metadata)),
+    ];
+
+    let mut res = Vec::new();
+    for (place, value) in sources {
+        let Some(section) = value.and_then(|v| v.get(SECTION)) else {
+            continue;
+        };
+        let Some(table) = section.as_object() else {
+            esafeprintln!("Ignoring [{place}.{SECTION}]: expected a table");
+            continue;
+        };
+        for (key, value) in table {
+            if IGNORED.contains(&key.as_str()) {
+                esafeprintln!("Ignoring {key} in [{place}.{SECTION}]: it can't be set from config");
+                continue;
+            }
+            if ENV_OPTIONS
+                .iter()
+                .any(|(var, keys)| keys.contains(&key.as_str()) && std::env::var_os(var).is_some())
+            {
+                continue;
+            }
+            if key == "syntax" {
+                match syntax_entry(place, value) {
+                    Ok(entry) => res.push(entry),
+                    Err(err) => esafeprintln!("Ignoring {key} in [{place}.{SECTION}]: {err}"),
+                }
+                continue;
+            }
+            match to_entry(place, key, value) {
+                Ok(Some(entry)) => res.push(entry),
+                Ok(None) => {}
+                Err(err) => esafeprintln!("Ignoring {key} in [{place}.{SECTION}]: {err}"),
+            }
+        }
+    }
+    res
+}
+
+fn parses(args: &[OsString]) -> bool {
+    crate::opts::options().run_inner(args).is_ok()
+}
+
+/// Reparse command line `args`, without the program name, with defaults from the environment
+/// and the config applied, `args` are updated to include the defaults that were used
+///
+/// Invalid entries are reported and ignored, entries that conflict with the command line
+/// are dropped silently.
+#[must_use]
+pub fn with_defaults(
+    opts: Options,
+    args: &mut Vec<OsString>,
+    metadata: Option<&Metadata>,
+) -> Options {
+    let mut entries = env_entries(|name| std::env::var(name).ok());
+    entries.extend(metadata.map(self::entries).unwrap_or_default());
+    if entries.is_empty() {
+        return opts;
+    }
+
+    // cargo passes subcommand name as the first argument, defaults must go after it
+    let at = usize::from(args.first().is_some_and(|a| a == "asm"));
+    let prefix = args[..at].to_vec();
+
+    let mut applied = Vec::new();
+    for entry in entries {
+        let entry_args = entry.args.iter().map(OsString::from).collect::<Vec<_>>();
+        if !parses(&[prefix.as_slice(), &entry_args].concat()) {
+            esafeprintln!("Ignoring {} in {}: invalid option", entry.key, entry.origin);
+            continue;
+        }
+
+        let mut candidate = args.clone();
+        candidate.splice(at..at, entry_args);
+        if parses(&candidate) {
+            *args = candidate;
+            applied.extend(entry.args);
+        }
+    }
+
+    if applied.is_empty() {
+        return opts;
+    }
+    if opts.format.verbosity > 0 {
+        esafeprintln!(
+            "Using defaults from environment and config: {}",
+            applied.join(" ")
+        );
+    }
+    crate::opts::options().run_inner(&args[..]).unwrap_or(opts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn args(key: &str, value: Value) -> Vec<String> {
+        to_entry("test", key, &value)
+            .unwrap()
+            .map(|e| e.args)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn env_values_to_args() {
+        let args = env_entries(|name| match name {
+            "CARGO_ASM_SYNTAX" => Some("att".to_owned()),
+            "CARGO_ASM_FORMAT" => Some("rust, context=2,".to_owned()),
+            _ => None,
+        })
+        .into_iter()
+        .map(|e| e.args)
+        .collect::<Vec<_>>();
+        assert_eq!(
+            args,
+            [vec!["--att"], vec!["--rust"], vec!["--context", "2"]]
+        );
+    }
+
+    #[test]
+    fn config_values_to_args() {
+        assert_eq!(args("rust", json!(true)), ["--rust"]);
+        assert!(args("rust", json!(false)).is_empty());
+        assert_eq!(args("profile", json!("bench")), ["--profile", "bench"]);
+        assert_eq!(args("context", json!(2)), ["--context", "2"]);
+        assert_eq!(
+            args("features", json!(["a", "b"])),
+            ["--features", "a", "--features", "b"]
+        );
+        assert!(to_entry("test", "features", &json!([true])).is_err());
+        assert!(to_entry("test", "color", &json!({})).is_err());
+        assert!(to_entry("test", "-v", &json!(true)).is_err());
+    }
+
+    #[test]
+    fn syntax_is_validated() {
+        let entry = syntax_entry("test", &json!("att")).unwrap();
+        assert_eq!(entry.args, ["--att"]);
+        let err = syntax_entry("test", &json!("atr")).unwrap_err();
+        assert_eq!(err, "unknown syntax \"atr\", did you mean \"att\"?");
+        assert!(syntax_entry("test", &json!("gibberish"))
+            .unwrap_err()
+            .starts_with("unknown syntax \"gibberish\", known syntaxes are: intel, asm"));
+        assert!(syntax_entry("test", &json!(true)).is_err());
+    }
+}
@@ -1,24 +1,63 @@
-use crate::{color, opts::NameDisplay};
+use crate::{
+    color,
+    opts::{Mangling, NameDisplay},
+    theme,
+};
 use once_cell::sync::Lazy;
-use owo_colors::OwoColorize;
 use regex::{Regex, RegexSet, Replacer};
 use rustc_demangle::Demangle;
 use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU8, Ordering};
 
 #[must_use]
 pub fn name(input: &str) -> Option<String> {
     Some(format!("{:#?}", demangled(input)?))
 }
 
+static MANGLING: AtomicU8 = AtomicU8::new(Mangling::Auto as u8);
+
+/// Pick which mangling schemes get demangled, symbols using other schemes are kept as is
+pub fn set_mangling(mangling: Mangling) {
+    MANGLING.store(mangling as u8, Ordering::Relaxed);
+}
+
+fn current_mangling() -> Mangling {
+    match MANGLING.load(Ordering::Relaxed) {
+        x if x == Mangling::Legacy as u8 => Mangling::Legacy,
+        x if x == Mangling::V0 as u8 => Mangling::V0,
+        _ => Mangling::Auto,
+    }
+}
+
+/// Check if symbol looks like it uses one of the schemes allowed by `mangling`
+fn uses_scheme(mangling: Mangling, input: &str) -> bool {
+    // MacOS adds an extra underscore in front
+    let bare = input.trim_start_matches('_');
+    match mangling {
+        Mangling::Auto => true,
+        Mangling::Legacy => bare.starts_with("ZN"),
+        Mangling::V0 => bare.starts_with('R'),
+    }
+}
+
+/// Demangle a symbol, as long as it uses one of the schemes picked with [`set_mangling`]
+fn try_demangle(input: &str) -> Option<Demangle<'_>> {
+    if uses_scheme(current_mangling(), input) {
+        rustc_demangle::try_demangle(input).ok()
+    } else {
+        None
+    }
+}
+
 #[must_use]
-pub fn demangled(input: &str) -> Option<Demangle> {
-    let name = if input.starts_with("__") {
+pub fn demangled(input: &str) -> Option<Demangle<'_>> {
+    if input.starts_with("__") {
         #[allow(clippy::string_slice)]
-        rustc_demangle::try_demangle(&input[1..]).ok()?
+        try_demangle(&input[1..])
     } else {
-        rustc_demangle::try_demangle(input).ok()?
-    };
-    Some(name)
+        try_demangle(input)
+    }
 }
 
 const GLOBAL_LABELS_REGEX: &str = r"\b_?(_[a-zA-Z0-9_$\.]+)";
@@ -55,6 +94,15 @@ static LABEL_KINDS: Lazy<RegexSet> = Lazy::new(|| {
 static COMMENT_ARGS: Lazy<Regex> =
     Lazy::new(|| regex::Regex::new(r"(?:\s|^)(#.+)").expect("regexp should be valid"));
 
+// x86, ARM and AArch64 general purpose and vector registers, with AT&T `%` prefix if present
+static REGISTERS: Lazy<Regex> = Lazy::new(|| {
+    regex::Regex::new(concat!(
+        r"%?\b(?:[re]?(?:[abcd]x|[sd]i|[sb]p|ip)|[abcd][lh]|[sd]il|[sb]pl|r(?:[89]|1[0-5])[dwb]?",
+        r"|[xyz]mm(?:[12]?\d|3[01])|k[0-7]|[xwbhsdqv](?:[12]?\d|3[01])|[wx]zr|sp|lr|fp|pc)\b"
+    ))
+    .expect("regexp should be valid")
+});
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LabelKind {
     Global,
@@ -63,7 +111,7 @@ pub enum LabelKind {
     Unknown,
 }
 
-pub fn local_labels(input: &str) -> regex::Matches {
+pub fn local_labels(input: &str) -> regex::Matches<'_, '_> {
     LOCAL_LABELS.find_iter(input)
 }
 
@@ -81,10 +129,22 @@ struct LabelColorizer;
 impl Replacer for LabelColorizer {
     fn replace_append(&mut self, caps: &regex::Captures<'_>, dst: &mut String) {
         use std::fmt::Write;
-        write!(dst, "{}", color!(&caps[0], OwoColorize::bright_yellow)).unwrap();
+        write!(dst, "{}", color!(&caps[0], theme::label)).unwrap();
     }
 }
 
+/// Replace local labels found in `names` with their new names
+pub fn rename_local_labels<'a>(input: &'a str, names: &BTreeMap<&str, String>) -> Cow<'a, str> {
+    LOCAL_LABELS.replace_all(input, |caps: &regex::Captures| {
+        let label = &caps[1];
+        let prefix = &caps[0][..caps[0].len() - label.len()];
+        match names.get(label) {
+            Some(name) => format!("{prefix}{name}"),
+            None => caps[0].to_owned(),
+        }
+    })
+}
+
 pub fn color_local_labels(input: &str) -> Cow<'_, str> {
     LOCAL_LABELS.replace_all(input, LabelColorizer)
 }
@@ -93,31 +153,96 @@ struct CommentColorizer;
 impl Replacer for CommentColorizer {
     fn replace_append(&mut self, caps: &regex::Captures<'_>, dst: &mut String) {
         use std::fmt::Write;
-        write!(dst, "{}", color!(&caps[0], OwoColorize::blue)).unwrap();
+        write!(dst, "{}", color!(&caps[0], theme::comment)).unwrap();
+    }
+}
+
+struct RegisterColorizer;
+impl Replacer for RegisterColorizer {
+    fn replace_append(&mut self, caps: &regex::Captures<'_>, dst: &mut String) {
+        use std::fmt::Write;
+        write!(dst, "{}", color!(&caps[0], theme::register)).unwrap();
     }
 }
 
+pub fn color_registers(input: &str) -> Cow<'_, str> {
+    REGISTERS.replace_all(input, RegisterColorizer)
+}
+
 pub fn color_comment(input: &str) -> Cow<'_, str> {
     COMMENT_ARGS.replace_all(input, CommentColorizer)
 }
 
+/// Remove generic arguments from a demangled name: `foo::bar<u8>` becomes `foo::bar`
+///
+/// Qualified paths such as `<T as Trait>::method` keep the type and the trait
+#[must_use]
+pub fn strip_generics(name: &str) -> Cow<'_, str> {
+    if !name.contains('<') {
+        return Cow::Borrowed(name);
+    }
+    let mut res = String::with_capacity(name.len());
+    let mut depth = 0usize;
+    let mut prev = None;
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        if depth > 0 {
+            match c {
+                // `->` in function pointer types is not a closing bracket
+                '-' if chars.peek() == Some(&'>') => {
+                    chars.next();
+                }
+                '<' => depth += 1,
+                '>' => depth -= 1,
+                _ => {}
+            }
+            continue;
+        }
+        if c == '<' && prev.is_some_and(|p: char| p.is_alphanumeric() || p == '_') {
+            depth = 1;
+            continue;
+        }
+        res.push(c);
+        prev = Some(c);
+    }
+    Cow::Owned(res)
+}
+
+/// Demangled name in a requested style, `hashed` is `name` followed by the hash suffix
+#[must_use]
+pub fn styled_name<'a>(
+    name: &'a str,
+    hashed: &'a str,
+    short: bool,
+    keep_hash: bool,
+) -> Cow<'a, str> {
+    match (short, keep_hash) {
+        (false, false) => Cow::Borrowed(name),
+        (false, true) => Cow::Borrowed(hashed),
+        (true, false) => strip_generics(name),
+        (true, true) => {
+            let suffix = hashed.strip_prefix(name).unwrap_or_default();
+            Cow::Owned(format!("{}{suffix}", strip_generics(name)))
+        }
+    }
+}
+
 struct Demangler {
     display: NameDisplay,
+    keep_hash: bool,
 }
 impl Replacer for Demangler {
     fn replace_append(&mut self, cap: &regex::Captures<'_>, dst: &mut std::string::String) {
-        if let Ok(dem) = rustc_demangle::try_demangle(&cap[1]) {
+        if let Some(dem) = try_demangle(&cap[1]) {
             use std::fmt::Write;
-            match self.display {
-                NameDisplay::Full => {
-                    write!(dst, "{:?}", color!(dem, OwoColorize::green)).unwrap();
-                }
-                NameDisplay::Short => {
-                    write!(dst, "{:#?}", color!(dem, OwoColorize::green)).unwrap();
-                }
-                NameDisplay::Mangled => {
-                    write!(dst, "{}", color!(&cap[1], OwoColorize::green)).unwrap();
-                }
+            if self.display == NameDisplay::Mangled {
+                write!(dst, "{}", color!(&cap[1], theme::name)).unwrap();
+            } else {
+                let name = format!("{dem:#?}");
+                let hashed = format!("{dem:?}");
+                let short = self.display == NameDisplay::Short;
+                let styled = styled_name(&name, &hashed, short, self.keep_hash);
+                write!(dst, "{}", color!(styled, theme::name)).unwrap();
             }
         } else {
             dst.push_str(&cap[0]);
@@ -126,8 +251,8 @@ impl Replacer for Demangler {
 }
 
 #[must_use]
-pub fn contents(input: &str, display: NameDisplay) -> Cow<'_, str> {
-    GLOBAL_LABELS.replace_all(input, Demangler { display })
+pub fn contents(input: &str, display: NameDisplay, keep_hash: bool) -> Cow<'_, str> {
+    GLOBAL_LABELS.replace_all(input, Demangler { display, keep_hash })
 }
 
 #[must_use]
@@ -139,9 +264,9 @@ pub fn global_reference(input: &str) -> Option<&str> {
 mod test {
     use owo_colors::set_override;
 
-    use crate::opts::NameDisplay;
+    use crate::opts::{Mangling, NameDisplay};
 
-    use super::{contents, name};
+    use super::{contents, name, rename_local_labels, strip_generics, styled_name, uses_scheme};
     const MAC: &str =
         "__ZN58_$LT$nom..error..ErrorKind$u20$as$u20$core..fmt..Debug$GT$3fmt17hb98704099c11c31fE";
     const LINUX: &str =
@@ -162,9 +287,9 @@ mod test {
     #[test]
     fn linux_no_demangle_call() {
         set_override(true);
-        let x = contents(CALL_L, NameDisplay::Mangled);
+        let x = contents(CALL_L, NameDisplay::Mangled, false);
         assert_eq!(
-            "[rip + \u{1b}[32m_ZN58_$LT$nom..error..ErrorKind$u20$as$u20$core..fmt..Debug$GT$3fmt17hb98704099c11c31fE\u{1b}[39m]",
+            "[rip + \u{1b}[32m_ZN58_$LT$nom..error..ErrorKind$u20$as$u20$core..fmt..Debug$GT$3fmt17hb98704099c11c31fE\u{1b}[0m]",
             x
         );
     }
@@ -172,9 +297,9 @@ mod test {
     #[test]
     fn linux_demangle_call() {
         set_override(true);
-        let x = contents(CALL_L, NameDisplay::Short);
+        let x = contents(CALL_L, NameDisplay::Full, false);
         assert_eq!(
-            "[rip + \u{1b}[32m<nom::error::ErrorKind as core::fmt::Debug>::fmt\u{1b}[39m]",
+            "[rip + \u{1b}[32m<nom::error::ErrorKind as core::fmt::Debug>::fmt\u{1b}[0m]",
             x
         );
     }
@@ -182,9 +307,9 @@ mod test {
     #[test]
     fn mac_demangle_call() {
         set_override(true);
-        let x = contents(CALL_M, NameDisplay::Short);
+        let x = contents(CALL_M, NameDisplay::Full, false);
         assert_eq!(
-            "[rip + \u{1b}[32m<nom::error::ErrorKind as core::fmt::Debug>::fmt\u{1b}[39m]",
+            "[rip + \u{1b}[32m<nom::error::ErrorKind as core::fmt::Debug>::fmt\u{1b}[0m]",
             x
         );
     }
@@ -192,10 +317,63 @@ mod test {
     #[test]
     fn mac_demangle_call2() {
         set_override(true);
-        let x = contents(CALL_M, NameDisplay::Full);
+        let x = contents(CALL_M, NameDisplay::Full, true);
         assert_eq!(
-            "[rip + \u{1b}[32m<nom::error::ErrorKind as core::fmt::Debug>::fmt::hb98704099c11c31f\u{1b}[39m]",
+            "[rip + \u{1b}[32m<nom::error::ErrorKind as core::fmt::Debug>::fmt::hb98704099c11c31f\u{1b}[0m]",
             x
         );
     }
+
+    #[test]
+    fn generics_are_stripped() {
+        assert_eq!(
+            strip_generics("core::ptr::drop_in_place<alloc::vec::Vec<u8>>"),
+            "core::ptr::drop_in_place"
+        );
+        assert_eq!(
+            strip_generics("<alloc::vec::Vec<T> as core::ops::Drop>::drop"),
+            "<alloc::vec::Vec as core::ops::Drop>::drop"
+        );
+        assert_eq!(strip_generics("foo::bar<fn() -> u8>::baz"), "foo::bar::baz");
+        assert_eq!(strip_generics("foo::bar"), "foo::bar");
+    }
+
+    #[test]
+    fn hash_is_independent_from_generics() {
+        let name = "foo::bar<u8>";
+        let hashed = "foo::bar<u8>::h0123456789abcdef";
+        assert_eq!(styled_name(name, hashed, false, false), name);
+        assert_eq!(styled_name(name, hashed, false, true), hashed);
+        assert_eq!(styled_name(name, hashed, true, false), "foo::bar");
+        assert_eq!(
+            styled_name(name, hashed, true, true),
+            "foo::bar::h0123456789abcdef"
+        );
+    }
+
+    #[test]
+    fn mangling_scheme_is_detected() {
+        let legacy = "_ZN4core3fmt9Formatter9write_fmt17h6a5b5c1a0dd1e7bfE";
+        let v0 = "_RNvCs1234_7mycrate3foo";
+        assert!(uses_scheme(Mangling::Auto, legacy));
+        assert!(uses_scheme(Mangling::Auto, v0));
+        assert!(uses_scheme(Mangling::Legacy, legacy));
+        assert!(!uses_scheme(Mangling::Legacy, v0));
+        assert!(uses_scheme(Mangling::V0, v0));
+        assert!(!uses_scheme(Mangling::V0, legacy));
+        assert!(uses_scheme(Mangling::Legacy, &format!("_{legacy}")));
+    }
+
+    #[test]
+    fn local_labels_are_renamed() {
+        let names = [(".LBB3_2", ".L1".to_owned()), (".Ltmp7", ".L2".to_owned())]
+            .into_iter()
+            .collect();
+        assert_eq!(rename_local_labels("jne .LBB3_2", &names), "jne .L1");
+        assert_eq!(
+            rename_local_labels("[rip + .Ltmp7-.LBB3_2]", &names),
+            "[rip + .L2-.L1]"
+        );
+        assert_eq!(rename_local_labels("jmp .LBB3_20", &names), "jmp .LBB3_20");
+    }
 }
@@ -0,0 +1,203 @@
+//! Line based diff between two renderings of a function
+use crate::{color, safeprintln};
+use owo_colors::OwoColorize;
+use regex::Regex;
+use std::collections::BTreeMap;
+
+/// Replace local labels such as `.LBB3_2` with names numbered in order of appearance
+///
+/// Numbering in local labels depends on the position of the function in a file, so the same
+/// code compiled in a slightly different context ends up with different labels.
+#[must_use]
+pub fn normalize(text: &str) -> Vec<String> {
+    let label = Regex::new(r"\.L[A-Za-z_$]*\d[\w$.]*").expect("valid regex");
+    let mut seen = BTreeMap::new();
+    text.lines()
+        .map(|line| {
+            label
+                .replace_all(line, |caps: &regex::Captures| {
+                    let name = caps[0].to_owned();
+                    let next = seen.len();
+                    let id = *seen.entry(name.clone()).or_insert(next);
+                    let prefix = name[2..]
+                        .split(|c: char| c.is_ascii_digit())
+                        .next()
+                        .unwrap_or_default();
+                    format!(".L{prefix}_{id}")
+                })
+                .into_owned()
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Same(usize, usize),
+    Del(usize),
+    Ins(usize),
+}
+
+/// Shortest edit script from `a` to `b`, Myers' algorithm
+fn edits<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Op> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = n + m;
+    let off = max as usize;
+    let mut v = vec![0isize; 2 * off + 2];
+    let ix = |k: isize| (k + max) as usize;
+    let mut trace = Vec::new();
+
+    'outer: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[ix(k - 1)] < v[ix(k + 1)]) {
+                v[ix(k + 1)]
+            } else {
+                v[ix(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[ix(k)] = x;
+            if x >= n && y >= m {
+                break 'outer;
+            }
+        }
+    }
+
+    let mut res = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && v[ix(k - 1)] < v[ix(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[ix(prev_k)];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            res.push(Op::Same(x as usize - 1, y as usize - 1));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == prev_x {
+                res.push(Op::Ins(y as usize - 1));
+            } else {
+                res.push(Op::Del(x as usize - 1));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    res.reverse();
+    res
+}
+
+/// Print unified diff between `old` and `new` with `context` unchanged lines around changes
+///
+/// Returns `false` if there are no differences and nothing was printed
+pub fn print_unified(old_name: &str, old: &[String], new_name: &str, new: &[String]) -> bool {
+    const CONTEXT: usize = 3;
+    let ops = edits(old, new);
+    let changed = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, Op::Same(..)))
+        .map(|(ix, _)| ix)
+        .collect::<Vec<_>>();
+    if changed.is_empty() {
+        return false;
+    }
+
+    safeprintln!("{}", color!(format!("--- {old_name}"), OwoColorize::red));
+    safeprintln!("{}", color!(format!("+++ {new_name}"), OwoColorize::green));
+
+    // group changes that are close enough to each other into hunks
+    let mut hunks = Vec::new();
+    let mut start = changed[0];
+    let mut end = changed[0];
+    for &ix in &changed[1..] {
+        if ix - end > 2 * CONTEXT {
+            hunks.push((start, end));
+            start = ix;
+        }
+        end = ix;
+    }
+    hunks.push((start, end));
+
+    for (start, end) in hunks {
+        let hunk = &ops[start.saturating_sub(CONTEXT)..(end + CONTEXT + 1).min(ops.len())];
+        let (mut old_start, mut new_start) = (None, None);
+        let (mut old_len, mut new_len) = (0, 0);
+        for op in hunk {
+            match *op {
+                Op::Same(o, n) => {
+                    old_start.get_or_insert(o);
+                    new_start.get_or_insert(n);
+                    old_len += 1;
+                    new_len += 1;
+                }
+                Op::Del(o) => {
+                    old_start.get_or_insert(o);
+                    old_len += 1;
+                }
+                Op::Ins(n) => {
+                    new_start.get_or_insert(n);
+                    new_len += 1;
+                }
+            }
+        }
+        let header = format!(
+            "@@ -{},{old_len} +{},{new_len} @@",
+            old_start.map_or(0, |s| s + 1),
+            new_start.map_or(0, |s| s + 1)
+        );
+        safeprintln!("{}", color!(header, OwoColorize::cyan));
+        for op in hunk {
+            match *op {
+                Op::Same(o, _) => safeprintln!(" {}", old[o]),
+                Op::Del(o) => safeprintln!("{}", color!(format!("-{}", old[o]), OwoColorize::red)),
+                Op::Ins(n) => {
+                    safeprintln!("{}", color!(format!("+{}", new[n]), OwoColorize::green));
+                }
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn labels_are_normalized() {
+        let a = normalize("\tjne .LBB3_2\n.LBB3_2:\n\tjmp .Ltmp12");
+        let b = normalize("\tjne .LBB0_7\n.LBB0_7:\n\tjmp .Ltmp1");
+        assert_eq!(a, b);
+        assert_eq!(a[0], "\tjne .LBB_0");
+    }
+
+    #[test]
+    fn edit_script_is_minimal() {
+        let a = ["a", "b", "c", "d"];
+        let b = ["a", "c", "d", "e"];
+        let ops = edits(&a, &b);
+        assert_eq!(
+            ops,
+            [
+                Op::Same(0, 0),
+                Op::Del(1),
+                Op::Same(2, 1),
+                Op::Same(3, 2),
+                Op::Ins(3)
+            ]
+        );
+        assert!(edits::<&str>(&[], &[]).is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+//! Self-contained HTML page for `--html`
+//!
+//! Functions are built from parsed statements, every line gets a class for its kind and rust
+//! source annotations from `--rust` become collapsible sections.
+use std::path::Path;
+
+/// Colors for every line kind, same as the dark terminal theme
+const STYLE: &str = "body { background: #1e1e1e; color: #d4d4d4; margin: 1em; }\n\
+    .code { font-family: monospace; white-space: pre; tab-size: 8; }\n\
+    details { border-left: 2px solid #444; padding-left: 0.5em; }\n\
+    summary { cursor: pointer; }\n\
+    .op { color: #3b8eea; }\n\
+    .label { color: #f5f543; }\n\
+    .comment { color: #2472c8; }\n\
+    .directive { color: #d670d6; }\n\
+    .location { color: #11a8cd; }\n\
+    .source { color: #f14c4c; }\n";
+
+/// Kind of a line that is not an instruction, picks its class
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Label,
+    Directive,
+    Comment,
+    Other,
+}
+
+/// Line of a function on the page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    /// Source location along with the rust code at it, starts a collapsible section
+    Source {
+        location: String,
+        code: Option<String>,
+    },
+    /// Instruction mnemonic and its operands
+    Instruction { op: String, args: String },
+    /// Labels, directives and comments
+    Other { kind: Kind, text: String },
+}
+
+fn escape(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+fn span(class: &str, text: &str, out: &mut String) {
+    out.push_str("<span class=\"");
+    out.push_str(class);
+    out.push_str("\">");
+    escape(text, out);
+    out.push_str("</span>");
+}
+
+/// Heading with the function name followed by its code
+#[must_use]
+pub fn section(title: &str, lines: &[Line]) -> String {
+    let mut out = String::from("<h3>");
+    escape(title, &mut out);
+    out.push_str("</h3>\n<div class=\"code\">");
+    let mut in_section = false;
+    for line in lines {
+        match line {
+            Line::Source { location, code } => {
+                if in_section {
+                    out.push_str("</details>");
+                }
+                out.push_str("<details open><summary>");
+                span("location", &format!("\t\t// {location}"), &mut out);
+                if let Some(code) = code {
+                    out.push('\n');
+                    span("source", &format!("\t\t{code}"), &mut out);
+                }
+                out.push_str("</summary>");
+                in_section = true;
+            }
+            Line::Instruction { op, args } => {
+                out.push('\t');
+                span("op", op, &mut out);
+                if !args.is_empty() {
+                    out.push(' ');
+                    escape(args, &mut out);
+                }
+                out.push('\n');
+            }
+            Line::Other { kind, text } => {
+                let class = match kind {
+                    Kind::Label => "label",
+                    Kind::Directive => "directive",
+                    Kind::Comment => "comment",
+                    Kind::Other => "other",
+                };
+                if *kind != Kind::Label {
+                    out.push('\t');
+                }
+                span(class, text, &mut out);
+                out.push('\n');
+            }
+        }
+    }
+    if in_section {
+        out.push_str("</details>");
+    }
+    out.push_str("</div>\n");
+    out
+}
+
+/// Page with `sections` made by [`section`]
+#[must_use]
+pub fn page(title: &str, sections: &str) -> String {
+    let mut out =
+        String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>");
+    escape(title, &mut out);
+    out.push_str("</title>\n<style>\n");
+    out.push_str(STYLE);
+    out.push_str("</style>\n</head>\n<body>\n");
+    out.push_str(sections);
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+/// Open a file with the default application, errors are reported but otherwise ignored
+pub fn open(path: &Path) {
+    let mut cmd = if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else if cfg!(windows) {
+        let mut cmd = std::process::Command::new("cmd");
+        cmd.args(["/C", "start", ""]);
+        cmd
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    if let Err(err) = cmd.arg(path).status() {
+        crate::esafeprintln!("Failed to open {}: {err}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lines_get_classes() {
+        let lines = [
+            Line::Other {
+                kind: Kind::Label,
+                text: "foo:".to_owned(),
+            },
+            Line::Instruction {
+                op: "mov".to_owned(),
+                args: "rax, <x>".to_owned(),
+            },
+        ];
+        assert_eq!(
+            section("foo", &lines),
+            "<h3>foo</h3>\n<div class=\"code\"><span class=\"label\">foo:</span>\n\
+            \t<span class=\"op\">mov</span> rax, &lt;x&gt;\n</div>\n"
+        );
+    }
+
+    #[test]
+    fn sources_are_collapsible() {
+        let lines = [
+            Line::Source {
+                location: "src/lib.rs : 1".to_owned(),
+                code: Some("let x = 1;".to_owned()),
+            },
+            Line::Instruction {
+                op: "ret".to_owned(),
+                args: String::new(),
+            },
+        ];
+        assert!(section("foo", &lines).contains(
+            "<details open><summary><span class=\"location\">\t\t// src/lib.rs : 1</span>\n\
+            <span class=\"source\">\t\tlet x = 1;</span></summary>\t<span class=\"op\">ret</span>\n\
+            </details>"
+        ));
+    }
+}
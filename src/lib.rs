@@ -1,18 +1,30 @@
 #![doc = include_str!("../README.md")]
 
 use std::{
+    borrow::Cow,
     collections::{BTreeMap, BTreeSet},
+    io::Write,
     ops::Range,
+    sync::{Mutex, PoisonError},
 };
 
-use opts::{Format, NameDisplay, ToDump};
+use opts::{Format, NameDisplay, Nth, Options, OutputStyle, Syntax, ToDump};
 pub mod asm;
+pub mod build;
+pub mod cache;
 pub mod cached_lines;
+pub mod config;
 pub mod demangle;
+pub mod diff;
+pub mod html;
 pub mod llvm;
 pub mod mca;
 pub mod mir;
+pub mod objdump;
 pub mod opts;
+pub mod perf;
+pub mod remarks;
+pub mod theme;
 
 #[macro_export]
 macro_rules! color {
@@ -21,6 +33,249 @@ macro_rules! color {
     };
 }
 
+static OUTPUT: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+
+/// Redirect everything printed with `safeprint[ln]!` to `out` instead of stdout
+pub fn set_output(out: Box<dyn Write + Send>) {
+    *OUTPUT.lock().unwrap_or_else(PoisonError::into_inner) = Some(out);
+}
+
+static PAGER: Mutex<Option<std::process::Child>> = Mutex::new(None);
+
+/// Send everything printed with `safeprint[ln]!` to a pager process
+pub fn set_pager(mut pager: std::process::Child) {
+    if let Some(stdin) = pager.stdin.take() {
+        set_output(Box::new(stdin));
+        *PAGER.lock().unwrap_or_else(PoisonError::into_inner) = Some(pager);
+    }
+}
+
+static PIPE_THROUGH: Mutex<Option<std::process::Child>> = Mutex::new(None);
+
+/// Send everything printed with `safeprint[ln]!` to a `--pipe-through` command, the command
+/// failing is reported once the output is finished
+pub fn set_pipe_through(mut command: std::process::Child) {
+    if let Some(stdin) = command.stdin.take() {
+        set_output(Box::new(stdin));
+        *PIPE_THROUGH.lock().unwrap_or_else(PoisonError::into_inner) = Some(command);
+    }
+}
+
+static CAPTURE: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+/// Collect everything printed with `safeprint[ln]!` in memory until [`take_capture`] is called
+pub fn start_capture() {
+    *CAPTURE.lock().unwrap_or_else(PoisonError::into_inner) = Some(Vec::new());
+}
+
+/// Stop capturing the output and get what was printed so far
+#[must_use]
+pub fn take_capture() -> Vec<u8> {
+    CAPTURE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+        .unwrap_or_default()
+}
+
+/// Close redirected output and wait for the pager or `--pipe-through` command to finish,
+/// if there's one
+///
+/// Captured output that was never taken, for example because of an early exit, is printed first
+///
+/// # Errors
+/// If the `--pipe-through` command fails, the error carries [`ExitCode::Failure`]
+pub fn finish_output() -> anyhow::Result<()> {
+    let captured = take_capture();
+    if !captured.is_empty() {
+        let _ = Stdout.write_all(&captured);
+    }
+    drop(OUTPUT.lock().unwrap_or_else(PoisonError::into_inner).take());
+    if let Some(mut pager) = PAGER.lock().unwrap_or_else(PoisonError::into_inner).take() {
+        let _ = pager.wait();
+    }
+    if let Some(mut command) = PIPE_THROUGH
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+    {
+        match command.wait() {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                return Err(
+                    ExitCode::Failure.error(format!("--pipe-through command failed with {status}"))
+                );
+            }
+            Err(err) => {
+                return Err(
+                    ExitCode::Failure.error(format!("--pipe-through command failed: {err}"))
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Exit codes scripts can rely on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Everything worked, `--compare` found no differences
+    Success,
+    /// Anything not covered by other codes went wrong, `--compare` found differences
+    Failure,
+    /// Cargo failed to build the crate
+    BuildFailed,
+    /// Nothing matches the requested function, package or target
+    NoMatch,
+    /// Several functions, packages or targets match and one of them needs to be picked
+    Ambiguous,
+}
+
+impl ExitCode {
+    #[must_use]
+    pub const fn code(self) -> i32 {
+        match self {
+            ExitCode::Success => 0,
+            ExitCode::Failure => 1,
+            ExitCode::BuildFailed => 2,
+            ExitCode::NoMatch => 3,
+            ExitCode::Ambiguous => 4,
+        }
+    }
+
+    /// Error with a message for the user that makes the process exit with this code
+    pub fn error<M>(self, msg: M) -> anyhow::Error
+    where
+        M: std::fmt::Display + Send + Sync + 'static,
+    {
+        anyhow::Error::new(self).context(msg)
+    }
+
+    /// Exit code for an error, [`ExitCode::Failure`] unless it was made with [`ExitCode::error`]
+    #[must_use]
+    pub fn of(err: &anyhow::Error) -> Self {
+        err.chain()
+            .find_map(|e| e.downcast_ref::<ExitCode>())
+            .copied()
+            .unwrap_or(ExitCode::Failure)
+    }
+}
+
+impl std::fmt::Display for ExitCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "exit code {}", self.code())
+    }
+}
+
+impl std::error::Error for ExitCode {}
+
+/// Exit the process, but let the pager to finish first, a failed `--pipe-through` command
+/// turns the exit code into [`ExitCode::Failure`]
+pub fn exit(code: ExitCode) -> ! {
+    let code = match finish_output() {
+        Ok(()) => code,
+        Err(err) => {
+            esafeprintln!("Error: {err}");
+            ExitCode::of(&err)
+        }
+    };
+    std::process::exit(code.code());
+}
+
+/// Lines printed so far and how many are allowed, see [`limit_lines`]
+struct LineLimit {
+    max: usize,
+    printed: usize,
+    hidden: usize,
+}
+
+impl LineLimit {
+    /// How many bytes of `buf` fit under the limit, lines past it are counted as hidden
+    fn visible(&mut self, buf: &[u8]) -> usize {
+        let mut visible = 0;
+        for (ix, byte) in buf.iter().enumerate() {
+            if self.printed < self.max {
+                visible = ix + 1;
+                if *byte == b'\n' {
+                    self.printed += 1;
+                }
+            } else if *byte == b'\n' {
+                self.hidden += 1;
+            }
+        }
+        visible
+    }
+}
+
+static LINE_LIMIT: Mutex<Option<LineLimit>> = Mutex::new(None);
+
+/// Stop printing with `safeprint[ln]!` after `max` lines, `0` removes the limit
+pub fn limit_lines(max: usize) {
+    *LINE_LIMIT.lock().unwrap_or_else(PoisonError::into_inner) = (max > 0).then_some(LineLimit {
+        max,
+        printed: 0,
+        hidden: 0,
+    });
+}
+
+/// Remove the limit set with [`limit_lines`] and get the number of lines that were not printed
+#[must_use]
+pub fn take_hidden_lines() -> usize {
+    LINE_LIMIT
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+        .map_or(0, |limit| limit.hidden)
+}
+
+/// Output stream used by `safeprint[ln]!`, stdout unless redirected with [`set_output`]
+pub struct Stdout;
+
+impl Write for Stdout {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let visible = LINE_LIMIT
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+            .map(|limit| limit.visible(buf));
+        match visible {
+            // write everything at once, partial writes would count the lines again
+            Some(visible) => {
+                Unlimited.write_all(&buf[..visible])?;
+                Ok(buf.len())
+            }
+            None => Unlimited.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Unlimited.flush()
+    }
+}
+
+/// Output stream ignoring [`limit_lines`]
+struct Unlimited;
+
+impl Write for Unlimited {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(captured) = &mut *CAPTURE.lock().unwrap_or_else(PoisonError::into_inner) {
+            captured.extend_from_slice(buf);
+            return Ok(buf.len());
+        }
+        match &mut *OUTPUT.lock().unwrap_or_else(PoisonError::into_inner) {
+            Some(out) => out.write(buf),
+            None => std::io::stdout().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut *OUTPUT.lock().unwrap_or_else(PoisonError::into_inner) {
+            Some(out) => out.flush(),
+            None => std::io::stdout().flush(),
+        }
+    }
+}
+
 /// Safe version of `print[ln]!` macro
 /// By default `print[ln]!` macro panics when print fails. Usually print fails when output
 /// stream is disconnected, for purposes of this application disconnected stream means output
@@ -31,8 +286,8 @@ macro_rules! color {
 macro_rules! safeprintln {
     ($($x:expr),* $(,)?) => {{
         use std::io::Write;
-        if writeln!(std::io::stdout(), $($x),*).is_err() {
-            std::process::exit(0);
+        if writeln!($crate::Stdout, $($x),*).is_err() {
+            $crate::exit($crate::ExitCode::Success);
         }
     }};
 }
@@ -41,8 +296,8 @@ macro_rules! safeprintln {
 macro_rules! safeprint {
     ($($x:expr),* $(,)?) => {{
         use std::io::Write;
-        if write!(std::io::stdout(), $($x),*).is_err() {
-            std::process::exit(0);
+        if write!($crate::Stdout, $($x),*).is_err() {
+            $crate::exit($crate::ExitCode::Success);
         }
     }};
 }
@@ -83,29 +338,61 @@ pub struct Item {
     pub non_blank_len: usize,
     /// mangled name
     pub mangled_name: String,
+    /// number of other items with identical code, set with `--dedup`
+    pub duplicates: usize,
+}
+
+impl Item {
+    /// Name of the item as it should be presented to the user
+    #[must_use]
+    pub fn display_name(&self, name_display: NameDisplay, keep_hash: bool) -> Cow<'_, str> {
+        match name_display {
+            NameDisplay::Mangled => Cow::from(&self.mangled_name),
+            NameDisplay::Full | NameDisplay::Short => demangle::styled_name(
+                &self.name,
+                &self.hashed,
+                name_display == NameDisplay::Short,
+                keep_hash,
+            ),
+        }
+    }
+
+    /// Name of the item used for matching against user requests: demangled name without the
+    /// hash suffix, regardless of how the names are displayed
+    #[must_use]
+    pub fn search_name(&self) -> &str {
+        &self.name
+    }
 }
 
+/// Print the names to pick from and get the error to stop with
+#[must_use]
 pub fn suggest_name<'a>(
     search: &str,
-    name_display: &NameDisplay,
+    fmt: &Format,
     items: impl IntoIterator<Item = &'a Item>,
-) {
+) -> anyhow::Error {
     let mut count = 0usize;
-    let names: BTreeMap<&String, Vec<usize>> =
+    // names along with the index of the first item, lengths of all the items and the number
+    // of hidden identical copies
+    let names: BTreeMap<Cow<str>, (usize, Vec<usize>, usize)> =
         items.into_iter().fold(BTreeMap::new(), |mut m, item| {
+            let entry = m
+                .entry(item.display_name(fmt.name_display, fmt.keep_hash))
+                .or_insert_with(|| (count, Vec::new(), 0));
+            entry.1.push(item.non_blank_len);
+            entry.2 += item.duplicates;
             count += 1;
-            let entry = match name_display {
-                NameDisplay::Full => &item.hashed,
-                NameDisplay::Short => &item.name,
-                NameDisplay::Mangled => &item.mangled_name,
-            };
-            m.entry(entry).or_default().push(item.non_blank_len);
             m
         });
 
     if names.is_empty() {
         if search.is_empty() {
             safeprintln!("This target defines no functions (or cargo-show-asm can't find them)");
+            safeprintln!(
+                "Functions that are never used are removed as dead code, make them pub or mark \
+                with #[no_mangle] to keep them"
+            );
         } else {
             safeprintln!("No matching functions, try relaxing your search request");
         }
@@ -118,59 +405,427 @@ pub fn suggest_name<'a>(
     #[allow(clippy::cast_precision_loss)]
     let width = (count as f64).log10().ceil() as usize;
 
-    let mut ix = 0;
-    for (name, lens) in &names {
+    for (name, (ix, lens, duplicates)) in &names {
         safeprintln!(
-            "{ix:width$} {:?} {:?}",
-            color!(name, owo_colors::OwoColorize::green),
+            "{ix:width$} {:?} {:?}{}",
+            color!(name, theme::name),
             color!(lens, owo_colors::OwoColorize::cyan),
+            DuplicatesNote(*duplicates),
+        );
+    }
+
+    if names.is_empty() {
+        ExitCode::NoMatch.error("No matching functions")
+    } else if search.is_empty() {
+        ExitCode::Ambiguous.error("No function picked")
+    } else {
+        ExitCode::Ambiguous.error(format!("{search:?} matches several functions"))
+    }
+}
+
+/// Don't look for similar names past this many items, it gets slow and unlikely to help
+const MAX_SIMILAR_CANDIDATES: usize = 20_000;
+/// Only compare this many characters of each name segment
+const MAX_SIMILAR_LEN: usize = 128;
+
+/// Levenshtein distance between two strings, in chars
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diag
+            } else {
+                1 + diag.min(above).min(row[j])
+            };
+            diag = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// How far away `name` is from `search`, comparing against every `::` separated segment
+/// as well as the whole name
+fn name_distance(search: &str, name: &str) -> usize {
+    let truncate = |s: &str| s.chars().take(MAX_SIMILAR_LEN).collect::<String>();
+    let search = truncate(&search.to_lowercase());
+    let name = name.to_lowercase();
+    std::iter::once(name.as_str())
+        .chain(name.split("::"))
+        .map(|part| edit_distance(&search, &truncate(part)))
+        .min()
+        .unwrap_or(usize::MAX)
+}
+
+/// Print up to 5 items with names similar to `search`, along with their index
+pub fn suggest_similar<'a>(search: &str, fmt: &Format, items: impl IntoIterator<Item = &'a Item>) {
+    let limit = search.chars().count().max(3) / 3;
+    let mut similar = items
+        .into_iter()
+        .take(MAX_SIMILAR_CANDIDATES)
+        .enumerate()
+        .map(|(ix, item)| (name_distance(search, item.search_name()), ix, item))
+        .filter(|(distance, _, _)| *distance <= limit)
+        .collect::<Vec<_>>();
+    similar.sort_by_key(|(distance, ix, _)| (*distance, *ix));
+    if similar.is_empty() {
+        return;
+    }
+    safeprintln!("Did you mean one of those? Pass the name or just the index to pick one");
+    for (_, ix, item) in similar.iter().take(5) {
+        safeprintln!(
+            "{ix} {}",
+            color!(
+                item.display_name(fmt.name_display, fmt.keep_hash),
+                theme::name
+            )
         );
-        ix += lens.len();
     }
+}
+
+/// List all the items in a stable order, one per line, along with their index
+pub fn list_items<'a>(fmt: &Format, items: impl ExactSizeIterator<Item = &'a Item>) {
+    if fmt.style == OutputStyle::Jsonl {
+        for (index, item) in items.enumerate() {
+            let item = JsonItem {
+                index,
+                name: item.display_name(fmt.name_display, fmt.keep_hash),
+                mangled_name: &item.mangled_name,
+                duplicates: item.duplicates,
+            };
+            print_json_line(&item);
+        }
+        return;
+    }
+    #[allow(clippy::cast_sign_loss)]
+    #[allow(clippy::cast_precision_loss)]
+    let width = (items.len() as f64).log10().ceil() as usize;
+    for (ix, item) in items.enumerate() {
+        safeprintln!(
+            "{ix:width$} {}{}",
+            color!(
+                item.display_name(fmt.name_display, fmt.keep_hash),
+                theme::name
+            ),
+            DuplicatesNote(item.duplicates),
+        );
+    }
+}
 
-    std::process::exit(1);
+#[derive(serde::Serialize)]
+struct JsonItem<'a> {
+    index: usize,
+    name: Cow<'a, str>,
+    mangled_name: &'a str,
+    duplicates: usize,
+}
+
+/// Print a value as a single line of JSON, for `--jsonl`
+fn print_json_line<T: serde::Serialize>(value: &T) {
+    match serde_json::to_string(value) {
+        Ok(json) => safeprintln!("{json}"),
+        Err(err) => esafeprintln!("Failed to serialize: {err}"),
+    }
 }
 
-/// Pick an item to dump based on a goal
+#[derive(serde::Serialize)]
+struct JsonSize<'a> {
+    name: Cow<'a, str>,
+    mangled_name: &'a str,
+    size: usize,
+}
+
+/// Print items sorted by size, largest first, `unit` describes what size counts
+pub fn list_sizes(fmt: &Format, mut sizes: Vec<(&Item, usize)>, unit: &str) {
+    sizes.sort_by(|(a_item, a_size), (b_item, b_size)| b_size.cmp(a_size).then(a_item.cmp(b_item)));
+    if fmt.style == OutputStyle::Json {
+        let sizes = sizes
+            .iter()
+            .map(|(item, size)| JsonSize {
+                name: item.display_name(fmt.name_display, fmt.keep_hash),
+                mangled_name: &item.mangled_name,
+                size: *size,
+            })
+            .collect::<Vec<_>>();
+        match serde_json::to_string_pretty(&sizes) {
+            Ok(json) => safeprintln!("{json}"),
+            Err(err) => esafeprintln!("Failed to serialize sizes: {err}"),
+        }
+        return;
+    }
+    if fmt.style == OutputStyle::Jsonl {
+        for (item, size) in &sizes {
+            print_json_line(&JsonSize {
+                name: item.display_name(fmt.name_display, fmt.keep_hash),
+                mangled_name: &item.mangled_name,
+                size: *size,
+            });
+        }
+        return;
+    }
+    let width = sizes
+        .first()
+        .map_or(0, |(_, size)| size.to_string().len())
+        .max(unit.len());
+    safeprintln!("{unit:>width$} name");
+    for (item, size) in &sizes {
+        safeprintln!(
+            "{size:width$} {}{}",
+            color!(
+                item.display_name(fmt.name_display, fmt.keep_hash),
+                theme::name
+            ),
+            DuplicatesNote(item.duplicates),
+        );
+    }
+}
+
+/// Number of hidden identical copies of an item, printed only if there are any
+struct DuplicatesNote(usize);
+
+impl std::fmt::Display for DuplicatesNote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0 == 0 {
+            return Ok(());
+        }
+        let note = format!(" (+{} identical)", self.0);
+        write!(f, "{}", color!(note, owo_colors::OwoColorize::cyan))
+    }
+}
+
+/// Keep one item out of each group of items with identical code, the rest are only counted
+/// in [`Item::duplicates`]
 ///
-/// Prints suggestions and exits if goal can't be reached or more info is needed
+/// `body` renders the lines of an item. Local labels are normalized and the item's own name is
+/// ignored so the same code generated for different generic arguments is still identical.
 #[must_use]
+pub fn dedup_items(
+    items: &BTreeMap<Item, Range<usize>>,
+    body: impl Fn(Range<usize>) -> String,
+) -> BTreeMap<Item, Range<usize>> {
+    let mut groups = BTreeMap::<Vec<String>, (Item, Range<usize>)>::new();
+    for (item, range) in items {
+        let mut text = body(range.clone());
+        for name in [&item.mangled_name, &item.name] {
+            if !name.is_empty() {
+                text = text.replace(name.as_str(), "");
+            }
+        }
+        groups
+            .entry(diff::normalize(&text))
+            .and_modify(|(first, _)| first.duplicates += 1)
+            .or_insert_with(|| (item.clone(), range.clone()));
+    }
+    groups.into_values().collect()
+}
+
+/// Let user pick one of several matching items, only works when talking to a terminal
+///
+/// Returns `None` if terminal is not available or user gave up on picking
+fn pick_interactively(fmt: &Format, items: &[&Item]) -> Option<usize> {
+    use std::io::{BufRead, IsTerminal};
+
+    if !(std::io::stdin().is_terminal() && std::io::stderr().is_terminal()) {
+        return None;
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    #[allow(clippy::cast_precision_loss)]
+    let width = (items.len() as f64).log10().ceil() as usize;
+    for (ix, item) in items.iter().enumerate() {
+        esafeprintln!(
+            "{ix:width$} {}",
+            color!(
+                item.display_name(fmt.name_display, fmt.keep_hash),
+                theme::name
+            )
+        );
+    }
+
+    let last = items.len() - 1;
+    let mut line = String::new();
+    loop {
+        esafeprint!("Pick a function to show [0-{last}], empty line to quit: ");
+        line.clear();
+        if std::io::stdin().lock().read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        match line.trim() {
+            "" => return None,
+            input => match input.parse::<usize>() {
+                Ok(ix) if ix <= last => return Some(ix),
+                _ => esafeprintln!("Expected a number between 0 and {last}"),
+            },
+        }
+    }
+}
+
+/// Print a list of items, their sizes or their count instead of code if `goal` or `--count`
+/// asks for it, `true` if it did
+///
+/// # Errors
+/// If `--count` finds no matching items or the function name is not a valid regex
+pub fn print_summary(
+    goal: &ToDump,
+    fmt: &Format,
+    items: &BTreeMap<Item, Range<usize>>,
+) -> anyhow::Result<bool> {
+    if fmt.count {
+        let count = match goal {
+            ToDump::Function {
+                function,
+                regex,
+                ignore_case,
+                ..
+            } => {
+                let matcher = query_matcher(function, *regex, *ignore_case)?;
+                items
+                    .keys()
+                    .filter(|item| matcher.is_match(item.search_name()))
+                    .count()
+            }
+            _ => items.len(),
+        };
+        safeprintln!("{count}");
+        if count == 0 {
+            return Err(ExitCode::NoMatch.error("No matching functions"));
+        }
+        return Ok(true);
+    }
+    match goal {
+        ToDump::List => list_items(fmt, items.keys()),
+        ToDump::Sizes => {
+            let sizes = items
+                .keys()
+                .map(|item| (item, item.non_blank_len))
+                .collect();
+            list_sizes(fmt, sizes, "lines");
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Pattern for a plain query, trait impls don't need to be spelled out in full
+///
+/// `::` can step over the trait part of `<foo::Foo as core::iter::Iterator>::next` so both
+/// `Foo::next` and `Iterator::next` find it, paths of the type and the trait can be left out
+/// after `<` and `as`
+fn query_pattern(function: &str) -> String {
+    function
+        .split("::")
+        .map(|segment| {
+            regex::escape(segment)
+                .replace('<', r"<(?:\w+::)*")
+                .replace(" as ", r" as (?:\w+::)*")
+        })
+        .collect::<Vec<_>>()
+        .join(r"(?: as [^<>]*)?>?::")
+}
+
+/// Matcher for demangled names, `function` is a substring unless `regex` is set
+pub fn function_matcher(
+    function: &str,
+    regex: bool,
+    ignore_case: bool,
+) -> Result<regex::Regex, regex::Error> {
+    let pattern = if regex {
+        Cow::from(function)
+    } else {
+        Cow::from(query_pattern(function))
+    };
+    regex::RegexBuilder::new(&pattern)
+        .case_insensitive(ignore_case)
+        .build()
+}
+
+/// [`function_matcher`] with an error ready to be shown to the user
+fn query_matcher(function: &str, regex: bool, ignore_case: bool) -> anyhow::Result<regex::Regex> {
+    function_matcher(function, regex, ignore_case)
+        .map_err(|err| anyhow::anyhow!("{function:?} is not a valid regular expression: {err}"))
+}
+
+/// Pick an item to dump based on a goal, `None` stands for the whole file
+///
+/// Prints suggestions and fails if goal can't be reached or more info is needed. Goals that
+/// don't show code are for [`print_summary`]
 pub fn get_dump_range(
     goal: ToDump,
     fmt: &Format,
     items: &BTreeMap<Item, Range<usize>>,
-) -> Option<Range<usize>> {
+) -> anyhow::Result<Option<Range<usize>>> {
+    if fmt.count || matches!(goal, ToDump::List | ToDump::Sizes) {
+        anyhow::bail!("No code to show for {goal:?}, use print_summary for it");
+    }
     if items.len() == 1 {
-        return Some(
+        return Ok(Some(
             items
                 .values()
                 .next()
                 .cloned()
                 .expect("We just checked there's one item present"),
-        );
+        ));
     }
     match goal {
         // to dump everything just return an empty range
-        ToDump::Everything => None,
+        ToDump::Everything => Ok(None),
 
         // By index without filtering
-        ToDump::ByIndex { value } => {
-            if let Some(range) = items.values().nth(value) {
-                Some(range.clone())
-            } else {
-                let actual = items.len();
-                safeprintln!("You asked to display item #{value} (zero based), but there's only {actual} items");
-                std::process::exit(1);
-            }
-        }
+        ToDump::ByIndex { value } => match items.values().nth(value) {
+            Some(range) => Ok(Some(range.clone())),
+            None => Err(ExitCode::NoMatch.error(format!(
+                "You asked to display item #{value} (zero based), but there's only {} items",
+                items.len()
+            ))),
+        },
 
         // By index with filtering
-        ToDump::Function { function, nth } => {
+        ToDump::Function {
+            function,
+            nth,
+            regex,
+            ignore_case,
+            interactive,
+        } => {
+            let matcher = query_matcher(&function, regex, ignore_case)?;
             let filtered = items
                 .iter()
-                .filter(|(item, _range)| item.name.contains(&function))
+                .filter(|(item, _range)| matcher.is_match(item.search_name()))
                 .collect::<Vec<_>>();
 
+            let nth = match nth {
+                None => None,
+                Some(Nth::Index(ix)) => Some(ix),
+                Some(Nth::Fragment(fragment)) => {
+                    let picked = filtered
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, (item, _))| item.search_name().contains(&fragment))
+                        .map(|(ix, _)| ix)
+                        .collect::<Vec<_>>();
+                    match picked[..] {
+                        [ix] => Some(ix),
+                        [] => {
+                            safeprintln!(
+                                "None of the items matching {function:?} contain {fragment:?}"
+                            );
+                            return Err(suggest_name(&function, fmt, filtered.iter().map(|x| x.0)));
+                        }
+                        _ => {
+                            safeprintln!(
+                                "{fragment:?} is ambiguous, it matches {} items",
+                                picked.len()
+                            );
+                            return Err(suggest_name(&function, fmt, filtered.iter().map(|x| x.0)));
+                        }
+                    }
+                }
+            };
+
             let range = if nth.is_none() && filtered.len() == 1 {
                 filtered
                     .first()
@@ -180,29 +835,375 @@ pub fn get_dump_range(
             } else if let Some(range) = nth.and_then(|nth| filtered.get(nth)) {
                 range.1.clone()
             } else if let Some(value) = nth {
-                let filtered = filtered.len();
-                safeprintln!("You asked to display item #{value} (zero based), but there's only {filtered} matching items");
-                std::process::exit(1);
+                return Err(ExitCode::NoMatch.error(format!(
+                    "You asked to display item #{value} (zero based), but there's only {} \
+                    matching items",
+                    filtered.len()
+                )));
+            } else if let Some(ix) = (interactive && filtered.len() > 1)
+                .then(|| {
+                    let names = filtered.iter().map(|x| x.0).collect::<Vec<_>>();
+                    pick_interactively(fmt, &names)
+                })
+                .flatten()
+            {
+                filtered[ix].1.clone()
             } else {
                 if filtered.is_empty() {
-                    safeprintln!("Can't find any items matching {function:?}");
-                } else {
-                    suggest_name(&function, &fmt.name_display, filtered.iter().map(|x| x.0));
+                    suggest_similar(&function, fmt, items.keys());
+                    return Err(ExitCode::NoMatch
+                        .error(format!("Can't find any items matching {function:?}")));
                 }
-                std::process::exit(1);
+                return Err(suggest_name(&function, fmt, filtered.iter().map(|x| x.0)));
             };
-            Some(range)
+            Ok(Some(range))
         }
 
-        // Unspecified, so print suggestions and exit
-        ToDump::Unspecified => {
-            let items = items.keys();
-            suggest_name("", &fmt.name_display, items);
-            unreachable!("suggest_name exits");
+        ToDump::List | ToDump::Sizes => unreachable!("handled above"),
+
+        ToDump::At { .. } => anyhow::bail!("--at is only supported for assembly output"),
+
+        // Unspecified, so print suggestions and stop
+        ToDump::Unspecified => Err(suggest_name("", fmt, items.keys())),
+    }
+}
+
+/// Generated code along with the functions found in it, see [`collect_asm`]
+pub struct Parsed {
+    /// Where the code comes from
+    pub generated: build::Generated,
+    /// Format of the code
+    pub syntax: Syntax,
+    /// Contents of the generated file
+    pub lines: cached_lines::CachedLines,
+    /// Functions along with the lines of [`Parsed::lines`] they occupy
+    pub items: BTreeMap<Item, Range<usize>>,
+    /// Lines of every generated file, the library included with `--include-lib` comes second
+    pub parts: Vec<Range<usize>>,
+}
+
+/// Read a generated file, for some reason llvm/rustc can produce non utf8 files...
+fn read_lossy(path: &std::path::Path) -> anyhow::Result<String> {
+    use anyhow::Context;
+    let payload = std::fs::read(path).with_context(|| format!("Can't read {}", path.display()))?;
+    Ok(String::from_utf8_lossy(&payload).into_owned())
+}
+
+/// Functions defined in a single generated file
+fn find_items(
+    syntax: Syntax,
+    lines: &cached_lines::CachedLines,
+) -> anyhow::Result<BTreeMap<Item, Range<usize>>> {
+    Ok(match syntax {
+        // every statement takes exactly one line so ranges of statements are ranges of lines
+        Syntax::Intel | Syntax::Att | Syntax::Wasm | Syntax::McaIntel | Syntax::McaAtt => {
+            asm::find_items(&asm::parse_file(&lines.content)?)
         }
+        Syntax::Llvm | Syntax::LlvmInput => llvm::find_items(lines),
+        Syntax::Mir => mir::find_items(lines),
+        Syntax::Objdump => objdump::find_items(lines),
+    })
+}
+
+/// Items from the same generated file as `range`, see [`Parsed::parts`]
+fn same_part<'a>(
+    items: &'a BTreeMap<Item, Range<usize>>,
+    parts: &[Range<usize>],
+    range: &Range<usize>,
+) -> Cow<'a, BTreeMap<Item, Range<usize>>> {
+    match parts.iter().find(|part| part.contains(&range.start)) {
+        Some(part) if parts.len() > 1 => Cow::Owned(
+            items
+                .iter()
+                .filter(|(_, r)| part.contains(&r.start))
+                .map(|(item, r)| (item.clone(), r.clone()))
+                .collect(),
+        ),
+        _ => Cow::Borrowed(items),
+    }
+}
+
+impl Parsed {
+    /// Read the generated file and find functions in it, code for the library included with
+    /// `--include-lib` is parsed separately and placed after it
+    pub fn parse(generated: build::Generated, syntax: Syntax) -> anyhow::Result<Self> {
+        let lines = if syntax == Syntax::Objdump {
+            objdump::disassemble(&generated.path, generated.target.as_deref())?
+        } else {
+            read_lossy(&generated.path)?
+        };
+        let lines = cached_lines::CachedLines::without_ending(lines);
+        let mut items = find_items(syntax, &lines)?;
+        // a list of ranges, one for every file, not a list of line numbers
+        #[allow(clippy::single_range_in_vec_init)]
+        let mut parts = vec![0..lines.splits.len()];
+        let lines = match &generated.lib {
+            Some(lib) => {
+                let lib = cached_lines::CachedLines::without_ending(read_lossy(lib)?);
+                let offset = lines.splits.len();
+                let lib_items = find_items(syntax, &lib)?
+                    .into_iter()
+                    .map(|(mut item, range)| {
+                        // keep numbering functions with the same name
+                        item.index += items.keys().filter(|i| i.name == item.name).count();
+                        (item, range.start + offset..range.end + offset)
+                    })
+                    .collect::<Vec<_>>();
+                items.extend(lib_items);
+                parts.push(offset..offset + lib.splits.len());
+                let mut content = lines.content;
+                if !content.ends_with('\n') {
+                    content.push('\n');
+                }
+                content.push_str(&lib.content);
+                cached_lines::CachedLines::without_ending(content)
+            }
+            None => lines,
+        };
+        Ok(Self {
+            generated,
+            syntax,
+            lines,
+            items,
+            parts,
+        })
+    }
+
+    /// Functions matching `name` the same way the command line finds them, see
+    /// [`function_matcher`]
+    pub fn find<'a>(
+        &'a self,
+        name: &str,
+        regex: bool,
+        ignore_case: bool,
+    ) -> anyhow::Result<impl Iterator<Item = (&'a Item, &'a Range<usize>)> + 'a> {
+        let matcher = query_matcher(name, regex, ignore_case)?;
+        Ok(self
+            .items
+            .iter()
+            .filter(move |(item, _)| matcher.is_match(item.search_name())))
+    }
+
+    /// Function by index, in the same order as `--list` prints them
+    #[must_use]
+    pub fn nth(&self, index: usize) -> Option<(&Item, &Range<usize>)> {
+        self.items.iter().nth(index)
+    }
+
+    /// Lines that make up a function
+    pub fn body(&self, range: Range<usize>) -> impl Iterator<Item = &str> {
+        range.filter_map(|ix| self.lines.get(ix))
     }
 }
 
+/// Build the code picked by `opts` and find functions in it, without printing anything but
+/// cargo messages and errors
+///
+/// This is the same thing the command line tool does before rendering the output, use
+/// [`render`] to print one of the functions the same way it does.
+pub fn collect_asm(opts: &Options) -> anyhow::Result<Parsed> {
+    let metadata = build::metadata(opts)?;
+    let generated = build::generate(opts, metadata.as_ref())?;
+    Parsed::parse(generated, opts.syntax)
+}
+
+/// What [`render`] printed for a goal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rendered {
+    /// Code of the picked function or of the whole file
+    Code,
+    /// List of functions, their sizes or their count, asked for instead of code
+    Summary,
+}
+
+/// Print `goal` from the parsed code, output goes wherever `safeprint[ln]!` sends it
+///
+/// # Errors
+/// If `goal` can't be found or is ambiguous, see [`ExitCode`], or an external tool fails
+pub fn render(opts: &Options, parsed: &Parsed, goal: ToDump) -> anyhow::Result<Rendered> {
+    let generated = &parsed.generated;
+    let (lines, items) = (&parsed.lines, &parsed.items);
+    match parsed.syntax {
+        Syntax::Intel | Syntax::Att | Syntax::Wasm => {
+            asm::dump_function(goal, parsed, &opts.format)
+        }
+        Syntax::McaAtt | Syntax::McaIntel => mca::dump_function(
+            goal,
+            lines,
+            &opts.format,
+            &opts.mca_arg,
+            parsed.syntax == Syntax::McaIntel,
+            generated.target.as_deref(),
+            opts.mca_cpu.as_ref().or(opts.target_cpu.as_ref()),
+        ),
+        Syntax::Llvm | Syntax::LlvmInput => {
+            llvm::dump_function(goal, lines, items, &parsed.parts, &opts.format)
+        }
+        Syntax::Mir => mir::dump_function(goal, lines, items, &parsed.parts, &opts.format),
+        Syntax::Objdump => {
+            let samples = opts.perf.as_deref().map(perf::Samples::load).transpose()?;
+            objdump::dump_function(goal, lines, items, &opts.format, samples.as_ref())
+        }
+    }
+}
+
+/// Print every goal from the parsed code, several functions are shown one after another with
+/// a header before each of them
+///
+/// # Errors
+/// See [`render`]
+pub fn render_goals(opts: &Options, parsed: &Parsed, goals: Vec<ToDump>) -> anyhow::Result<()> {
+    if let [goal] = &goals[..] {
+        return render(opts, parsed, goal.clone()).map(drop);
+    }
+    for (ix, goal) in goals.into_iter().enumerate() {
+        // JSON documents and HTML sections are printed one after another, without separators
+        if let (ToDump::Function { function, .. }, OutputStyle::Text) = (&goal, opts.format.style) {
+            if ix > 0 {
+                safeprintln!("\n");
+            }
+            safeprintln!("{:=<68}", format!("======================= {function} "));
+        }
+        render(opts, parsed, goal)?;
+    }
+    Ok(())
+}
+
+/// Search for `goal` in the same target of every workspace member and list the matches along
+/// with their packages, a single match is shown right away
+///
+/// # Errors
+/// If nothing matches, in which case members that failed to build are listed, or there are
+/// several matches to pick from
+pub fn search_workspace(
+    opts: &Options,
+    metadata: &cargo_metadata::Metadata,
+    goal: ToDump,
+) -> anyhow::Result<()> {
+    let matcher = match &goal {
+        ToDump::Function {
+            function,
+            regex,
+            ignore_case,
+            ..
+        } => Some(function_matcher(function, *regex, *ignore_case)?),
+        ToDump::List => None,
+        _ => anyhow::bail!("--workspace needs a function name or --list"),
+    };
+    let focus = opts
+        .select_fragment
+        .focus
+        .clone()
+        .unwrap_or(opts::Focus::Lib);
+    let packages = metadata
+        .workspace_packages()
+        .into_iter()
+        .filter(|p| build::defines_focus(p, &focus))
+        .collect::<Vec<_>>();
+    if opts.format.verbosity > 0 {
+        esafeprintln!(
+            "Building {} workspace members, this can take a while",
+            packages.len()
+        );
+    }
+
+    let fmt = &opts.format;
+    let mut results = Vec::new();
+    let mut failed = Vec::new();
+    for package in packages {
+        let mut opts = opts.clone();
+        opts.select_fragment.package = Some(package.name.clone());
+        opts.select_fragment.focus = Some(focus.clone());
+        // one broken member shouldn't hide matches in the others
+        let parsed = match build::generate(&opts, Some(metadata))
+            .and_then(|generated| Parsed::parse(generated, opts.syntax))
+        {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                esafeprintln!("Skipping {}: {err}", package.name);
+                failed.push(package.name.as_str());
+                continue;
+            }
+        };
+        let names = parsed
+            .items
+            .keys()
+            .filter(|item| {
+                matcher
+                    .as_ref()
+                    .is_none_or(|m| m.is_match(item.search_name()))
+            })
+            .map(|item| {
+                item.display_name(fmt.name_display, fmt.keep_hash)
+                    .into_owned()
+            })
+            .collect::<Vec<_>>();
+        results.push((package.name.as_str(), opts, parsed, names));
+    }
+
+    let total = results.iter().map(|r| r.3.len()).sum::<usize>();
+    if matcher.is_some() && total == 1 {
+        let (_, opts, parsed, _) = results
+            .iter()
+            .find(|r| !r.3.is_empty())
+            .expect("There's exactly one match");
+        return render(opts, parsed, goal).map(drop);
+    }
+    if total == 0 && !failed.is_empty() {
+        return Err(ExitCode::BuildFailed.error(format!(
+            "No matching functions in workspace members that built, failed to build: {}",
+            failed.join(", ")
+        )));
+    }
+    if total == 0 {
+        return Err(ExitCode::NoMatch.error("No matching functions in workspace members"));
+    }
+    for (package, _, _, names) in &results {
+        for name in names {
+            safeprintln!("{}: {}", package, color!(name, theme::name));
+        }
+    }
+    if !failed.is_empty() {
+        esafeprintln!("Failed to build: {}", failed.join(", "));
+    }
+    if matcher.is_some() {
+        return Err(ExitCode::Ambiguous.error(
+            "Multiple functions match, pass -p PACKAGE instead of --workspace to see one of them",
+        ));
+    }
+    Ok(())
+}
+
+/// Print a header with the item name and open a code block for `--markdown`
+pub fn markdown_start(fmt: &Format, item: Option<&Item>, lang: &str) {
+    match item {
+        Some(item) if fmt.show_mangled => safeprintln!(
+            "**`{}`** (`{}`)\n",
+            item.display_name(fmt.name_display, fmt.keep_hash),
+            item.mangled_name
+        ),
+        Some(item) => safeprintln!(
+            "**`{}`**\n",
+            item.display_name(fmt.name_display, fmt.keep_hash)
+        ),
+        None => {}
+    }
+    safeprintln!("```{lang}");
+}
+
+/// Print the raw symbol name of the selected item before its code for `--show-mangled`
+pub fn mangled_header(fmt: &Format, item: Option<&Item>) {
+    if let Some(item) = item.filter(|_| fmt.show_mangled) {
+        let header = format!("// mangled: {}", item.mangled_name);
+        safeprintln!("{}", color!(header, theme::comment));
+    }
+}
+
+/// Close a code block opened with [`markdown_start`]
+pub fn markdown_end() {
+    safeprintln!("```");
+}
+
 trait RawLines {
     fn lines(&self) -> Option<&str>;
 }
@@ -252,3 +1253,127 @@ fn get_context_for<R: RawLines>(
     out.sort_by_key(|r| r.start);
     out
 }
+
+/// Ranges of up to `count` items located right before and right after `self_range`
+#[must_use]
+pub fn get_neighbors_for(
+    count: usize,
+    self_range: &Range<usize>,
+    items: &BTreeMap<Item, Range<usize>>,
+) -> (Vec<Range<usize>>, Vec<Range<usize>>) {
+    if count == 0 {
+        return (Vec::new(), Vec::new());
+    }
+    let mut ranges = items.values().cloned().collect::<Vec<_>>();
+    ranges.sort_by_key(|r| r.start);
+    let Some(pos) = ranges.iter().position(|r| r == self_range) else {
+        return (Vec::new(), Vec::new());
+    };
+    let before = ranges[pos.saturating_sub(count)..pos].to_vec();
+    let after = ranges[pos + 1..].iter().take(count).cloned().collect();
+    (before, after)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        dedup_items, edit_distance, function_matcher, name_distance, ExitCode, Item, LineLimit,
+    };
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn edit_distance_works() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("sum_u32", "sum_u23"), 2);
+        assert_eq!(edit_distance("abc", ""), 3);
+    }
+
+    #[test]
+    fn name_distance_uses_segments() {
+        assert_eq!(name_distance("sum_u23", "scratch::sum_u32"), 2);
+        assert_eq!(name_distance("Scratch::Add", "scratch::add"), 0);
+    }
+
+    #[test]
+    fn trait_methods_are_matched() {
+        let name = "<foo::Foo as core::iter::traits::iterator::Iterator>::next";
+        let matches = |query| {
+            function_matcher(query, false, false)
+                .unwrap()
+                .is_match(name)
+        };
+        assert!(matches("Foo::next"));
+        assert!(matches("Iterator::next"));
+        assert!(matches("foo::Foo::next"));
+        assert!(matches("Foo as Iterator::next"));
+        assert!(matches("<Foo as Iterator>::next"));
+        assert!(matches(name));
+        assert!(!matches("Foo::prev"));
+        assert!(!matches("Bar::next"));
+        assert!(function_matcher("sum(", false, false)
+            .unwrap()
+            .is_match("scratch::sum("));
+    }
+
+    #[test]
+    fn lines_past_the_limit_are_hidden() {
+        let mut limit = LineLimit {
+            max: 2,
+            printed: 0,
+            hidden: 0,
+        };
+        assert_eq!(limit.visible(b"one\ntw"), 6);
+        assert_eq!(limit.visible(b"o\nthree\nfour\n"), 2);
+        assert_eq!(limit.visible(b"five\n"), 0);
+        assert_eq!(limit.hidden, 3);
+    }
+
+    #[test]
+    fn exit_codes_survive_context() {
+        use anyhow::Context;
+        let err = Err::<(), _>(ExitCode::Ambiguous.error("Multiple targets found"))
+            .context("Can't pick a target")
+            .unwrap_err();
+        assert_eq!(ExitCode::of(&err), ExitCode::Ambiguous);
+        assert_eq!(ExitCode::of(&anyhow::anyhow!("oops")), ExitCode::Failure);
+        assert_eq!(ExitCode::BuildFailed.code(), 2);
+    }
+
+    #[test]
+    fn identical_items_are_grouped() {
+        let item = |name: &str| Item {
+            name: name.to_owned(),
+            hashed: name.to_owned(),
+            index: 0,
+            len: 2,
+            non_blank_len: 2,
+            mangled_name: format!("_{name}"),
+            duplicates: 0,
+        };
+        let lines = [
+            "_a:",
+            "jmp .LBB0_1",
+            "_b:",
+            "jmp .LBB1_1",
+            "_c:",
+            "jmp .LBB2_2",
+            "_d:",
+            "ret",
+        ];
+        let items = [
+            (item("a"), 0..2),
+            (item("b"), 2..4),
+            (item("c"), 4..6),
+            (item("d"), 6..8),
+        ]
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+        let deduped = dedup_items(&items, |range| lines[range].join("\n"));
+        let names = deduped
+            .keys()
+            .map(|i| (i.name.as_str(), i.duplicates))
+            .collect::<Vec<_>>();
+        assert_eq!(names, [("a", 2), ("d", 0)]);
+    }
+}
@@ -5,13 +5,14 @@ use regex::Regex;
 
 use crate::{
     cached_lines::CachedLines,
-    color,
+    color, dedup_items,
     demangle::{self, contents},
-    get_context_for, get_dump_range,
-    opts::{Format, ToDump},
-    safeprintln, Item,
+    get_context_for, get_dump_range, mangled_header, markdown_end, markdown_start,
+    opts::{Format, OutputStyle, ToDump},
+    print_summary, safeprintln, same_part, Item, Rendered,
 };
 use std::{
+    borrow::Cow,
     collections::BTreeMap,
     fs::File,
     io::{BufRead, BufReader},
@@ -28,7 +29,9 @@ enum State {
     Define,
 }
 
-fn find_items(lines: &CachedLines) -> BTreeMap<Item, Range<usize>> {
+/// Functions defined in the file along with the lines they occupy
+#[must_use]
+pub fn find_items(lines: &CachedLines) -> BTreeMap<Item, Range<usize>> {
     struct ItemParseState {
         item: Item,
         start: usize,
@@ -50,6 +53,7 @@ fn find_items(lines: &CachedLines) -> BTreeMap<Item, Range<usize>> {
                     index: res.len(),
                     len: 0,
                     non_blank_len: 0,
+                    duplicates: 0,
                 },
                 start: ix,
             });
@@ -104,17 +108,41 @@ fn line_is_blank(line: &str) -> bool {
     let is_multiline_instruction_extension = line.starts_with("   ");
     is_comment_or_label || is_multiline_instruction_extension
 }
-pub fn dump_function(goal: ToDump, path: &Path, fmt: &Format) -> anyhow::Result<()> {
-    // For some reason llvm/rustc can produce non utf8 files...
-    let payload = std::fs::read(path)?;
-    let contents = String::from_utf8_lossy(&payload).into_owned();
-
-    let lines = CachedLines::without_ending(contents);
-    let items = find_items(&lines);
+pub fn dump_function(
+    goal: ToDump,
+    lines: &CachedLines,
+    items: &BTreeMap<Item, Range<usize>>,
+    parts: &[Range<usize>],
+    fmt: &Format,
+) -> anyhow::Result<Rendered> {
     let strs = lines.iter().collect::<Vec<_>>();
-    match get_dump_range(goal, fmt, &items) {
+    let selectable = if fmt.dedup {
+        Cow::Owned(dedup_items(items, |range| strs[range].join("\n")))
+    } else {
+        Cow::Borrowed(items)
+    };
+    if print_summary(&goal, fmt, &selectable)? {
+        return Ok(Rendered::Summary);
+    }
+    let range = get_dump_range(goal, fmt, &selectable)?;
+    let item = range
+        .as_ref()
+        .and_then(|range| items.iter().find(|(_, r)| *r == range))
+        .map(|(item, _)| item);
+    let markdown = fmt.style == OutputStyle::Markdown;
+    if markdown {
+        markdown_start(fmt, item, "llvm");
+    } else {
+        mangled_header(fmt, item);
+    }
+    match range {
         Some(range) => {
-            let context = get_context_for(fmt.context, &strs[..], range.clone(), &items);
+            let context = get_context_for(
+                fmt.context,
+                &strs[..],
+                range.clone(),
+                &same_part(items, parts, &range),
+            );
             dump_range(fmt, &strs[range]);
             if !context.is_empty() {
                 safeprintln!(
@@ -128,7 +156,10 @@ pub fn dump_function(goal: ToDump, path: &Path, fmt: &Format) -> anyhow::Result<
         }
         None => dump_range(fmt, &strs),
     };
-    Ok(())
+    if markdown {
+        markdown_end();
+    }
+    Ok(Rendered::Code)
 }
 
 fn dump_range(fmt: &Format, strings: &[&str]) {
@@ -136,7 +167,7 @@ fn dump_range(fmt: &Format, strings: &[&str]) {
         if line.starts_with("; ") {
             safeprintln!("{}", color!(line, OwoColorize::bright_cyan));
         } else {
-            let line = demangle::contents(line, fmt.name_display);
+            let line = demangle::contents(line, fmt.name_display, fmt.keep_hash);
             safeprintln!("{line}");
         }
     }
@@ -198,7 +229,7 @@ pub fn collect_or_dump(
                     {
                         let hashed = format!("{hashed:?}");
                         let name_entry = names.entry(name.clone()).or_insert(0);
-                        seen = goal.map_or(true, |goal| {
+                        seen = goal.is_none_or(|goal| {
                             (name.as_ref(), *name_entry) == goal || hashed == goal.0
                         });
 
@@ -209,13 +240,14 @@ pub fn collect_or_dump(
                             index: *name_entry,
                             len: ix,
                             non_blank_len: 0,
+                            duplicates: 0,
                         });
                         *name_entry += 1;
 
                         if seen {
                             safeprintln!("{}", color!(name, OwoColorize::cyan));
                             safeprintln!("{}", color!(attrs, OwoColorize::cyan));
-                            safeprintln!("{}", contents(&line, fmt.name_display));
+                            safeprintln!("{}", contents(&line, fmt.name_display, fmt.keep_hash));
                         }
                     } else {
                         state = State::Skipping;
@@ -226,14 +258,13 @@ pub fn collect_or_dump(
             }
             State::Define => {
                 if seen {
-                    safeprintln!("{}", contents(&line, fmt.name_display));
+                    safeprintln!("{}", contents(&line, fmt.name_display, fmt.keep_hash));
                 }
                 if line == "}" {
                     if let Some(mut cur) = current_item.take() {
                         cur.len = ix - cur.len;
                         cur.non_blank_len = cur.len;
-                        if goal.map_or(true, |goal| goal.0.is_empty() || cur.name.contains(goal.0))
-                        {
+                        if goal.is_none_or(|goal| goal.0.is_empty() || cur.name.contains(goal.0)) {
                             items.push(cur);
                         }
                     }
@@ -1,406 +1,751 @@
 use anyhow::Context;
-use cargo_metadata::{Artifact, Message, MetadataCommand, Package};
-use cargo_show_asm::{asm, esafeprintln, llvm, mca, mir, opts};
-use once_cell::sync::Lazy;
+use cargo_show_asm::{
+    asm, build, cache, config, diff, esafeprint, esafeprintln, opts, safeprintln, ExitCode,
+};
 use std::{
-    io::BufReader,
+    ffi::OsString,
+    io::IsTerminal,
     path::{Path, PathBuf},
     process::Stdio,
 };
 
-static CARGO_PATH: Lazy<PathBuf> =
-    Lazy::new(|| std::env::var_os("CARGO").map_or_else(|| "cargo".into(), PathBuf::from));
-static RUSTC_PATH: Lazy<PathBuf> =
-    Lazy::new(|| std::env::var_os("RUSTC").map_or_else(|| "rustc".into(), PathBuf::from));
-
-fn spawn_cargo(
-    cargo: &opts::Cargo,
-    format: &opts::Format,
-    syntax: opts::Syntax,
-    target_cpu: Option<&str>,
-    focus_package: &Package,
-    focus_artifact: &opts::Focus,
-) -> std::io::Result<std::process::Child> {
-    use std::ffi::OsStr;
-
-    let mut cmd = std::process::Command::new(&*CARGO_PATH);
-
-    // Cargo flags.
-    cmd.arg("rustc")
-        // General.
-        .args([
-            "--message-format=json-render-diagnostics",
-            "--color",
-            if format.color { "always" } else { "never" },
-        ])
-        .args(std::iter::repeat("-v").take(format.verbosity))
-        // Workspace location.
-        .arg("--manifest-path")
-        .arg(&cargo.manifest_path)
-        // Artifact selectors.
-        .args(["--package", &focus_package.name])
-        .args(focus_artifact.as_cargo_args())
-        // Compile options.
-        .args(cargo.dry.then_some("--dry"))
-        .args(cargo.frozen.then_some("--frozen"))
-        .args(cargo.locked.then_some("--locked"))
-        .args(cargo.offline.then_some("--offline"))
-        .args(cargo.target.iter().flat_map(|t| ["--target", t]))
-        .args(cargo.unstable.iter().flat_map(|z| ["-Z", z]))
-        .args((syntax == opts::Syntax::Wasm).then_some("--target=wasm32-unknown-unknown"))
-        .args(
-            cargo
-                .target_dir
-                .iter()
-                .flat_map(|t| [OsStr::new("--target-dir"), t.as_ref()]),
-        )
-        .args(
-            cargo
-                .cli_features
-                .no_default_features
-                .then_some("--no-default-features"),
-        )
-        .args(cargo.cli_features.all_features.then_some("--all-features"))
-        .args(
-            cargo
-                .cli_features
-                .features
-                .iter()
-                .flat_map(|feat| ["--features", feat]),
-        );
-    match &cargo.compile_mode {
-        opts::CompileMode::Dev => {}
-        opts::CompileMode::Release => {
-            cmd.arg("--release");
-        }
-        opts::CompileMode::Custom(profile) => {
-            cmd.args(["--profile", profile]);
-        }
+/// Start a pager for the output, the same way git does: `CARGO_ASM_PAGER` or `PAGER`
+/// environment variables are used before falling back to `less`.
+fn spawn_pager() -> Option<std::process::Child> {
+    let pager = std::env::var("CARGO_ASM_PAGER")
+        .or_else(|_| std::env::var("PAGER"))
+        .unwrap_or_else(|_| "less".to_owned());
+    let mut words = pager.split_whitespace();
+    let program = words.next().filter(|p| *p != "cat")?;
+    let mut cmd = std::process::Command::new(program);
+    cmd.args(words).stdin(Stdio::piped());
+    if std::env::var_os("LESS").is_none() {
+        // quit if output fits on one screen, pass colors through, don't clear the screen
+        cmd.env("LESS", "FRX");
     }
+    cmd.spawn().ok()
+}
 
-    // Cargo flags terminator.
-    cmd.arg("--");
-
-    // Rustc flags.
-    cmd
-        // Start with the user-supplied codegen flags, which we might need to override.
-        .args(cargo.codegen.iter().flat_map(|c| ["-C", c]))
-        // Next, we care about asm/wasm/llvm-ir/llvm-mac.
-        .args(["--emit", syntax.emit()])
-        // So only one file gets created.
-        .arg("-Ccodegen-units=1")
-        .args(syntax.format().iter().flat_map(|s| ["-C", s]))
-        .args(target_cpu.iter().map(|cpu| format!("-Ctarget-cpu={cpu}")));
+/// Shell command for `--pipe-through`
+fn shell(command: &str) -> std::process::Command {
+    if cfg!(windows) {
+        let mut cmd = std::process::Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    } else {
+        let mut cmd = std::process::Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    }
+}
 
+/// Send the output to a pager unless it's disabled or output is not a terminal
+fn start_pager(opts: &opts::Options) {
+    // pager would fight with interactive selection for the terminal
+    let interactive = matches!(
+        opts.to_dump,
+        opts::ToDump::Function {
+            interactive: true,
+            ..
+        }
+    );
+    if !opts.no_pager
+        && !interactive
+        && opts.output.is_none()
+        && opts.pipe_through.is_none()
+        && opts.format.style != opts::OutputStyle::Html
+        && std::io::stdout().is_terminal()
     {
-        #[allow(clippy::enum_glob_use)]
-        use opts::Syntax::*;
-        // Debug info is needed to detect function boundaries in asm (Windows/Mac), and to map asm/wasm
-        // output to rust source.
-        if matches!(syntax, Intel | Att | Wasm | McaAtt | McaIntel) {
-            cmd.arg("-Cdebuginfo=2");
+        if let Some(pager) = spawn_pager() {
+            cargo_show_asm::set_pager(pager);
         }
     }
-
-    cmd.stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
 }
 
-fn sysroot() -> anyhow::Result<PathBuf> {
-    let output = std::process::Command::new(&*RUSTC_PATH)
-        .arg("--print=sysroot")
-        .stdin(Stdio::null())
-        .stderr(Stdio::inherit())
-        .stdout(Stdio::piped())
-        .output()?;
-    if !output.status.success() {
-        anyhow::bail!(
-            "Failed to get sysroot. '{RUSTC_PATH:?} --print=sysroot' exited with {}",
-            output.status,
-        );
+/// Run ourselves without `--watch` every time sources in the workspace change
+fn watch(
+    args: &[OsString],
+    metadata: &cargo_metadata::Metadata,
+    quiet: bool,
+) -> anyhow::Result<()> {
+    use std::time::{Duration, SystemTime};
+
+    let changed_since = |time| {
+        metadata
+            .packages
+            .iter()
+            .filter_map(|p| p.manifest_path.parent())
+            .any(|dir| cache::newer_sources(dir.as_std_path(), time))
+    };
+
+    loop {
+        let started = SystemTime::now();
+        // clear the screen and move the cursor to the top left corner
+        esafeprint!("\x1b[2J\x1b[H");
+        let status = rerun(args, &[], None)?.status()?;
+        if !status.success() {
+            esafeprintln!("cargo-asm failed with {status}");
+        }
+        if !quiet {
+            esafeprintln!("Waiting for changes...");
+        }
+
+        while !changed_since(started) {
+            std::thread::sleep(Duration::from_millis(500));
+        }
+        // editors tend to save several files at once, wait for things to settle down
+        loop {
+            let checked = SystemTime::now();
+            std::thread::sleep(Duration::from_millis(200));
+            if !changed_since(checked) {
+                break;
+            }
+        }
     }
-    // `rustc` prints a trailing newline.
-    Ok(PathBuf::from(
-        std::str::from_utf8(&output.stdout)?.trim_end(),
-    ))
 }
 
-#[allow(clippy::too_many_lines)]
-fn main() -> anyhow::Result<()> {
-    use opts::Syntax;
+/// Environment variable that marks runs of this program started by itself, such runs take the
+/// command line as is: config defaults are already applied and there's nothing to watch or to
+/// split by target
+const CHILD_ENV: &str = "CARGO_ASM_CHILD";
 
-    let opts = opts::options().run();
-    owo_colors::set_override(opts.format.color);
+/// Flags that only make sense for the process started by the user
+const PARENT_FLAGS: &[&str] = &["--watch", "--no-pager"];
 
-    let sysroot = sysroot()?;
-    if opts.format.verbosity > 0 {
-        esafeprintln!("Found sysroot: {}", sysroot.display());
+/// Options that pick where the output goes and what is saved along with it, runs with the
+/// output captured by the parent process never get them
+const OUTPUT_OPTIONS: &[&str] = &[
+    "--output",
+    "-o",
+    "--pipe-through",
+    "--html-output",
+    "--save-artifacts",
+];
+
+/// Command line arguments without any uses of `flags` and `options`, options are dropped along
+/// with their values: `--name VALUE`, `--name=VALUE` or `-nVALUE` for short names
+///
+/// `--color` options are dropped too if `color` is set, it replaces them.
+fn without_options(
+    args: &[OsString],
+    flags: &[&str],
+    options: &[&str],
+    color: Option<bool>,
+) -> Vec<OsString> {
+    let mut res = Vec::new();
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.next() {
+        let Some(str) = arg.to_str() else {
+            res.push(arg.clone());
+            continue;
+        };
+        if str == "--" {
+            res.push(arg.clone());
+            res.extend(args.cloned());
+            break;
+        }
+        if flags.contains(&str) {
+            continue;
+        }
+        if options.contains(&str) {
+            args.next();
+            continue;
+        }
+        let attached = options.iter().any(|name| {
+            str.strip_prefix(name).is_some_and(|rest| {
+                rest.starts_with('=') || (!name.starts_with("--") && !rest.is_empty())
+            })
+        });
+        if attached {
+            continue;
+        }
+        if color.is_some() {
+            if str == "--color" {
+                // the value is optional
+                args.next_if(|v| ["auto", "always", "never"].contains(&&*v.to_string_lossy()));
+                continue;
+            }
+            if str == "--no-color" || str.starts_with("--color=") {
+                continue;
+            }
+        }
+        res.push(arg.clone());
+    }
+    if let Some(color) = color {
+        res.push(format!("--color={}", if color { "always" } else { "never" }).into());
     }
+    res
+}
 
-    let unstable = opts
-        .cargo
-        .unstable
-        .iter()
-        .flat_map(|x| ["-Z".to_owned(), x.clone()])
-        .collect::<Vec<_>>();
+/// Command to run this program again with `args` it was started with, including config
+/// defaults, but without `options` and [`PARENT_FLAGS`], `color` replaces the color choice
+/// if set
+fn rerun(
+    args: &[OsString],
+    options: &[&str],
+    color: Option<bool>,
+) -> anyhow::Result<std::process::Command> {
+    let mut cmd = std::process::Command::new(std::env::current_exe()?);
+    cmd.args(without_options(args, PARENT_FLAGS, options, color))
+        .arg("--no-pager")
+        .env(CHILD_ENV, "1");
+    Ok(cmd)
+}
 
-    let metadata = MetadataCommand::new()
-        .cargo_path(&*CARGO_PATH)
-        .manifest_path(&opts.cargo.manifest_path)
-        .other_options(unstable)
-        .no_deps()
-        .exec()?;
+/// Run ourselves once for every `--target` and print results one after another,
+/// a failure with one of the targets doesn't prevent showing the rest
+fn for_each_target(args: &[OsString], targets: &[String], color: bool) -> anyhow::Result<()> {
+    use std::io::Write;
 
-    let focus_package = match opts.select_fragment.package {
-        Some(name) => metadata
-            .packages
-            .iter()
-            .find(|p| p.name == name)
-            .with_context(|| format!("Package '{name}' is not found"))?,
-        None if metadata.packages.len() == 1 => &metadata.packages[0],
-        None => {
+    let mut failed = Vec::new();
+    for (ix, target) in targets.iter().enumerate() {
+        if ix > 0 {
+            safeprintln!("\n");
+        }
+        safeprintln!("{:=<68}", format!("======================= {target} "));
+        // output is piped so color can't be detected in the child
+        let mut cmd = rerun(args, &[OUTPUT_OPTIONS, &["--target"]].concat(), Some(color))?;
+        cmd.args(["--target", target])
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit());
+        let output = cmd.output()?;
+        if cargo_show_asm::Stdout.write_all(&output.stdout).is_err() {
+            cargo_show_asm::exit(ExitCode::Success);
+        }
+        if !output.status.success() {
             esafeprintln!(
-                "{:?} refers to multiple packages, you need to specify which one to use",
-                opts.cargo.manifest_path
+                "Failed to show results for {target}: cargo-asm exited with {}",
+                output.status
             );
-            for package in &metadata.packages {
-                esafeprintln!("\t-p {}", package.name);
-            }
-            anyhow::bail!("Multiple packages found")
+            failed.push(target.as_str());
         }
+    }
+    if !failed.is_empty() {
+        anyhow::bail!("Failed to show results for {}", failed.join(", "));
+    }
+    Ok(())
+}
+
+fn main() {
+    let res = run();
+    let finished = cargo_show_asm::finish_output();
+    if let Err(err) = res.and(finished) {
+        // exit code is for scripts, not something to show along with the message
+        let message = err
+            .chain()
+            .filter(|e| !e.is::<ExitCode>())
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(": ");
+        esafeprintln!("Error: {message}");
+        std::process::exit(ExitCode::of(&err).code());
+    }
+}
+
+/// Send the output to `--output` file or `--pipe-through` command and pick colors for it,
+/// returns `true` if the output is colored
+fn set_up_output(opts: &opts::Options) -> anyhow::Result<bool> {
+    if let Some(command) = &opts.pipe_through {
+        // the command writes to the output file itself
+        let stdout = match &opts.output {
+            Some(path) => Stdio::from(
+                std::fs::File::create(path)
+                    .with_context(|| format!("Can't create output file {}", path.display()))?,
+            ),
+            None => Stdio::inherit(),
+        };
+        let child = shell(command)
+            .stdin(Stdio::piped())
+            .stdout(stdout)
+            .spawn()
+            .with_context(|| format!("Can't start {command:?}"))?;
+        cargo_show_asm::set_pipe_through(child);
+    } else if let Some(path) = &opts.output {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Can't create output file {}", path.display()))?;
+        cargo_show_asm::set_output(Box::new(file));
+    }
+
+    let color = opts
+        .format
+        .color
+        .or_else(opts::color_from_env)
+        .unwrap_or_else(|| {
+            opts.output.is_none()
+                && opts.pipe_through.is_none()
+                && supports_color::on(supports_color::Stream::Stdout).is_some()
+        });
+    owo_colors::set_override(color && opts.format.style == opts::OutputStyle::Text);
+    if opts.format.style == opts::OutputStyle::Text {
+        cargo_show_asm::theme::set_highlights(&opts.format.highlight, color)
+            .context("Invalid --highlight pattern")?;
+    }
+    Ok(color)
+}
+
+/// Save sections of an HTML page captured from the output, the page is opened in a browser
+/// unless it goes to `--html-output`. Otherwise it is placed in the target directory, or in a
+/// new temporary file if there's none
+fn save_html(opts: &opts::Options, target_dir: Option<&Path>) -> anyhow::Result<()> {
+    let sections = String::from_utf8_lossy(&cargo_show_asm::take_capture()).into_owned();
+    let title = match &opts.to_dump {
+        opts::ToDump::Function { function, .. } => function.as_str(),
+        _ => "cargo asm",
     };
+    let page = cargo_show_asm::html::page(title, &sections);
+    let (path, file) = match (&opts.html_output, target_dir) {
+        (Some(path), _) => (path.clone(), std::fs::File::create(path)),
+        (None, Some(dir)) => {
+            let path = dir.join("cargo-asm.html");
+            let file = std::fs::File::create(&path);
+            (path, file)
+        }
+        (None, None) => {
+            let name = format!("cargo-asm-{}.html", std::process::id());
+            let path = std::env::temp_dir().join(name);
+            let file = std::fs::File::options()
+                .write(true)
+                .create_new(true)
+                .open(&path);
+            (path, file)
+        }
+    };
+    let mut file = file.with_context(|| format!("Can't create {}", path.display()))?;
+    std::io::Write::write_all(&mut file, page.as_bytes())
+        .with_context(|| format!("Can't write HTML page to {}", path.display()))?;
+    if !opts.format.quiet {
+        esafeprintln!("Saved HTML page to {}", path.display());
+    }
+    if opts.html_output.is_none() {
+        cargo_show_asm::html::open(&path);
+    }
+    Ok(())
+}
 
-    let focus_artifact = match opts.select_fragment.focus {
-        Some(focus) => focus,
-        None => match focus_package.targets.len() {
-            0 => anyhow::bail!("No targets found"),
-            1 => opts::Focus::try_from(&focus_package.targets[0])?,
-            _ => {
-                esafeprintln!(
-                    "{} defines multiple targets, you need to specify which one to use:",
-                    focus_package.name
-                );
-                for target in &focus_package.targets {
-                    if let Ok(focus) = opts::Focus::try_from(target) {
-                        esafeprintln!("\t{}", focus.as_cargo_args().collect::<Vec<_>>().join(" "));
-                    }
-                }
-                anyhow::bail!("Multiple targets found")
-            }
-        },
+fn run() -> anyhow::Result<()> {
+    use opts::{OutputStyle, Syntax};
+
+    let opts = opts::options().run();
+
+    build::select_toolchain(&opts)?;
+
+    if opts.version_verbose {
+        return build::version_verbose();
+    }
+
+    let metadata = build::metadata(&opts)?;
+
+    // runs started by ourselves get config defaults on the command line
+    let child = std::env::var_os(CHILD_ENV).is_some();
+    let mut args = std::env::args_os().skip(1).collect::<Vec<_>>();
+    let mut opts = if child {
+        opts
+    } else {
+        config::with_defaults(opts, &mut args, metadata.as_ref())
     };
+    opts.normalize()?;
+    opts.check()?;
+    if opts.format.theme == "list" {
+        cargo_show_asm::theme::list_themes();
+        return Ok(());
+    }
+    if !cargo_show_asm::theme::set_theme(&opts.format.theme) {
+        anyhow::bail!(
+            "Unknown theme {:?}, use --theme list to see available themes",
+            opts.format.theme
+        );
+    }
 
-    let mut cargo_child = spawn_cargo(
-        &opts.cargo,
-        &opts.format,
-        opts.syntax,
-        opts.target_cpu.as_deref(),
-        focus_package,
-        &focus_artifact,
-    )?;
+    if let (true, false, Some(metadata)) = (opts.watch, child, &metadata) {
+        return watch(&args, metadata, opts.format.quiet);
+    }
 
-    let mut result_artifact = None;
-    let mut success = false;
-    for msg in Message::parse_stream(BufReader::new(cargo_child.stdout.take().unwrap())) {
-        match msg? {
-            Message::CompilerArtifact(artifact) if focus_artifact.matches_artifact(&artifact) => {
-                result_artifact = Some(artifact);
-            }
-            Message::BuildFinished(fin) => {
-                success = fin.success;
-                break;
+    let color = set_up_output(&opts)?;
+    let html = opts.format.style == OutputStyle::Html;
+
+    if opts.cargo.target.len() > 1 && !child {
+        start_pager(&opts);
+        return for_each_target(&args, &opts.cargo.target, color);
+    }
+
+    cargo_show_asm::demangle::set_mangling(opts.format.mangling);
+    if opts.stdin {
+        return asm::filter(std::io::stdin().lock(), &opts.format);
+    }
+
+    let goals = opts.to_dump.clone().split_queries()?;
+    if opts.format.count && goals.len() > 1 {
+        anyhow::bail!("--count takes a single function name, use --regex to count several names");
+    }
+
+    if opts.list_targets {
+        let metadata = metadata
+            .as_ref()
+            .context("--list-targets needs a cargo project")?;
+        return build::list_targets(&opts, metadata);
+    }
+
+    if opts.select_fragment.workspace {
+        let metadata = metadata
+            .as_ref()
+            .context("--workspace needs a cargo project")?;
+        let [goal] = &goals[..] else {
+            anyhow::bail!("--workspace can only search for one function at a time");
+        };
+        start_pager(&opts);
+        return cargo_show_asm::search_workspace(&opts, metadata, goal.clone());
+    }
+
+    let mut baseline_features = Vec::new();
+    if let Some(features) = &opts.compare_features {
+        baseline_features = features_without(&opts, metadata.as_ref(), features)?;
+        opts.cargo.cli_features.features.push(features.clone());
+    }
+    let mut baseline = opts
+        .compare
+        .as_ref()
+        .map(|path| {
+            std::fs::read_to_string(path)
+                .map(|text| (path.display().to_string(), text))
+                .with_context(|| format!("Can't read baseline file {}", path.display()))
+        })
+        .transpose()?;
+
+    if opts.cargo.dry && opts.file.is_none() {
+        let plan = build::plan(&opts, metadata.as_ref())?;
+        safeprintln!("{}", serde_json::to_string_pretty(&plan)?);
+        return Ok(());
+    }
+
+    if let Some(opts::Focus::DoctestList) = &opts.select_fragment.focus {
+        return build::list_doctests(&opts, metadata.as_ref());
+    }
+
+    let generated = build::generate(&opts, metadata.as_ref())?;
+
+    start_pager(&opts);
+
+    if opts.raw {
+        use std::io::Write;
+        let contents = if opts.syntax == Syntax::Objdump {
+            cargo_show_asm::objdump::disassemble(&generated.path, generated.target.as_deref())?
+                .into_bytes()
+        } else {
+            let mut contents = Vec::new();
+            for path in std::iter::once(&generated.path).chain(&generated.lib) {
+                contents.extend(
+                    std::fs::read(path)
+                        .with_context(|| format!("Can't read {}", path.display()))?,
+                );
             }
-            _ => {}
+            contents
+        };
+        if cargo_show_asm::Stdout.write_all(&contents).is_err() {
+            cargo_show_asm::exit(ExitCode::Success);
         }
+        return Ok(());
+    }
+
+    let parsed = cargo_show_asm::Parsed::parse(generated, opts.syntax)?;
+
+    if let Some(rev) = &opts.compare_rev {
+        let target_dir = parsed
+            .generated
+            .target_dir
+            .as_ref()
+            .context("No target directory")?;
+        let text = render_at_rev(
+            &args,
+            rev,
+            &opts.cargo.manifest_path,
+            target_dir,
+            opts.format.quiet,
+        )?;
+        baseline = Some((rev.clone(), text));
+    }
+
+    if let Some(features) = &opts.compare_features {
+        let text = render_without_features(&args, &baseline_features)?;
+        baseline = Some((format!("without {features}"), text));
     }
-    // add some spacing between cargo's output and ours
-    esafeprintln!();
-    if !success {
-        let status = cargo_child.wait()?;
-        esafeprintln!("Cargo failed with {status}");
-        std::process::exit(101);
-    }
-    let artifact = result_artifact.context("No artifact found")?;
-
-    if opts.format.verbosity > 0 {
-        esafeprintln!("Artifact files: {:?}", artifact.filenames);
-    }
-
-    let asm_path = locate_asm_path_via_artifact(&artifact, opts.syntax.ext())?;
-    if opts.format.verbosity > 0 {
-        esafeprintln!("Asm file: {}", asm_path.display());
-    }
-
-    match opts.syntax {
-        Syntax::Intel | Syntax::Att | Syntax::Wasm => asm::dump_function(
-            opts.to_dump,
-            &asm_path,
-            metadata.workspace_root.as_std_path(),
-            &sysroot,
-            &opts.format,
-        ),
-        Syntax::McaAtt | Syntax::McaIntel => mca::dump_function(
-            opts.to_dump,
-            &asm_path,
-            &opts.format,
-            &opts.mca_arg,
-            opts.syntax == Syntax::McaIntel,
-            &opts.cargo.target,
-            &opts.target_cpu,
-        ),
-        Syntax::Llvm | Syntax::LlvmInput => {
-            llvm::dump_function(opts.to_dump, &asm_path, &opts.format)
+
+    // render into memory to compare with the baseline, without colors as in a saved file
+    if baseline.is_some() {
+        cargo_show_asm::start_capture();
+        owo_colors::set_override(false);
+    } else if html {
+        cargo_show_asm::start_capture();
+    } else if opts.format.style == OutputStyle::Text {
+        cargo_show_asm::limit_lines(opts.format.max_lines);
+    }
+
+    let res = cargo_show_asm::render_goals(&opts, &parsed, goals);
+
+    let hidden = cargo_show_asm::take_hidden_lines();
+    if hidden > 0 {
+        safeprintln!("... {hidden} more lines (use --max-lines 0)");
+    }
+
+    if html {
+        res?;
+        return save_html(&opts, parsed.generated.target_dir.as_deref());
+    }
+
+    if let Some((name, baseline)) = baseline {
+        res?;
+        let current = String::from_utf8_lossy(&cargo_show_asm::take_capture()).into_owned();
+        owo_colors::set_override(color);
+        let differs = diff::print_unified(
+            &name,
+            &diff::normalize(&baseline),
+            "current",
+            &diff::normalize(&current),
+        );
+        if differs {
+            cargo_show_asm::exit(ExitCode::Failure);
         }
-        Syntax::Mir => mir::dump_function(opts.to_dump, &asm_path, &opts.format),
+        if !opts.format.quiet {
+            esafeprintln!("No differences from {name}");
+        }
+        return Ok(());
     }
+    res
 }
 
-fn locate_asm_path_via_artifact(artifact: &Artifact, expect_ext: &str) -> anyhow::Result<PathBuf> {
-    // For lib, test, bench, lib-type example, `filenames` hint the file stem of the asm file.
-    // We could locate asm files precisely.
-    //
-    // `filenames`:
-    // [..]/target/debug/deps/libfoo-01234567.rmeta         # lib by-product
-    // [..]/target/debug/deps/foo-01234567                  # test & bench
-    // [..]/target/debug/deps/example/libfoo-01234567.rmeta # lib-type example by-product
-    // Asm files:
-    // [..]/target/debug/deps/foo-01234567.s
-    // [..]/target/debug/deps/example/foo-01234567.s
-    if let Some(path) = artifact
-        .filenames
-        .iter()
-        .filter(|path| {
-            matches!(
-                path.parent().unwrap().file_name(),
-                Some("deps" | "examples")
-            )
-        })
-        .find_map(|path| {
-            let path = path.with_extension(expect_ext);
-            if path.exists() {
-                return Some(path);
-            }
-            let path = path.with_file_name(path.file_name()?.strip_prefix("lib")?);
-            if path.exists() {
-                return Some(path);
-            }
-            None
-        })
-    {
-        return Ok(path.into_std_path_buf());
+fn git(dir: &Path) -> std::process::Command {
+    let mut cmd = std::process::Command::new("git");
+    cmd.arg("-C").arg(dir).stdin(Stdio::null());
+    cmd
+}
+
+fn git_output(cmd: &mut std::process::Command) -> anyhow::Result<String> {
+    let output = match cmd.stderr(Stdio::inherit()).output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            anyhow::bail!("--compare-rev needs git, but it can't be found")
+        }
+        Err(err) => return Err(err.into()),
+    };
+    if !output.status.success() {
+        anyhow::bail!("{cmd:?} failed with {}", output.status);
     }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_owned())
+}
 
-    // then there's rlib with filenames as following:
-    // `filenames`:
-    // [..]/target/debug/libfoo.a              <+
-    // [..]/target/debug/libfoo.rlib            | <+ Hard linked.
-    // Asm files:                               |  | Or same contents at least
-    // [..]/target/debug/libfoo-01234567.a     <+  |
-    // [..]/target/debug/libfoo-01234567.rlib     <+
-    // [..]/target/debug/foo-01234567.s
+/// Temporary git worktree, removed when dropped
+struct Worktree {
+    repo: PathBuf,
+    path: PathBuf,
+}
 
-    if artifact.target.kind.iter().any(|k| k == "rlib") {
-        let rlib_path = artifact
-            .filenames
-            .iter()
-            .find(|f| f.extension().map_or(false, |e| e == "rlib"))
-            .expect("No rlib?");
-        let deps_dir = rlib_path.with_file_name("deps");
-
-        for entry in deps_dir.read_dir()? {
-            let maybe_origin = entry?.path();
-            if same_contents(&rlib_path, &maybe_origin)? {
-                let name = maybe_origin
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .strip_prefix("lib")
-                    .unwrap();
-                let asm_file = maybe_origin.with_file_name(name).with_extension(expect_ext);
-                if asm_file.exists() {
-                    return Ok(asm_file);
-                }
+impl Drop for Worktree {
+    fn drop(&mut self) {
+        let _ = git(&self.repo)
+            .args(["worktree", "remove", "--force"])
+            .arg(&self.path)
+            .stdout(Stdio::null())
+            .status();
+    }
+}
+
+/// Feature options for the build without `compared` features: requested features minus the
+/// compared ones, default features are dropped if they enable any of them
+fn features_without(
+    opts: &opts::Options,
+    metadata: Option<&cargo_metadata::Metadata>,
+    compared: &str,
+) -> anyhow::Result<Vec<String>> {
+    let compared = compared
+        .split([',', ' '])
+        .filter(|f| !f.is_empty())
+        .collect::<Vec<_>>();
+    let cli = &opts.cargo.cli_features;
+    let mut features = cli
+        .features
+        .iter()
+        .flat_map(|f| f.split([',', ' ']))
+        .filter(|f| !f.is_empty() && !compared.contains(f))
+        .collect::<Vec<_>>();
+    let mut args = Vec::new();
+
+    if let Some(metadata) = metadata {
+        let package = build::select_package(opts, metadata)?;
+        if !cli.no_default_features && enables_any(package, &["default"], &compared) {
+            args.push("--no-default-features".to_owned());
+            // keep the rest of the defaults
+            let defaults = package.features.get("default").into_iter().flatten();
+            features.extend(
+                defaults
+                    .map(String::as_str)
+                    .filter(|f| !enables_any(package, &[f], &compared)),
+            );
+            if !opts.format.quiet {
+                esafeprintln!(
+                    "{} is enabled by default, building the baseline with --no-default-features",
+                    compared.join(", ")
+                );
             }
         }
+        if enables_any(package, &features, &compared) {
+            anyhow::bail!(
+                "--compare-features needs a build without {}, but the other requested features \
+                enable it",
+                compared.join(", ")
+            );
+        }
+    }
+    if !features.is_empty() {
+        args.push("--features".to_owned());
+        args.push(features.join(","));
     }
+    Ok(args)
+}
 
-    // for cdylib we have
-    // [..]/target/debug/deps/xx.d
-    // [..]/target/debug/deps/libxx.so <+ Hard linked/same contents
-    // [..]/target/debug/deps/xx.s      | <- asm file
-    // [..]/target/debug/libxx.d        |
-    // [..]/target/debug/libxx.so      <+ <- artifact
-    //
-    // on windows it's xx.dll / xx.s, on MacOS it's libxx.dylib / xx.s...
-    if artifact.target.kind.iter().any(|k| k == "cdylib") {
-        let cdylib_path = artifact
-            .filenames
-            .iter()
-            .find(|f| {
-                f.extension()
-                    .map_or(false, |e| ["so", "dylib", "dll"].contains(&e))
-            })
-            .expect("No cdylib?");
-        let deps_dir = cdylib_path.with_file_name("deps");
-        for entry in deps_dir.read_dir()? {
-            let entry = entry?;
-            let maybe_origin = entry.path();
-            if same_contents(cdylib_path, &maybe_origin)? {
-                let Some(name) = maybe_origin.file_name() else {
-                    continue;
-                };
-                let Some(name) = name.to_str() else { continue };
-                let name = name.strip_prefix("lib").unwrap_or(name);
-                // on windows this is xx.dll -> xx.s, no lib....
-                let asm_file = maybe_origin.with_file_name(name).with_extension(expect_ext);
-                if asm_file.exists() {
-                    return Ok(asm_file);
-                }
-            }
+/// Does any of `roots` turn on any of `compared`, directly or through the features it enables
+fn enables_any(package: &cargo_metadata::Package, roots: &[&str], compared: &[&str]) -> bool {
+    let mut seen = Vec::new();
+    let mut todo = roots.to_vec();
+    while let Some(feature) = todo.pop() {
+        if compared.contains(&feature) {
+            return true;
+        }
+        if !seen.contains(&feature) {
+            seen.push(feature);
+            let enabled = package.features.get(feature).into_iter().flatten();
+            todo.extend(enabled.map(String::as_str));
         }
     }
+    false
+}
 
-    // For bin or bin-type example artifacts, `filenames` provide hard-linked paths
-    // without extra-filename.
-    // We scans all possible original artifacts by checking hard links,
-    // in order to retrieve the correct extra-filename, and then locate asm files.
-    //
-    // `filenames`, also `executable`:
-    // [..]/target/debug/foobin                    <+
-    // [..]/target/debug/examples/fooexample        | <+ Hard linked.
-    // Origins:                                     |  |
-    // [..]/target/debug/deps/foobin-01234567      <+  |
-    // [..]/target/debug/examples/fooexample-01234567 <+
-    // Asm files:
-    // [..]/target/debug/deps/foobin-01234567.s
-    // [..]/target/debug/examples/fooexample-01234567.s
-    if let Some(exe_path) = &artifact.executable {
-        let parent = exe_path.parent().unwrap();
-        let deps_dir = if parent.file_name() == Some("examples") {
-            parent.to_owned()
-        } else {
-            exe_path.with_file_name("deps")
-        };
+/// Render the same function without the features from `--compare-features`, `features` are
+/// the feature options to use instead of the ones on the command line
+fn render_without_features(args: &[OsString], features: &[String]) -> anyhow::Result<String> {
+    let output = std::env::temp_dir().join(format!("cargo-show-asm-{}.out", std::process::id()));
+    let options = ["--compare-features", "--features"];
+    let status = rerun(args, &[OUTPUT_OPTIONS, &options].concat(), Some(false))?
+        .args(features)
+        .arg("--output")
+        .arg(&output)
+        .status()?;
+    let text = std::fs::read_to_string(&output);
+    let _ = std::fs::remove_file(&output);
+    if !status.success() {
+        anyhow::bail!("Failed to render the function without the features");
+    }
+    Ok(text?)
+}
 
-        for entry in deps_dir.read_dir()? {
-            let maybe_origin = entry?.path();
-            if same_contents(&exe_path, &maybe_origin)? {
-                let asm_file = maybe_origin.with_extension(expect_ext);
-                if asm_file.exists() {
-                    return Ok(asm_file);
-                }
-            }
-        }
+/// Render the same function from a different git revision, using a temporary worktree
+fn render_at_rev(
+    args: &[OsString],
+    rev: &str,
+    manifest_path: &Path,
+    target_dir: &Path,
+    quiet: bool,
+) -> anyhow::Result<String> {
+    let manifest_dir = manifest_path.parent().unwrap_or(Path::new("."));
+    let toplevel = PathBuf::from(git_output(
+        git(manifest_dir).args(["rev-parse", "--show-toplevel"]),
+    )?);
+    let relative_manifest = manifest_path
+        .canonicalize()?
+        .strip_prefix(toplevel.canonicalize()?)
+        .context("Cargo.toml is not inside of a git repository")?
+        .to_path_buf();
+
+    if !quiet && !git_output(git(&toplevel).args(["status", "--porcelain"]))?.is_empty() {
+        esafeprintln!("Uncommitted changes in the working tree are a part of the current version");
     }
 
-    anyhow::bail!("Cannot locate the path to the asm file");
+    let path = std::env::temp_dir().join(format!("cargo-show-asm-{}", std::process::id()));
+    git_output(
+        git(&toplevel)
+            .args(["worktree", "add", "--detach"])
+            .args(quiet.then_some("--quiet"))
+            .arg(&path)
+            .arg(rev),
+    )?;
+    let worktree = Worktree {
+        repo: toplevel,
+        path,
+    };
+
+    let output = worktree.path.join("cargo-show-asm.out");
+    let options = ["--compare-rev", "--manifest-path", "--target-dir"];
+    let status = rerun(args, &[OUTPUT_OPTIONS, &options].concat(), Some(false))?
+        .arg("--manifest-path")
+        .arg(worktree.path.join(relative_manifest))
+        .arg("--target-dir")
+        .arg(target_dir.join("cargo-show-asm-rev"))
+        .arg("--output")
+        .arg(&output)
+        .status()?;
+    if !status.success() {
+        anyhow::bail!("Failed to render the function at {rev}");
+    }
+    Ok(std::fs::read_to_string(&output)?)
 }
 
-fn same_contents<A: AsRef<Path>, B: AsRef<Path>>(a: &A, b: &B) -> anyhow::Result<bool> {
-    Ok(same_file::is_same_file(a, b)?
-        || (std::fs::metadata(a)?.len() == std::fs::metadata(b)?.len()
-            && std::fs::read(a)? == std::fs::read(b)?))
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn filter(args: &[&str], color: Option<bool>) -> Vec<String> {
+        let args = args.iter().map(OsString::from).collect::<Vec<_>>();
+        let options = [OUTPUT_OPTIONS, &["--target"]].concat();
+        without_options(&args, PARENT_FLAGS, &options, color)
+            .into_iter()
+            .map(|a| a.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn options_are_dropped_with_values() {
+        let args = [
+            "asm",
+            "--no-pager",
+            "--target",
+            "x",
+            "--target=y",
+            "-o",
+            "out",
+            "-o/tmp/out",
+            "--output=out",
+            "--pipe-through",
+            "cat",
+            "--save-artifacts=dir",
+            "-M",
+            "foo",
+        ];
+        assert_eq!(filter(&args, None), ["asm", "-M", "foo"]);
+    }
+
+    #[test]
+    fn similar_names_are_kept() {
+        let args = ["--targets", "--outputs", "--watched", "--html", "foo"];
+        assert_eq!(filter(&args, None), args);
+    }
+
+    #[test]
+    fn color_is_replaced() {
+        let args = [
+            "--color",
+            "always",
+            "--no-color",
+            "--color=auto",
+            "--color",
+            "foo",
+        ];
+        assert_eq!(filter(&args, Some(false)), ["foo", "--color=never"]);
+        assert_eq!(filter(&args, None), args);
+    }
+
+    #[test]
+    fn arguments_after_separator_are_kept() {
+        let args = ["--watch", "--", "--target", "x"];
+        assert_eq!(filter(&args, None), ["--", "--target", "x"]);
+    }
 }
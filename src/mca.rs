@@ -1,13 +1,15 @@
+use anyhow::Context;
 use std::{
     io::{BufRead, BufReader},
-    path::Path,
     process::{Command, Stdio},
 };
 
 use crate::{
-    demangle, esafeprintln, get_dump_range,
-    opts::{Format, ToDump},
-    safeprintln,
+    cached_lines::CachedLines,
+    dedup_items, demangle, esafeprintln, get_dump_range, mangled_header, markdown_end,
+    markdown_start,
+    opts::{Format, OutputStyle, ToDump},
+    print_summary, safeprintln, Rendered,
 };
 
 /// dump mca analysis
@@ -16,25 +18,38 @@ use crate::{
 /// Clippy, why do you care?
 pub fn dump_function(
     goal: ToDump,
-    path: &Path,
+    lines: &CachedLines,
     fmt: &Format,
     mca_args: &[String],
     mca_intel: bool,
-    triple: &Option<String>,
-    target_cpu: &Option<String>,
-) -> anyhow::Result<()> {
+    triple: Option<&str>,
+    target_cpu: Option<&String>,
+) -> anyhow::Result<Rendered> {
     use std::io::Write;
 
-    // For some reason llvm/rustc can produce non utf8 files...
-    let payload = std::fs::read(path)?;
-    let contents = String::from_utf8_lossy(&payload).into_owned();
+    let statements = crate::asm::parse_file(&lines.content)?;
+    let functions = crate::asm::in_sections(
+        crate::asm::find_items(&statements),
+        &statements,
+        &fmt.section,
+    );
 
-    let statements = crate::asm::parse_file(&contents)?;
-    let functions = crate::asm::find_items(&statements);
-
-    let lines = contents.lines().collect::<Vec<_>>();
+    let lines = lines.iter().collect::<Vec<_>>();
+    let functions = if fmt.dedup {
+        dedup_items(&functions, |range| lines[range].join("\n"))
+    } else {
+        functions
+    };
 
-    let lines = if let Some(range) = get_dump_range(goal, fmt, &functions) {
+    if print_summary(&goal, fmt, &functions)? {
+        return Ok(Rendered::Summary);
+    }
+    let range = get_dump_range(goal, fmt, &functions)?;
+    let item = range
+        .as_ref()
+        .and_then(|range| functions.iter().find(|(_, r)| *r == range))
+        .map(|(item, _)| item);
+    let lines = if let Some(range) = range {
         &lines[range]
     } else {
         if fmt.verbosity > 0 {
@@ -57,10 +72,12 @@ pub fn dump_function(
     let mca = mca.spawn();
     let mut mca = match mca {
         Ok(mca) => mca,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => anyhow::bail!(
+            "Failed to start llvm-mca, it needs to be installed and available in PATH. \
+            llvm-mca is a part of LLVM tools, try installing llvm package for your system"
+        ),
         Err(err) => {
-            esafeprintln!("Failed to start llvm-mca, do you have it installed? The error was");
-            esafeprintln!("{err}");
-            std::process::exit(1);
+            return Err(err).context("Failed to start llvm-mca, do you have it installed?");
         }
     };
 
@@ -85,16 +102,25 @@ pub fn dump_function(
     writeln!(i, ".cfi_endproc")?;
     drop(i);
 
+    let markdown = fmt.style == OutputStyle::Markdown;
+    if markdown {
+        markdown_start(fmt, item, "text");
+    } else {
+        mangled_header(fmt, item);
+    }
     for line in BufRead::lines(BufReader::new(o)) {
         let line = line?;
-        let line = demangle::contents(&line, fmt.name_display);
+        let line = demangle::contents(&line, fmt.name_display, fmt.keep_hash);
         safeprintln!("{line}");
     }
+    if markdown {
+        markdown_end();
+    }
 
     for line in BufRead::lines(BufReader::new(e)) {
         let line = line?;
         esafeprintln!("{line}");
     }
 
-    Ok(())
+    Ok(Rendered::Code)
 }
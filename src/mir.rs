@@ -1,13 +1,16 @@
 use crate::{
     cached_lines::CachedLines,
-    color, get_context_for, get_dump_range,
-    opts::{Format, ToDump},
-    safeprintln, Item,
+    color, dedup_items, get_context_for, get_dump_range, mangled_header, markdown_end,
+    markdown_start,
+    opts::{Format, OutputStyle, ToDump},
+    print_summary, safeprintln, same_part, Item, Rendered,
 };
 use owo_colors::OwoColorize;
-use std::{collections::BTreeMap, ops::Range, path::Path};
+use std::{borrow::Cow, collections::BTreeMap, ops::Range};
 
-fn find_items(lines: &CachedLines) -> BTreeMap<Item, Range<usize>> {
+/// Functions defined in the file along with the lines they occupy
+#[must_use]
+pub fn find_items(lines: &CachedLines) -> BTreeMap<Item, Range<usize>> {
     let mut res = BTreeMap::new();
     let mut current_item = None::<Item>;
     let mut block_start = None;
@@ -44,6 +47,7 @@ fn find_items(lines: &CachedLines) -> BTreeMap<Item, Range<usize>> {
                 index: res.len(),
                 len: start,
                 non_blank_len: 0,
+                duplicates: 0,
             });
         }
     }
@@ -65,16 +69,41 @@ fn dump_range(_fmt: &Format, strings: &[&str]) {
 ///
 /// # Errors
 /// Reports file IO errors
-pub fn dump_function(goal: ToDump, path: &Path, fmt: &Format) -> anyhow::Result<()> {
-    // For some reason llvm/rustc can produce non utf8 files...
-    let payload = std::fs::read(path)?;
-    let contents = String::from_utf8_lossy(&payload).into_owned();
-    let lines = CachedLines::without_ending(contents);
-    let items = find_items(&lines);
+pub fn dump_function(
+    goal: ToDump,
+    lines: &CachedLines,
+    items: &BTreeMap<Item, Range<usize>>,
+    parts: &[Range<usize>],
+    fmt: &Format,
+) -> anyhow::Result<Rendered> {
     let strs = lines.iter().collect::<Vec<_>>();
-    match get_dump_range(goal, fmt, &items) {
+    let selectable = if fmt.dedup {
+        Cow::Owned(dedup_items(items, |range| strs[range].join("\n")))
+    } else {
+        Cow::Borrowed(items)
+    };
+    if print_summary(&goal, fmt, &selectable)? {
+        return Ok(Rendered::Summary);
+    }
+    let range = get_dump_range(goal, fmt, &selectable)?;
+    let item = range
+        .as_ref()
+        .and_then(|range| items.iter().find(|(_, r)| *r == range))
+        .map(|(item, _)| item);
+    let markdown = fmt.style == OutputStyle::Markdown;
+    if markdown {
+        markdown_start(fmt, item, "rust");
+    } else {
+        mangled_header(fmt, item);
+    }
+    match range {
         Some(range) => {
-            let context = get_context_for(fmt.context, &strs[..], range.clone(), &items);
+            let context = get_context_for(
+                fmt.context,
+                &strs[..],
+                range.clone(),
+                &same_part(items, parts, &range),
+            );
             dump_range(fmt, &strs[range]);
 
             if !context.is_empty() {
@@ -89,5 +118,8 @@ pub fn dump_function(goal: ToDump, path: &Path, fmt: &Format) -> anyhow::Result<
         }
         None => dump_range(fmt, &strs),
     };
-    Ok(())
+    if markdown {
+        markdown_end();
+    }
+    Ok(Rendered::Code)
 }
@@ -0,0 +1,226 @@
+//! Disassembly of a linked executable with `objdump`, for `--objdump`
+//!
+//! Unlike `--emit asm` this shows the code after linking: functions inlined across crates, LTO
+//! and linker relaxations are all visible here.
+use crate::{
+    cached_lines::CachedLines,
+    color, dedup_items, demangle, esafeprintln, get_context_for, get_dump_range, mangled_header,
+    markdown_end, markdown_start,
+    opts::{is_x86, Format, OutputStyle, ToDump},
+    perf::{Heat, Samples},
+    print_summary, safeprintln, theme, Item, Rendered,
+};
+use anyhow::Context;
+use once_cell::sync::Lazy;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+static OBJDUMP_PATH: Lazy<PathBuf> =
+    Lazy::new(|| std::env::var_os("OBJDUMP").map_or_else(|| "objdump".into(), PathBuf::from));
+
+/// Disassemble the executable, Intel syntax is used for x86 targets
+///
+/// # Errors
+/// If objdump can't be started or fails
+pub fn disassemble(exe: &Path, triple: Option<&str>) -> anyhow::Result<String> {
+    let mut cmd = Command::new(&*OBJDUMP_PATH);
+    cmd.args(["--disassemble", "--no-show-raw-insn"])
+        .args(
+            is_x86(triple)
+                .then_some(["-M", "intel"])
+                .into_iter()
+                .flatten(),
+        )
+        .arg(exe)
+        .stdin(Stdio::null());
+    let output = match cmd.output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => anyhow::bail!(
+            "Failed to start objdump, it needs to be installed and available in PATH. \
+            objdump is a part of GNU binutils, try installing binutils package for your system \
+            or point OBJDUMP variable to llvm-objdump"
+        ),
+        Err(err) => return Err(err).context("Failed to start objdump"),
+    };
+    if !output.status.success() {
+        anyhow::bail!(
+            "objdump failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Symbol name from a function header: `0000000000001130 <_ZN4main4main17h0123456789abcdefE>:`
+fn symbol(line: &str) -> Option<&str> {
+    let (addr, rest) = line.split_once(" <")?;
+    if addr.is_empty() || !addr.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    rest.strip_suffix(">:")
+}
+
+/// Functions found in objdump output along with the lines they occupy, header included
+#[must_use]
+pub fn find_items(lines: &CachedLines) -> BTreeMap<Item, Range<usize>> {
+    let mut res = BTreeMap::new();
+    let mut names = BTreeMap::new();
+    let mut current = None::<(Item, usize)>;
+
+    for (ix, line) in lines.iter().chain([""]).enumerate() {
+        if line.trim().is_empty() {
+            if let Some((mut item, start)) = current.take() {
+                item.len = ix - start;
+                item.non_blank_len = item.len - 1;
+                res.insert(item, start..ix);
+            }
+        } else if let Some(sym) = symbol(line) {
+            let (name, hashed) = match demangle::demangled(sym) {
+                Some(dem) => (format!("{dem:#?}"), format!("{dem:?}")),
+                None => (sym.to_owned(), sym.to_owned()),
+            };
+            let index = names.entry(name.clone()).or_insert(0);
+            current = Some((
+                Item {
+                    mangled_name: sym.to_owned(),
+                    name,
+                    hashed,
+                    index: *index,
+                    len: 0,
+                    non_blank_len: 0,
+                    duplicates: 0,
+                },
+                ix,
+            ));
+            *index += 1;
+        }
+    }
+    res
+}
+
+/// Start address of a function from its header line
+fn start_address(line: &str) -> Option<u64> {
+    let (addr, _) = line.split_once(' ')?;
+    u64::from_str_radix(addr, 16).ok()
+}
+
+/// Print lines of objdump output, with `heat` instructions are prefixed with their share of
+/// samples, relative to the function that starts at a given address
+fn dump_range(fmt: &Format, strings: &[&str], heat: Option<(&Heat, u64)>) {
+    for line in strings {
+        let line = demangle::contents(line, fmt.name_display, fmt.keep_hash);
+        // addresses are mostly noise, but they are what jumps refer to
+        match line.split_once(":\t") {
+            Some((addr, rest)) if !addr.trim().is_empty() => {
+                let prefix = heat
+                    .and_then(|(heat, start)| {
+                        let addr = u64::from_str_radix(addr.trim(), 16).ok()?;
+                        Some(heat.prefix(addr.checked_sub(start)?))
+                    })
+                    .unwrap_or_default();
+                safeprintln!("{prefix}{}:\t{}", color!(addr, theme::comment), rest);
+            }
+            _ => safeprintln!("{line}"),
+        }
+    }
+}
+
+/// Disassemble an executable and print `goal` from it, hot instructions are marked if there
+/// are `samples` for it
+///
+/// # Errors
+/// If objdump fails
+pub fn dump_function(
+    goal: ToDump,
+    lines: &CachedLines,
+    items: &BTreeMap<Item, Range<usize>>,
+    fmt: &Format,
+    samples: Option<&Samples>,
+) -> anyhow::Result<Rendered> {
+    let strs = lines.iter().collect::<Vec<_>>();
+    let selectable = if fmt.dedup {
+        Cow::Owned(dedup_items(items, |range| strs[range].join("\n")))
+    } else {
+        Cow::Borrowed(items)
+    };
+    if print_summary(&goal, fmt, &selectable)? {
+        return Ok(Rendered::Summary);
+    }
+    let range = get_dump_range(goal, fmt, &selectable)?;
+    let item = range
+        .as_ref()
+        .and_then(|range| items.iter().find(|(_, r)| *r == range))
+        .map(|(item, _)| item);
+    let markdown = fmt.style == OutputStyle::Markdown;
+    if markdown {
+        markdown_start(fmt, item, "asm");
+    } else {
+        mangled_header(fmt, item);
+    }
+    let heat = item.zip(samples).and_then(|(item, samples)| {
+        let heat = samples.heat(&item.mangled_name);
+        if heat.is_none() && !fmt.quiet {
+            esafeprintln!("No samples for {} in the profile", item.name);
+        }
+        heat
+    });
+    match range {
+        Some(range) => {
+            let context = get_context_for(fmt.context, &strs[..], range.clone(), items);
+            let start = start_address(strs[range.start]).unwrap_or(0);
+            dump_range(fmt, &strs[range], heat.as_ref().map(|heat| (heat, start)));
+            if let Some(heat) = &heat {
+                heat.print_hottest(start, 5);
+            }
+            if !context.is_empty() {
+                safeprintln!(
+                    "\n\n======================= Additional context ========================="
+                );
+                for range in context {
+                    safeprintln!("\n");
+                    dump_range(fmt, &strs[range], None);
+                }
+            }
+        }
+        None => dump_range(fmt, &strs, None),
+    }
+    if markdown {
+        markdown_end();
+    }
+    Ok(Rendered::Code)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn functions_are_found() {
+        let text = "\n\
+            target/debug/foo:     file format elf64-x86-64\n\
+            \n\
+            Disassembly of section .text:\n\
+            \n\
+            0000000000001130 <_start>:\n    \
+                1130:\txor    ebp,ebp\n    \
+                1132:\tcall   1140 <_ZN3foo4main17h0123456789abcdefE>\n\
+            \n\
+            0000000000001140 <_ZN3foo4main17h0123456789abcdefE>:\n    \
+                1140:\tret\n";
+        let lines = CachedLines::without_ending(text.to_owned());
+        let items = find_items(&lines);
+        let found = items
+            .iter()
+            .map(|(item, range)| (item.name.as_str(), item.non_blank_len, range.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(found, [("_start", 2, 5..8), ("foo::main", 1, 9..11)]);
+        assert_eq!(symbol("    1132:\tcall   1140 <foo>:"), None);
+        assert_eq!(start_address(lines.get(9).unwrap()), Some(0x1140));
+    }
+}
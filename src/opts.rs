@@ -3,12 +3,14 @@ use cargo_metadata::Artifact;
 use std::path::PathBuf;
 
 fn check_target_dir(path: PathBuf) -> anyhow::Result<PathBuf> {
-    if path.is_dir() {
-        Ok(path)
-    } else {
-        std::fs::create_dir(&path)?;
-        Ok(std::fs::canonicalize(path)?)
+    if !path.is_dir() {
+        std::fs::create_dir_all(&path)?;
     }
+    Ok(std::fs::canonicalize(path)?)
+}
+
+fn is_positive(n: &usize) -> bool {
+    *n > 0
 }
 
 #[derive(Clone, Debug, Bpaf)]
@@ -39,6 +41,10 @@ pub struct Options {
     #[bpaf(short('M'), long)]
     pub mca_arg: Vec<String>,
 
+    /// CPU to use for llvm-mca analysis, defaults to the one used for code generation
+    #[bpaf(argument("CPU"), hide_usage)]
+    pub mca_cpu: Option<String>,
+
     // how to display
     /// Generate code for a specific CPU
     #[bpaf(external)]
@@ -48,23 +54,390 @@ pub struct Options {
     #[bpaf(external)]
     pub syntax: Syntax,
 
+    /// Write the output to a file instead of stdout, colors are disabled unless --color always is passed
+    #[bpaf(short('o'), long, argument("PATH"), hide_usage)]
+    pub output: Option<PathBuf>,
+
+    /// Send the output to the standard input of a shell command and show what it prints
+    /// instead, --raw passes the whole file, colors are disabled unless --color always is passed
+    #[bpaf(argument("CMD"), hide_usage)]
+    pub pipe_through: Option<String>,
+
+    /// Save the page produced by --html to a file instead of opening one placed in the target
+    /// directory
+    #[bpaf(argument("PATH"), hide_usage)]
+    pub html_output: Option<PathBuf>,
+
+    /// Print targets available in the workspace or in --package along with the option to pick
+    /// each one, as JSON with --json
+    #[bpaf(hide_usage)]
+    pub list_targets: bool,
+
+    /// Demangle and highlight assembly read from stdin, such as objdump output, without building
+    /// or searching for functions
+    #[bpaf(hide_usage)]
+    pub stdin: bool,
+
+    /// Show results from an existing .s, .ll or .mir file instead of building the code,
+    /// format is picked from the extension unless specified explicitly
+    #[bpaf(argument("PATH"), hide_usage)]
+    pub file: Option<PathBuf>,
+
+    /// Print the whole generated file as is without looking for functions, useful for reporting
+    /// parsing bugs
+    #[bpaf(hide_usage)]
+    pub raw: bool,
+
+    /// Copy the file the code is shown from into DIR as PACKAGE-KIND-NAME.EXT, with the target
+    /// triple if it's specified, the library from --include-lib is copied as well
+    #[bpaf(argument("DIR"), hide_usage)]
+    pub save_artifacts: Option<PathBuf>,
+
+    /// Mark instructions with their share of samples from a perf.data profile, needs --objdump
+    #[bpaf(argument("PATH"), hide_usage)]
+    pub perf: Option<PathBuf>,
+
+    /// Don't send the output to a pager, pager is picked from CARGO_ASM_PAGER/PAGER or less
+    #[bpaf(hide_usage)]
+    pub no_pager: bool,
+
+    /// Compare the results with a file saved earlier with --output, exit with 1 if they differ
+    #[bpaf(argument("BASELINE"), hide_usage)]
+    pub compare: Option<PathBuf>,
+
+    /// Compare the results with the ones built from a git revision, exit with 1 if they differ
+    #[bpaf(argument("REV"), hide_usage)]
+    pub compare_rev: Option<String>,
+
+    /// Compare the results with the ones built without FEATURES, exit with 1 if they differ
+    #[bpaf(argument("FEATURES"), hide_usage)]
+    pub compare_features: Option<String>,
+
+    /// Print versions of cargo-show-asm and rustc along with the host triple and supported
+    /// output kinds, useful for bug reports
+    #[bpaf(hide_usage)]
+    pub version_verbose: bool,
+
+    /// Keep running and show the results again every time sources change
+    #[bpaf(hide_usage)]
+    pub watch: bool,
+
     // what to display
     #[bpaf(external)]
     pub to_dump: ToDump,
 }
 
+impl Options {
+    /// Apply options that change other options: `--simplify`, `package/target` focus, syntax
+    /// picked by `--file` extension and the CPU for `--annotate-cost`
+    ///
+    /// # Errors
+    /// If the options contradict each other
+    pub fn normalize(&mut self) -> anyhow::Result<()> {
+        self.format.expand_simplify();
+        if let Some((package, focus)) = self
+            .select_fragment
+            .focus
+            .as_ref()
+            .and_then(Focus::split_package)
+        {
+            if self
+                .select_fragment
+                .package
+                .as_ref()
+                .is_some_and(|p| *p != package)
+            {
+                anyhow::bail!(
+                    "Target from package {package} can't be used with a different --package"
+                );
+            }
+            self.select_fragment.package = Some(package);
+            self.select_fragment.focus = Some(focus);
+        }
+
+        if let Some(file) = &self.file {
+            if !file.is_file() {
+                anyhow::bail!("{} is not a file", file.display());
+            }
+            if self.watch || self.compare_rev.is_some() || self.compare_features.is_some() {
+                anyhow::bail!(
+                    "--watch, --compare-rev and --compare-features need to build the code, they can't \
+                    be used with --file"
+                );
+            }
+            self.syntax = match file.extension().and_then(|e| e.to_str()) {
+                Some("ll") if !matches!(self.syntax, Syntax::Llvm | Syntax::LlvmInput) => {
+                    Syntax::Llvm
+                }
+                Some("mir") => Syntax::Mir,
+                _ => self.syntax,
+            };
+        }
+
+        if self.format.annotate_cost {
+            let known = || {
+                crate::asm::cost::CPUS
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            let target = self.cargo.target.first().map(String::as_str);
+            let cpu = match &self.format.cost_cpu {
+                Some(cpu) => cpu.clone(),
+                None => match self.target_cpu.as_deref() {
+                    Some(cpu) if crate::asm::cost::table(cpu).is_some() => cpu.to_owned(),
+                    _ if is_x86(target) => "skylake".to_owned(),
+                    _ if is_aarch64(target) => "neoverse-n1".to_owned(),
+                    _ => anyhow::bail!(
+                        "There's no default cost table for {}, pick one with --cost-cpu: {}",
+                        target.unwrap_or(std::env::consts::ARCH),
+                        known()
+                    ),
+                },
+            };
+            if crate::asm::cost::table(&cpu).is_none() {
+                anyhow::bail!(
+                    "No cost table for CPU {cpu:?}, available tables are: {}",
+                    known()
+                );
+            }
+            self.format.cost_cpu = Some(cpu);
+        }
+        Ok(())
+    }
+
+    /// Reject combinations of options that can't work together, options that are ignored
+    /// are reported unless `--quiet` is used
+    ///
+    /// # Errors
+    /// If some options can't be used together
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.select_fragment.dep.is_some()
+            && (self.select_fragment.package.is_some()
+                || self.select_fragment.workspace
+                || self.select_fragment.focus.is_some())
+        {
+            anyhow::bail!(
+                "--dep picks the library of a dependency, it can't be used with --package, \
+                --workspace or a target"
+            );
+        }
+
+        if self.save_artifacts.is_some() && (self.file.is_some() || self.stdin) {
+            anyhow::bail!(
+                "--save-artifacts copies files made by a build, it can't be used with --file or \
+                --stdin"
+            );
+        }
+
+        if self.cargo.no_build && (self.cargo.no_cache || self.watch || self.compare_rev.is_some())
+        {
+            anyhow::bail!("--no-build can't be used with --no-cache, --watch or --compare-rev");
+        }
+
+        if self.select_fragment.workspace && self.select_fragment.package.is_some() {
+            anyhow::bail!("--workspace and --package can't be used together");
+        }
+
+        if self.compare.is_some() && self.compare_rev.is_some() {
+            anyhow::bail!("--compare and --compare-rev can't be used together");
+        }
+        if let Some(features) = &self.compare_features {
+            if self.compare.is_some() || self.compare_rev.is_some() {
+                anyhow::bail!("--compare-features can't be used with --compare or --compare-rev");
+            }
+            if self.cargo.cli_features.all_features {
+                anyhow::bail!(
+                    "--compare-features needs a build without {features}, but --all-features \
+                    enables everything"
+                );
+            }
+        }
+        let comparing =
+            self.compare.is_some() || self.compare_rev.is_some() || self.compare_features.is_some();
+        if comparing && self.format.style == OutputStyle::Html {
+            anyhow::bail!(
+                "--html can't be combined with --compare, --compare-rev or --compare-features"
+            );
+        }
+        if comparing && self.cargo.target.len() > 1 {
+            anyhow::bail!(
+                "--compare, --compare-rev and --compare-features can't be used with several --target"
+            );
+        }
+
+        if self.format.style == OutputStyle::Json
+            && !matches!(self.syntax, Syntax::Intel | Syntax::Att | Syntax::Wasm)
+            && !matches!(self.to_dump, ToDump::Sizes)
+        {
+            anyhow::bail!("JSON output is only supported for assembly");
+        }
+        if self.format.style == OutputStyle::Html {
+            if !matches!(self.syntax, Syntax::Intel | Syntax::Att | Syntax::Wasm) {
+                anyhow::bail!("HTML output is only supported for assembly");
+            }
+            if matches!(self.to_dump, ToDump::List | ToDump::Sizes) || self.format.count {
+                anyhow::bail!(
+                    "--html shows code, it can't be used with --list, --sizes or --count"
+                );
+            }
+        }
+        if self.format.style == OutputStyle::Jsonl
+            && !matches!(self.to_dump, ToDump::List | ToDump::Sizes)
+        {
+            anyhow::bail!("--jsonl is only supported with --list and --sizes");
+        }
+
+        if self.format.quiet && self.format.verbosity > 0 {
+            anyhow::bail!("--quiet and --verbose can't be used together");
+        }
+
+        if self.format.rust && !self.format.quiet {
+            match self.syntax {
+                Syntax::Llvm | Syntax::LlvmInput => {
+                    crate::esafeprintln!(
+                        "llvm-ir doesn't map cleanly to rust source, ignoring --rust"
+                    );
+                }
+                Syntax::Mir => {
+                    crate::esafeprintln!(
+                        "MIR doesn't carry source location comments, ignoring --rust"
+                    );
+                }
+                Syntax::Objdump => {
+                    crate::esafeprintln!(
+                        "objdump output is not annotated with rust source, ignoring --rust"
+                    );
+                }
+                Syntax::Intel | Syntax::Att | Syntax::Wasm | Syntax::McaIntel | Syntax::McaAtt => {}
+            }
+        }
+
+        if self.format.stats
+            && !self.format.quiet
+            && !matches!(self.syntax, Syntax::Intel | Syntax::Att | Syntax::Wasm)
+        {
+            crate::esafeprintln!("Statistics are only available for assembly, ignoring --stats");
+        }
+
+        if self.format.frame
+            && !self.format.quiet
+            && !matches!(self.syntax, Syntax::Intel | Syntax::Att | Syntax::Wasm)
+        {
+            crate::esafeprintln!(
+                "Stack frame summary is only available for assembly, ignoring --frame"
+            );
+        }
+
+        if (self.format.cfg || self.format.cfg_dot)
+            && !self.format.quiet
+            && !matches!(self.syntax, Syntax::Intel | Syntax::Att | Syntax::Wasm)
+        {
+            crate::esafeprintln!("Control flow is only available for assembly, ignoring --cfg");
+        }
+
+        if self.cargo.no_syntax_flag
+            && !self.format.quiet
+            && matches!(self.syntax, Syntax::Att | Syntax::McaAtt)
+        {
+            crate::esafeprintln!(
+                "Assembly syntax comes from your own flags with --no-syntax-flag, ignoring --att"
+            );
+        }
+
+        if (self.format.head.is_some() || self.format.tail.is_some())
+            && !self.format.quiet
+            && !matches!(self.syntax, Syntax::Intel | Syntax::Att | Syntax::Wasm)
+        {
+            crate::esafeprintln!(
+                "--head and --tail are only available for assembly, ignoring them"
+            );
+        }
+
+        if self.format.group_by.is_some()
+            && !self.format.quiet
+            && !matches!(self.syntax, Syntax::Intel | Syntax::Att | Syntax::Wasm)
+        {
+            crate::esafeprintln!("Grouping is only available for assembly, ignoring --group-by");
+        }
+
+        if let (Some(Focus::Doctest(_)), false) =
+            (&self.select_fragment.focus, self.syntax == Syntax::Objdump)
+        {
+            anyhow::bail!(
+                "Doctests can only be shown with --objdump, rustdoc doesn't keep generated assembly"
+            );
+        }
+
+        if self.format.remarks
+            && !self.format.quiet
+            && !matches!(self.syntax, Syntax::Intel | Syntax::Att | Syntax::Wasm)
+        {
+            crate::esafeprintln!(
+                "Optimization remarks are only available for assembly, ignoring --remarks"
+            );
+        }
+
+        if let Some(perf) = &self.perf {
+            if self.syntax != Syntax::Objdump {
+                anyhow::bail!(
+                    "--perf needs --objdump, samples are matched to instructions by their addresses"
+                );
+            }
+            if !perf.is_file() {
+                anyhow::bail!("{} is not a file", perf.display());
+            }
+        }
+
+        if let (Some(2..), false) = (self.cargo.codegen_units, self.syntax == Syntax::Objdump) {
+            anyhow::bail!(
+                "--codegen-units above 1 needs --objdump, other output formats need all the code \
+                in a single file"
+            );
+        }
+
+        if let (Syntax::Wasm, Some(target)) = (self.syntax, self.cargo.target.first()) {
+            if !target.starts_with("wasm") {
+                anyhow::bail!("--wasm needs a wasm target, but --target {target} was requested");
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Bpaf)]
 pub struct SelectFragment {
     // what to compile
     /// Package to use, defaults to a current one,
     ///
     /// required for workspace projects, can also point
-    /// to a dependency
-    #[bpaf(long, short, argument("SPEC"))]
+    /// to a dependency or be a glob such as `isin-*`
+    #[bpaf(long, short, argument("SPEC"), complete(complete_package))]
     pub package: Option<String>,
 
+    /// Show code from the library of a dependency as it's built for this workspace, CRATE is
+    /// a name from Cargo.lock, `name@version` picks one of several versions
+    #[bpaf(argument("CRATE"), hide_usage)]
+    pub dep: Option<String>,
+
+    /// In a workspace without --package pick the first package sorted by name
+    /// instead of asking to specify one
+    #[bpaf(hide_usage)]
+    pub first_match: bool,
+
+    /// Search in every workspace member instead of a single package, matches are listed along
+    /// with their packages
+    #[bpaf(hide_usage)]
+    pub workspace: bool,
+
     #[bpaf(external, optional)]
     pub focus: Option<Focus>,
+
+    /// Also build the library of the package and search in both, useful when code from
+    /// the library is only instantiated by a test, a benchmark or a binary
+    #[bpaf(hide_usage)]
+    pub include_lib: bool,
 }
 
 #[derive(Debug, Clone, Bpaf)]
@@ -76,6 +449,9 @@ pub struct Cargo {
     pub manifest_path: PathBuf,
 
     /// Use custom target directory for generated artifacts, create if missing
+    ///
+    /// Defaults to CARGO_TARGET_DIR, then to build.target-dir from cargo config,
+    /// then to target in the workspace root
     #[bpaf(
         env("CARGO_TARGET_DIR"),
         argument("DIR"),
@@ -84,9 +460,15 @@ pub struct Cargo {
         hide_usage
     )]
     pub target_dir: Option<PathBuf>,
-    /// Produce a build plan instead of actually building
+    /// Print cargo and rustc invocations as a JSON build plan instead of actually building
     #[bpaf(hide_usage)]
     pub dry: bool,
+    /// Always run cargo instead of reusing a file generated by a previous run with the same options
+    #[bpaf(hide_usage)]
+    pub no_cache: bool,
+    /// Don't run cargo, fail if the file generated by a previous run is missing or out of date
+    #[bpaf(hide_usage)]
+    pub no_build: bool,
     /// Requires Cargo.lock and cache are up to date
     #[bpaf(hide_usage)]
     pub frozen: bool,
@@ -100,12 +482,32 @@ pub struct Cargo {
     pub cli_features: CliFeatures,
     #[bpaf(external)]
     pub compile_mode: CompileMode,
-    /// Build for the target triple
+    /// Build for the target triple, repeat to show the results for several targets one after another
     #[bpaf(argument("TRIPLE"))]
-    pub target: Option<String>,
+    pub target: Vec<String>,
+    /// Build the library as this crate type, for packages that produce several kinds of libraries
+    #[bpaf(argument("TYPE"), hide_usage)]
+    pub crate_type: Option<String>,
     /// Codegen flags to rustc, see 'rustc -C help' for details
     #[bpaf(short('C'), argument("FLAG"))]
     pub codegen: Vec<String>,
+    /// Don't pass the assembly syntax to LLVM, for projects that set -x86-asm-syntax themselves
+    #[bpaf(hide_usage)]
+    pub no_syntax_flag: bool,
+    /// Number of codegen units, only --objdump can use more than one
+    ///
+    /// A single unit lets LLVM see the whole crate at once, code is closer to what LTO builds
+    /// produce but builds are slower. Used by default for everything but --objdump
+    #[bpaf(
+        argument("N"),
+        guard(is_positive, "needs at least one codegen unit"),
+        optional,
+        hide_usage
+    )]
+    pub codegen_units: Option<usize>,
+    /// Build with this rustup toolchain, same as `cargo +TOOLCHAIN asm`
+    #[bpaf(argument("TOOLCHAIN"), optional, hide_usage)]
+    pub toolchain: Option<String>,
     /// Unstable (nightly-only) flags to Cargo, see 'cargo -Z help' for details
     // OsString would be better but MetadataCommand takes a vector of strings...
     #[bpaf(short('Z'), argument("FLAG"))]
@@ -119,6 +521,12 @@ pub enum ToDump {
     /// Dump the whole file
     Everything,
 
+    /// List all available functions along with their index
+    List,
+
+    /// List all available functions sorted by size, largest first
+    Sizes,
+
     #[bpaf(hide)]
     ByIndex {
         /// Dump name with this index
@@ -126,26 +534,202 @@ pub enum ToDump {
         value: usize,
     },
 
+    At {
+        /// Dump functions with code from a given source location, requires debug info
+        // parsed later: a failed parse here makes bpaf pick FUNCTION and complain about --at
+        #[bpaf(long("at"), argument("FILE:LINE"))]
+        location: String,
+
+        /// Select specific function when there's several covering the location
+        #[bpaf(positional("INDEX"))]
+        nth: Option<usize>,
+    },
+
     Function {
-        /// Dump a function with a given name, filter functions by name
+        /// Treat FUNCTION as a regular expression matched against demangled names
+        #[bpaf(long, hide_usage)]
+        regex: bool,
+
+        /// Ignore case when matching FUNCTION against demangled names
+        #[bpaf(short('i'), long, hide_usage)]
+        ignore_case: bool,
+
+        /// Ask which function to show when several match FUNCTION, output is not paged
+        #[bpaf(short('I'), long, hide_usage)]
+        interactive: bool,
+
+        /// Dump a function with a given name, filter functions by name, separate several names
+        /// with commas to dump several functions
+        ///
+        /// Trait methods can be found by the type or by the trait: `Foo::next`, `Iterator::next`
+        /// and `Foo as Iterator::next` all match `<foo::Foo as core::iter::Iterator>::next`
         #[bpaf(positional("FUNCTION"))]
         function: String,
 
-        /// Select specific function when there's several with the same name
+        /// Select specific function when there's several with the same name, either by index
+        /// or by a fragment of the name such as `u32`
         #[bpaf(positional("INDEX"))]
-        nth: Option<usize>,
+        nth: Option<Nth>,
     },
 
     #[bpaf(skip)]
     Unspecified,
 }
 
+impl ToDump {
+    /// Split comma separated names into separate goals, regular expressions are used as is
+    ///
+    /// # Errors
+    /// If INDEX is used with several names, it's not clear which one it refers to
+    pub fn split_queries(self) -> anyhow::Result<Vec<Self>> {
+        let ToDump::Function {
+            regex: false,
+            ignore_case,
+            interactive,
+            function,
+            nth,
+        } = self
+        else {
+            return Ok(vec![self]);
+        };
+        let names = split_top_level(&function)
+            .into_iter()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>();
+        if names.len() < 2 {
+            return Ok(vec![ToDump::Function {
+                regex: false,
+                ignore_case,
+                interactive,
+                function,
+                nth,
+            }]);
+        }
+        if nth.is_some() {
+            anyhow::bail!("INDEX can't be used with several comma separated function names");
+        }
+        Ok(names
+            .into_iter()
+            .map(|name| ToDump::Function {
+                regex: false,
+                ignore_case,
+                interactive,
+                function: name.to_owned(),
+                nth: None,
+            })
+            .collect())
+    }
+}
+
+/// Split a list of names on commas, except for the ones inside of generic arguments and
+/// parameter lists such as in `HashMap<K, V>::insert`
+fn split_top_level(names: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut prev = None;
+    for (ix, c) in names.char_indices() {
+        match c {
+            '<' | '(' => depth += 1,
+            // `>` of `->` in `Fn(u8) -> u8` doesn't close anything
+            '>' if prev == Some('-') => {}
+            '>' | ')' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                res.push(&names[start..ix]);
+                start = ix + 1;
+            }
+            _ => {}
+        }
+        prev = Some(c);
+    }
+    res.push(&names[start..]);
+    res
+}
+
+/// Pick one of the functions matching a name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Nth {
+    /// Zero based index
+    Index(usize),
+    /// Fragment of the demangled name, for example one of the generic arguments
+    Fragment(String),
+}
+
+impl std::str::FromStr for Nth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("expected an index or a fragment of the name".to_owned());
+        }
+        Ok(s.parse()
+            .map_or_else(|_| Self::Fragment(s.to_owned()), Self::Index))
+    }
+}
+
+/// Location in the source code, as used by `--at`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: PathBuf,
+    pub line: u64,
+}
+
+impl std::str::FromStr for SourceLocation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (file, line) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected FILE:LINE, got {s:?}"))?;
+        let line = line
+            .parse()
+            .map_err(|_| format!("{line:?} is not a valid line number"))?;
+        if file.is_empty() {
+            return Err(format!("expected FILE:LINE, got {s:?}"));
+        }
+        Ok(Self {
+            file: PathBuf::from(file),
+            line,
+        })
+    }
+}
+
+/// Part of a function between two labels, as used by `--range`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelRange {
+    pub start: Option<String>,
+    pub end: Option<String>,
+    /// Include the code following the `end` label, up to the next label
+    pub inclusive: bool,
+}
+
+impl std::str::FromStr for LabelRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once("..")
+            .ok_or_else(|| format!("expected LABEL..LABEL, got {s:?}"))?;
+        let (end, inclusive) = match end.strip_prefix('=') {
+            Some(end) => (end, true),
+            None => (end, false),
+        };
+        let label = |l: &str| (!l.is_empty()).then(|| l.to_owned());
+        Ok(Self {
+            start: label(start),
+            end: label(end),
+            inclusive,
+        })
+    }
+}
+
 fn target_cpu() -> impl Parser<Option<String>> {
     let native = long("native")
-        .help("Optimize for the CPU running the compiler")
+        .help("Optimize for the CPU running the compiler, produced code might not run elsewhere")
         .req_flag("native".to_string());
     let cpu = long("target-cpu")
-        .help("Optimize code for a specific CPU, see 'rustc --print target-cpus'")
+        .help("Optimize code for a specific CPU, see 'rustc --print target-cpus', also used by llvm-mca")
         .argument::<String>("CPU");
     construct!([native, cpu])
         .custom_usage(&[("TARGET-CPU", Style::Metavar)])
@@ -154,17 +738,37 @@ fn target_cpu() -> impl Parser<Option<String>> {
 
 #[derive(Bpaf, Clone, Debug)]
 pub struct CliFeatures {
-    /// Do not activate `default` feature
+    /// Do not activate `default` feature, other requested features are still activated
     pub no_default_features: bool,
 
-    /// Activate all available features
+    /// Activate all available features, takes priority over the other feature flags
     pub all_features: bool,
 
-    /// A feature to activate, can be used multiple times
-    #[bpaf(argument("FEATURE"))]
+    /// Space or comma separated list of features to activate, can be used multiple times
+    #[bpaf(argument("FEATURES"))]
     pub features: Vec<String>,
 }
 
+impl CliFeatures {
+    /// All the requested features merged into a single comma separated list,
+    /// `None` if there's nothing to pass or `--all-features` makes it redundant
+    #[must_use]
+    pub fn merged_features(&self) -> Option<String> {
+        if self.all_features {
+            return None;
+        }
+        let mut merged = Vec::new();
+        for feature in self.features.iter().flat_map(|f| f.split([',', ' '])) {
+            if !feature.is_empty() && !merged.contains(&feature) {
+                merged.push(feature);
+            }
+        }
+        (!merged.is_empty()).then(|| merged.join(","))
+    }
+}
+
+// Release rather than dev is the default: unoptimized code is rarely what people want to look
+// at, and existing users and scripts rely on getting optimized code without extra flags
 #[derive(Bpaf, Clone, Debug)]
 #[bpaf(fallback(CompileMode::Release))]
 pub enum CompileMode {
@@ -173,12 +777,25 @@ pub enum CompileMode {
     /// Compile in dev mode
     Dev,
     Custom(
-        /// Build for this specific profile, you can also use `dev` and `release` here
+        /// Build for this specific profile, you can also use `dev` and `release` here,
+        /// can't be combined with --release or --dev
         #[bpaf(env("CARGO_SHOW_ASM_PROFILE"), long("profile"), argument("PROFILE"))]
         String,
     ),
 }
 
+impl CompileMode {
+    /// Name of the cargo profile used for the build
+    #[must_use]
+    pub fn profile(&self) -> &str {
+        match self {
+            CompileMode::Release => "release",
+            CompileMode::Dev => "dev",
+            CompileMode::Custom(profile) => profile,
+        }
+    }
+}
+
 fn verbosity() -> impl Parser<usize> {
     short('v')
         .long("verbose")
@@ -193,18 +810,40 @@ fn manifest_path() -> impl Parser<PathBuf> {
     long("manifest-path")
         .help("Path to Cargo.toml, defaults to one in current folder")
         .argument::<PathBuf>("PATH")
-        .parse(|p| {
-            if p.is_absolute() {
-                Ok(p)
-            } else {
-                std::env::current_dir()
-                    .map(|d| d.join(p))
-                    .and_then(|full_path| full_path.canonicalize())
-            }
-        })
+        .parse(resolve_manifest_path)
         .fallback_with(|| std::env::current_dir().map(|x| x.join("Cargo.toml")))
 }
 
+/// Make manifest path absolute, a directory refers to `Cargo.toml` inside of it
+fn resolve_manifest_path(path: PathBuf) -> Result<PathBuf, String> {
+    let mut full_path = if path.is_absolute() {
+        path
+    } else {
+        std::env::current_dir()
+            .map_err(|err| format!("Can't get current directory: {err}"))?
+            .join(path)
+    };
+    if full_path.is_dir() {
+        full_path.push("Cargo.toml");
+    }
+    let Ok(full_path) = full_path.canonicalize() else {
+        return Err(format!(
+            "no Cargo.toml at {}; pass --manifest-path",
+            full_path.display()
+        ));
+    };
+    if full_path
+        .file_name()
+        .is_none_or(|name| name != "Cargo.toml")
+    {
+        return Err(format!(
+            "{} is not a Cargo.toml; pass a path to Cargo.toml or to a directory with it",
+            full_path.display()
+        ));
+    }
+    Ok(full_path)
+}
+
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, Clone, Bpaf)]
 /// Postprocessing options:
@@ -212,32 +851,215 @@ pub struct Format {
     /// Print interleaved Rust code
     pub rust: bool,
 
+    /// With --rust, print source locations as clickable FILE:LINE and show each source line once
+    #[bpaf(hide_usage)]
+    pub rust_numbers: bool,
+
+    /// Group instructions by the source line they come from instead of showing them in order,
+    /// needs debug info, MODE can only be `line`
+    #[bpaf(argument("MODE"), hide_usage)]
+    pub group_by: Option<GroupBy>,
+
     /// Include other called functions, recursively, up to COUNT depth
     #[bpaf(short, long, argument("COUNT"), fallback(0), display_fallback)]
     pub context: usize,
 
+    /// Also show COUNT functions located before and after the selected one in the output
+    #[bpaf(long, argument("COUNT"), fallback(0), hide_usage)]
+    pub neighbors: usize,
+
+    /// Show only the part of the selected function from one label to another, `..=` includes
+    /// the code after the last label, either label can be omitted
+    #[bpaf(argument("LABEL..LABEL"), hide_usage)]
+    pub range: Option<LabelRange>,
+
+    /// Stop after printing N lines of output, 0 prints everything
+    #[bpaf(argument("N"), fallback(0), hide_usage)]
+    pub max_lines: usize,
+
+    /// Show only the first N instructions of each function, can be combined with --tail
+    #[bpaf(argument("N"), optional, hide_usage)]
+    pub head: Option<usize>,
+
+    /// Show only the last N instructions of each function, can be combined with --head
+    #[bpaf(argument("N"), optional, hide_usage)]
+    pub tail: Option<usize>,
+
+    /// Use colors, detected from the output stream if not specified
     #[bpaf(external(color_detection), hide_usage)]
-    pub color: bool,
+    pub color: Option<bool>,
+
+    /// Color theme to use, `--theme list` prints available themes
+    #[bpaf(argument("NAME"), fallback("dark".to_owned()), display_fallback, hide_usage)]
+    pub theme: String,
+
+    /// Emphasize instruction tokens matching PATTERN, a regular expression, can be used
+    /// multiple times
+    #[bpaf(argument("PATTERN"), hide_usage)]
+    pub highlight: Vec<String>,
 
     #[bpaf(hide_usage, external)]
     pub name_display: NameDisplay,
 
+    /// Keep the hash suffix of demangled names, independently of --full-name and --short-name
+    #[bpaf(hide_usage)]
+    pub keep_hash: bool,
+
+    /// Print the raw symbol name of the selected function before its code
+    #[bpaf(hide_usage)]
+    pub show_mangled: bool,
+
+    /// Symbol mangling scheme to demangle: auto, legacy or v0
+    #[bpaf(
+        argument("SCHEME"),
+        fallback(Mangling::Auto),
+        display_fallback,
+        hide_usage
+    )]
+    pub mangling: Mangling,
+
     #[bpaf(external, hide_usage)]
     pub redundant_labels: RedundantLabels,
 
+    /// Rename local jump targets in each function to `.L1`, `.L2`, etc in order of definition
+    #[bpaf(hide_usage)]
+    pub rename_labels: bool,
+
+    /// Show one of each group of functions with identical code along with the number of copies
+    #[bpaf(hide_usage)]
+    pub dedup: bool,
+
     /// more verbose output, can be specified multiple times
     #[bpaf(external)]
     pub verbosity: usize,
 
-    /// Try to strip some of the non-assembly instruction information
+    /// Only print the results and errors, tell cargo to be quiet too
+    #[bpaf(short('q'), long, hide_usage)]
+    pub quiet: bool,
+
+    /// Print the number of functions matching FUNCTION instead of showing them, exit with 3 if
+    /// there are none
+    #[bpaf(hide_usage)]
+    pub count: bool,
+
+    /// Annotate calls and jumps to other functions with the demangled name of the target
+    #[bpaf(hide_usage)]
+    pub resolve_calls: bool,
+
+    /// Annotate instructions with approximate latency and throughput from a built-in table
+    #[bpaf(hide_usage)]
+    pub annotate_cost: bool,
+
+    /// Table to use for --annotate-cost: skylake, znver3 or neoverse-n1, picked from the target
+    /// for x86 and aarch64 by default and required for other architectures
+    #[bpaf(argument("CPU"), hide_usage)]
+    pub cost_cpu: Option<String>,
+
+    /// Prefix each instruction with its position in the function, to line it up with a disassembler
+    #[bpaf(hide_usage)]
+    pub offsets: bool,
+
+    /// Print instruction, branch, call, memory access and branch target counts after the function
+    #[bpaf(hide_usage)]
+    pub stats: bool,
+
+    /// Print stack space reserved by the function prologue and registers it saves
+    #[bpaf(hide_usage)]
+    pub frame: bool,
+
+    /// Print basic blocks of the function and blocks control goes to from each of them
+    #[bpaf(hide_usage)]
+    pub cfg: bool,
+
+    /// Print basic blocks of the function as a Graphviz graph instead of the code
+    #[bpaf(hide_usage)]
+    pub cfg_dot: bool,
+
+    /// Only look for functions in this section and its subsections, can be used several times
+    #[bpaf(argument("NAME"), hide_usage)]
+    pub section: Vec<String>,
+
+    /// Print constants, jump tables and other data the function refers to
+    #[bpaf(hide_usage)]
+    pub data: bool,
+
+    /// Print LLVM optimization remarks for the function, such as loops that weren't vectorized
+    #[bpaf(hide_usage)]
+    pub remarks: bool,
+
+    /// Show only instructions and short labels, unused labels become blank lines
+    ///
+    /// Same as --no-directives --rename-labels --keep-blanks
+    #[bpaf(hide_usage)]
     pub simplify: bool,
 
+    /// Start instruction operands at the same column
+    #[bpaf(hide_usage)]
+    pub align: bool,
+
+    /// Column to start operands at, counted from the mnemonic, implies --align, defaults to 8
+    #[bpaf(
+        argument("N"),
+        guard(is_positive, "width must be positive"),
+        optional,
+        hide_usage
+    )]
+    pub width: Option<usize>,
+
+    /// Strip assembler directives and comments, keep only labels and instructions
+    #[bpaf(hide_usage)]
+    pub no_directives: bool,
+
     /// Keep blank lines
     #[bpaf(short('b'), long, hide_usage)]
     pub keep_blank: bool,
 
     #[bpaf(external)]
     pub sources_from: SourcesFrom,
+
+    #[bpaf(external(output_style), hide_usage)]
+    pub style: OutputStyle,
+}
+
+impl Format {
+    /// Column instruction operands start at, counted from the mnemonic, 0 to keep them as is
+    #[must_use]
+    pub fn operand_column(&self) -> usize {
+        match (self.align, self.width) {
+            (_, Some(width)) => width,
+            (true, None) => 8,
+            (false, None) => 0,
+        }
+    }
+
+    /// Turn on the options `--simplify` stands for: `--no-directives`, `--rename-labels` and
+    /// `--keep-blanks`, unless all the labels are kept with `--keep-labels`
+    pub fn expand_simplify(&mut self) {
+        if !self.simplify {
+            return;
+        }
+        self.no_directives = true;
+        self.rename_labels = true;
+        if self.redundant_labels == RedundantLabels::Strip {
+            self.redundant_labels = RedundantLabels::Blanks;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Bpaf, Eq, PartialEq)]
+#[bpaf(fallback(OutputStyle::Text))]
+pub enum OutputStyle {
+    #[bpaf(skip)]
+    Text,
+    /// Print selected function as a single JSON document, implies --no-color
+    Json,
+    /// Print selected function as a Markdown code block with a header, implies --no-color
+    Markdown,
+    /// Save selected function as a highlighted HTML page and open it, --rust sources can be
+    /// collapsed
+    Html,
+    /// Print --list and --sizes results as one JSON object per line, implies --no-color
+    Jsonl,
 }
 
 #[derive(Debug, Clone, Copy, Bpaf)]
@@ -266,13 +1088,13 @@ pub enum RedundantLabels {
 }
 
 #[derive(Debug, Copy, Clone, Bpaf, Eq, PartialEq)]
-#[bpaf(fallback(NameDisplay::Short))]
+#[bpaf(fallback(NameDisplay::Full))]
 pub enum NameDisplay {
     #[bpaf(long("full-name"))]
-    /// Include full demangled name instead of just prefix
+    /// Include full demangled names with generic arguments (default)
     Full,
 
-    /// Include demangled names without hash suffix (default)
+    /// Include demangled names without generic arguments
     #[bpaf(long("short-name"))]
     Short,
 
@@ -281,6 +1103,58 @@ pub enum NameDisplay {
     Mangled,
 }
 
+/// Which symbol mangling schemes to demangle
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Mangling {
+    /// Both v0 and legacy
+    Auto,
+    /// Legacy `_ZN` symbols only
+    Legacy,
+    /// v0 `_R` symbols only
+    V0,
+}
+
+impl std::str::FromStr for Mangling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "legacy" => Ok(Self::Legacy),
+            "v0" => Ok(Self::V0),
+            _ => Err(format!("expected auto, legacy or v0, got {s:?}")),
+        }
+    }
+}
+
+impl std::fmt::Display for Mangling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Auto => "auto",
+            Self::Legacy => "legacy",
+            Self::V0 => "v0",
+        })
+    }
+}
+
+/// How `--group-by` groups instructions
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GroupBy {
+    /// Source line from debug info
+    Line,
+}
+
+impl std::str::FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "line" | "source-line" => Ok(Self::Line),
+            _ => Err(format!("expected line, got {s:?}")),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Bpaf, Eq, PartialEq, Copy)]
 #[bpaf(custom_usage(&[("OUTPUT-FORMAT", Style::Metavar)]), fallback(Syntax::Intel))]
 /// Pick output format:
@@ -303,6 +1177,39 @@ pub enum Syntax {
     McaIntel,
     /// Show llvm-mca analysis, AT&T style asm
     McaAtt,
+    /// Show disassembly of the linked executable made with objdump, includes effects of
+    /// inlining across crates and LTO
+    Objdump,
+}
+
+/// Architecture part of a target triple (or of a path to a custom target spec), host
+/// architecture if the target is not specified
+fn target_arch(target: Option<&str>) -> &str {
+    match target {
+        Some(triple) => {
+            let name = triple.rsplit(['/', '\\']).next().unwrap_or(triple);
+            name.split('-').next().unwrap_or(name)
+        }
+        None => std::env::consts::ARCH,
+    }
+}
+
+/// Check if a target triple (or a path to a custom target spec) describes x86 or x86_64
+#[must_use]
+pub fn is_x86(target: Option<&str>) -> bool {
+    matches!(
+        target_arch(target),
+        "x86" | "x86_64" | "i386" | "i586" | "i686" | "x86_64h"
+    )
+}
+
+/// Check if a target triple (or a path to a custom target spec) describes 64-bit ARM
+#[must_use]
+pub fn is_aarch64(target: Option<&str>) -> bool {
+    matches!(
+        target_arch(target),
+        "aarch64" | "aarch64_be" | "arm64" | "arm64e" | "arm64ec"
+    )
 }
 
 impl Syntax {
@@ -312,7 +1219,34 @@ impl Syntax {
             Self::Intel | Self::McaIntel => Some("llvm-args=-x86-asm-syntax=intel"),
             Self::Att | Self::McaAtt => Some("llvm-args=-x86-asm-syntax=att"),
             Self::LlvmInput => Some("no-prepopulate-passes"),
-            Self::Wasm | Self::Mir | Self::Llvm => None,
+            Self::Wasm | Self::Mir | Self::Llvm | Self::Objdump => None,
+        }
+    }
+
+    /// Same as [`format`](Self::format), but skips x86 specific flags for other architectures
+    ///
+    /// `target` is the triple passed to cargo, host architecture is used if it's missing
+    #[must_use]
+    pub fn format_for(&self, target: Option<&str>) -> Option<&str> {
+        match self {
+            Self::Intel | Self::McaIntel | Self::Att | Self::McaAtt if !is_x86(target) => None,
+            _ => self.format(),
+        }
+    }
+
+    /// Assembly syntax rustc ends up producing, `None` for outputs that are not assembly and
+    /// for targets other than x86 where there's no choice to report
+    ///
+    /// LLVM defaults to AT&T for x86 on every platform, Intel only comes from `-x86-asm-syntax`.
+    /// `syntax_flag` is false with `--no-syntax-flag` when user flags decide
+    #[must_use]
+    pub fn effective_syntax(&self, target: Option<&str>, syntax_flag: bool) -> Option<&str> {
+        match self {
+            Self::Wasm | Self::Mir | Self::Llvm | Self::LlvmInput | Self::Objdump => None,
+            _ if !is_x86(target) => None,
+            _ if !syntax_flag => Some("AT&T unless -x86-asm-syntax in your flags says otherwise"),
+            Self::Intel | Self::McaIntel => Some("Intel"),
+            Self::Att | Self::McaAtt => Some("AT&T"),
         }
     }
 
@@ -322,6 +1256,7 @@ impl Syntax {
             Self::Intel | Self::Att | Self::Wasm | Self::McaIntel | Self::McaAtt => "asm",
             Self::Llvm | Self::LlvmInput => "llvm-ir",
             Self::Mir => "mir",
+            Self::Objdump => "link",
         }
     }
 
@@ -331,20 +1266,89 @@ impl Syntax {
             Self::Intel | Self::McaAtt | Self::McaIntel | Self::Att | Self::Wasm => "s",
             Self::Llvm | Self::LlvmInput => "ll",
             Self::Mir => "mir",
+            Self::Objdump => std::env::consts::EXE_EXTENSION,
         }
     }
 }
 
-fn color_detection() -> impl Parser<bool> {
-    let yes = long("color")
-        .help("Enable color highlighting")
-        .req_flag(true);
+fn color_detection() -> impl Parser<Option<bool>> {
+    let when = long("color")
+        .help("Use colors: auto, always or never, auto honors FORCE_COLOR and NO_COLOR")
+        .argument::<String>("WHEN")
+        .parse(|when| match when.as_str() {
+            "auto" => Ok(None),
+            "always" => Ok(Some(true)),
+            "never" => Ok(Some(false)),
+            _ => Err(format!("expected auto, always or never, got {when:?}")),
+        });
+    // `--color` without a value used to be the only way to enable colors
+    let yes = long("color").req_flag(Some(true)).hide();
     let no = long("no-color")
-        .help("Disable color highlighting")
-        .req_flag(false);
-    construct!([yes, no]).fallback_with::<_, &str>(|| {
-        Ok(supports_color::on(supports_color::Stream::Stdout).is_some())
-    })
+        .help("Disable color highlighting, same as --color never")
+        .req_flag(Some(false));
+    construct!([when, yes, no]).fallback(None)
+}
+
+/// Color preference from `NO_COLOR` and `FORCE_COLOR` environment variables, if any
+#[must_use]
+pub fn color_from_env() -> Option<bool> {
+    let var = |name| std::env::var_os(name).filter(|v| !v.is_empty());
+    if var("NO_COLOR").is_some() {
+        Some(false)
+    } else {
+        var("FORCE_COLOR").map(|v| v != "0")
+    }
+}
+
+/// Workspace metadata for shell completion, looked up from the current directory
+fn completion_metadata() -> Option<cargo_metadata::Metadata> {
+    cargo_metadata::MetadataCommand::new().no_deps().exec().ok()
+}
+
+fn complete_package(input: &Option<String>) -> Vec<(String, Option<String>)> {
+    let Some(metadata) = completion_metadata() else {
+        return Vec::new();
+    };
+    let input = input.as_deref().unwrap_or_default();
+    metadata
+        .workspace_packages()
+        .into_iter()
+        .filter(|p| p.name.starts_with(input))
+        .map(|p| (p.name.clone(), None))
+        .collect()
+}
+
+fn complete_target(kind: &str, input: &str) -> Vec<(String, Option<String>)> {
+    let Some(metadata) = completion_metadata() else {
+        return Vec::new();
+    };
+    metadata
+        .workspace_packages()
+        .into_iter()
+        .flat_map(|p| p.targets.iter().map(move |t| (p, t)))
+        .filter(|(_, t)| t.kind.iter().any(|k| k == kind) && t.name.starts_with(input))
+        .map(|(p, t)| (t.name.clone(), Some(p.name.clone())))
+        .collect()
+}
+
+#[allow(clippy::ptr_arg)] // bpaf passes a &String here
+fn complete_test(input: &String) -> Vec<(String, Option<String>)> {
+    complete_target("test", input)
+}
+
+#[allow(clippy::ptr_arg)]
+fn complete_bench(input: &String) -> Vec<(String, Option<String>)> {
+    complete_target("bench", input)
+}
+
+#[allow(clippy::ptr_arg)]
+fn complete_example(input: &String) -> Vec<(String, Option<String>)> {
+    complete_target("example", input)
+}
+
+#[allow(clippy::ptr_arg)]
+fn complete_bin(input: &String) -> Vec<(String, Option<String>)> {
+    complete_target("bin", input)
 }
 
 #[derive(Debug, Clone, Bpaf)]
@@ -355,8 +1359,8 @@ pub enum Focus {
     Lib,
 
     Test(
-        /// Show results from an integration test
-        #[bpaf(long("test"), argument("TEST"))]
+        /// Show results from an integration test, use PACKAGE/TEST if the name is not unique
+        #[bpaf(long("test"), argument("TEST"), complete(complete_test))]
         String,
     ),
 
@@ -365,8 +1369,8 @@ pub enum Focus {
     TestList,
 
     Bench(
-        /// Show results from a benchmark
-        #[bpaf(long("bench"), argument("BENCH"))]
+        /// Show results from a benchmark, use PACKAGE/BENCH if the name is not unique
+        #[bpaf(long("bench"), argument("BENCH"), complete(complete_bench))]
         String,
     ),
 
@@ -375,8 +1379,8 @@ pub enum Focus {
     BenchList,
 
     Example(
-        /// Show results from an example
-        #[bpaf(long("example"), argument("EXAMPLE"))]
+        /// Show results from an example, use PACKAGE/EXAMPLE if the name is not unique
+        #[bpaf(long("example"), argument("EXAMPLE"), complete(complete_example))]
         String,
     ),
 
@@ -385,22 +1389,35 @@ pub enum Focus {
     ExampleList,
 
     Bin(
-        /// Show results from a binary
-        #[bpaf(long("bin"), argument("BIN"))]
+        /// Show results from a binary, use PACKAGE/BIN if the name is not unique
+        #[bpaf(long("bin"), argument("BIN"), complete(complete_bin))]
         String,
     ),
 
     // Show available binaries (hidden: cargo shows the list as an error)
     #[bpaf(long("bin"), hide)]
     BinList,
+
+    Doctest(
+        /// Show results from a doctest, needs --objdump and a nightly toolchain
+        #[bpaf(long("doctest"), argument("NAME"))]
+        String,
+    ),
+
+    /// List doctests of the library that can be picked with --doctest
+    #[bpaf(long("doc"))]
+    DoctestList,
 }
 
+/// Target kinds selected with `--lib`
+pub const LIB_KINDS: &[&str] = &["lib", "rlib", "dylib", "cdylib", "staticlib", "proc-macro"];
+
 impl TryFrom<&'_ cargo_metadata::Target> for Focus {
     type Error = anyhow::Error;
 
     fn try_from(target: &cargo_metadata::Target) -> Result<Self, Self::Error> {
         match target.kind.first().map(|s| &**s) {
-            Some("lib" | "rlib" | "cdylib") => Ok(Focus::Lib),
+            Some(kind) if LIB_KINDS.contains(&kind) => Ok(Focus::Lib),
             Some("test") => Ok(Focus::Test(target.name.clone())),
             Some("bench") => Ok(Focus::Bench(target.name.clone())),
             Some("example") => Ok(Focus::Example(target.name.clone())),
@@ -423,14 +1440,37 @@ impl Focus {
             Focus::ExampleList => ("example", None),
             Focus::Bin(name) => ("bin", Some(name)),
             Focus::BinList => ("bin", None),
+            Focus::Doctest(name) => ("doctest", Some(name)),
+            Focus::DoctestList => ("doc", None),
         }
     }
 
+    /// Split `PACKAGE/NAME` form of a target name into the package and the target itself
+    #[must_use]
+    pub fn split_package(&self) -> Option<(String, Focus)> {
+        let (package, name) = self.as_parts().1?.split_once('/')?;
+        let name = name.to_owned();
+        let focus = match self {
+            Focus::Test(_) => Focus::Test(name),
+            Focus::Bench(_) => Focus::Bench(name),
+            Focus::Example(_) => Focus::Example(name),
+            Focus::Bin(_) => Focus::Bin(name),
+            _ => return None,
+        };
+        Some((package.to_owned(), focus))
+    }
+
     pub fn as_cargo_args(&self) -> impl Iterator<Item = String> {
         let (kind, name) = self.as_parts();
         std::iter::once(format!("--{kind}")).chain(name.map(ToOwned::to_owned))
     }
 
+    /// Doctests are built by rustdoc rather than `cargo rustc`
+    #[must_use]
+    pub fn is_doctest(&self) -> bool {
+        matches!(self, Focus::Doctest(_) | Focus::DoctestList)
+    }
+
     #[must_use]
     pub fn matches_artifact(&self, artifact: &Artifact) -> bool {
         let (kind, name) = self.as_parts();
@@ -439,10 +1479,9 @@ impl Focus {
                 .target
                 .kind
                 .iter()
-                .any(|i| ["rlib", "cdylib"].contains(&i.as_str()));
+                .any(|i| LIB_KINDS.contains(&i.as_str()));
         let kind_matches = artifact.target.kind == [kind];
-        (somewhat_matches || kind_matches)
-            && name.map_or(true, |name| artifact.target.name == *name)
+        (somewhat_matches || kind_matches) && name.is_none_or(|name| artifact.target.name == *name)
     }
 }
 
@@ -455,6 +1494,7 @@ fn write_updated(new_val: &str, path: impl AsRef<std::path::Path>) -> std::io::R
         .write(true)
         .read(true)
         .create(true)
+        .truncate(false)
         .open(path)?;
     let mut current_val = String::new();
     file.read_to_string(&mut current_val)?;
@@ -476,3 +1516,162 @@ fn docs_are_up_to_date() {
     let docs = readme.replacen("<USAGE>", &usage, 1);
     assert!(write_updated(&docs, "README.md").unwrap());
 }
+
+#[test]
+fn compile_mode_is_exclusive() {
+    let parse = |args: &[&str]| options().run_inner(args).map(|o| o.cargo.compile_mode);
+    assert!(matches!(parse(&[]), Ok(CompileMode::Release)));
+    assert!(matches!(parse(&["--dev"]), Ok(CompileMode::Dev)));
+    assert!(matches!(parse(&["--profile", "bench"]), Ok(CompileMode::Custom(p)) if p == "bench"));
+    assert!(parse(&["--release", "--dev"]).is_err());
+    assert!(parse(&["--release", "--profile", "dev"]).is_err());
+    assert!(parse(&["--profile", "dev", "--dev"]).is_err());
+}
+
+#[test]
+fn color_takes_a_choice() {
+    let parse = |args: &[&str]| options().run_inner(args).map(|o| o.format.color);
+    assert_eq!(parse(&[]).unwrap(), None);
+    assert_eq!(parse(&["--color", "auto"]).unwrap(), None);
+    assert_eq!(parse(&["--color", "always"]).unwrap(), Some(true));
+    assert_eq!(parse(&["--color=never"]).unwrap(), Some(false));
+    assert_eq!(parse(&["--no-color"]).unwrap(), Some(false));
+    assert_eq!(parse(&["--color"]).unwrap(), Some(true));
+}
+
+#[test]
+fn simplify_sets_underlying_flags() {
+    let parse = |args: &[&str]| {
+        let mut format = options().run_inner(args).unwrap().format;
+        format.expand_simplify();
+        (
+            format.no_directives,
+            format.rename_labels,
+            format.redundant_labels,
+        )
+    };
+    assert_eq!(parse(&[]), (false, false, RedundantLabels::Strip));
+    assert_eq!(
+        parse(&["--simplify"]),
+        (true, true, RedundantLabels::Blanks)
+    );
+    assert_eq!(
+        parse(&["--simplify", "--keep-labels"]),
+        (true, true, RedundantLabels::Keep)
+    );
+}
+
+#[test]
+fn focus_can_name_a_package() {
+    let (package, focus) = Focus::Example("core/demo".to_owned())
+        .split_package()
+        .unwrap();
+    assert_eq!(package, "core");
+    assert_eq!(focus.as_parts(), ("example", Some("demo")));
+    assert!(Focus::Bin("demo".to_owned()).split_package().is_none());
+}
+
+#[test]
+fn label_range_parses() {
+    let range = ".LBB0_3..=.LBB0_5".parse::<LabelRange>().unwrap();
+    assert_eq!(range.start.as_deref(), Some(".LBB0_3"));
+    assert_eq!(range.end.as_deref(), Some(".LBB0_5"));
+    assert!(range.inclusive);
+    let range = "..L4".parse::<LabelRange>().unwrap();
+    assert_eq!(
+        (range.start, range.end, range.inclusive),
+        (None, Some("L4".to_owned()), false)
+    );
+    assert!(".L1".parse::<LabelRange>().is_err());
+}
+
+#[test]
+fn source_location_parses() {
+    let loc = "src/base36.rs:42".parse::<SourceLocation>().unwrap();
+    assert_eq!(loc.file, PathBuf::from("src/base36.rs"));
+    assert_eq!(loc.line, 42);
+    assert!("C:\\src\\lib.rs:7".parse::<SourceLocation>().is_ok());
+    assert!("src/lib.rs".parse::<SourceLocation>().is_err());
+    assert!("src/lib.rs:x".parse::<SourceLocation>().is_err());
+    assert!(":7".parse::<SourceLocation>().is_err());
+}
+
+#[test]
+fn nth_is_index_or_fragment() {
+    assert_eq!("3".parse::<Nth>(), Ok(Nth::Index(3)));
+    assert_eq!("u32".parse::<Nth>(), Ok(Nth::Fragment("u32".to_owned())));
+    assert_eq!("-1".parse::<Nth>(), Ok(Nth::Fragment("-1".to_owned())));
+    assert!("".parse::<Nth>().is_err());
+}
+
+#[test]
+fn queries_are_split() {
+    let parse = |args: &[&str]| {
+        options()
+            .run_inner(args)
+            .unwrap()
+            .to_dump
+            .split_queries()
+            .map(|goals| goals.len())
+    };
+    assert_eq!(parse(&["foo"]).unwrap(), 1);
+    assert_eq!(parse(&["foo,bar, baz"]).unwrap(), 3);
+    assert_eq!(parse(&["foo::<u32, u64>"]).unwrap(), 1);
+    assert_eq!(
+        parse(&["HashMap<K, V>::insert,Vec<(u8, u8)>::push"]).unwrap(),
+        2
+    );
+    assert_eq!(parse(&["call<F: Fn(u8) -> u8, G>,other"]).unwrap(), 2);
+    assert_eq!(parse(&["--regex", "fo{1,2}"]).unwrap(), 1);
+    assert_eq!(parse(&["--everything"]).unwrap(), 1);
+    assert!(parse(&["foo,bar", "1"]).is_err());
+}
+
+#[test]
+fn manifest_path_is_resolved() {
+    let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+    let manifest = root.join("Cargo.toml");
+    assert_eq!(resolve_manifest_path(root.to_owned()).unwrap(), manifest);
+    assert_eq!(resolve_manifest_path(manifest.clone()).unwrap(), manifest);
+    assert!(resolve_manifest_path(root.join("src/lib.rs"))
+        .unwrap_err()
+        .contains("is not a Cargo.toml"));
+    assert!(resolve_manifest_path(root.join("src/Cargo.toml"))
+        .unwrap_err()
+        .starts_with("no Cargo.toml at"));
+    assert!(resolve_manifest_path(root.join("no-such-dir"))
+        .unwrap_err()
+        .starts_with("no Cargo.toml at"));
+}
+
+#[test]
+fn x86_is_detected_from_triple() {
+    assert!(is_x86(Some("x86_64-unknown-linux-gnu")));
+    assert!(is_x86(Some("i686-pc-windows-msvc")));
+    assert!(is_x86(Some("specs/x86_64-custom.json")));
+    assert!(!is_x86(Some("thumbv7em-none-eabihf")));
+    assert!(!is_x86(Some("aarch64-apple-darwin")));
+    assert!(is_aarch64(Some("aarch64-apple-darwin")));
+    assert!(!is_aarch64(Some("thumbv7em-none-eabihf")));
+    assert_eq!(Syntax::Att.format_for(Some("aarch64-apple-darwin")), None);
+    assert_eq!(
+        Syntax::Intel.format_for(Some("x86_64-unknown-linux-gnu")),
+        Syntax::Intel.format()
+    );
+}
+
+#[test]
+fn effective_syntax_is_described() {
+    let x86 = Some("x86_64-pc-windows-msvc");
+    assert_eq!(Syntax::McaIntel.effective_syntax(x86, true), Some("Intel"));
+    assert_eq!(Syntax::Att.effective_syntax(x86, true), Some("AT&T"));
+    assert!(Syntax::Intel
+        .effective_syntax(x86, false)
+        .unwrap()
+        .starts_with("AT&T unless"));
+    assert_eq!(
+        Syntax::Intel.effective_syntax(Some("aarch64-apple-darwin"), true),
+        None
+    );
+    assert_eq!(Syntax::Llvm.effective_syntax(x86, true), None);
+}
@@ -0,0 +1,147 @@
+//! Sample counts from a `perf record` profile, for `--perf`
+//!
+//! Samples are read with `perf script` as symbol and offset pairs, offsets don't depend on where
+//! the executable was loaded so they can be matched with addresses in `--objdump` output.
+use crate::{color, safeprintln};
+use anyhow::Context;
+use owo_colors::OwoColorize;
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/// Sample counts by mangled symbol name and instruction offset from the start of the symbol
+#[derive(Debug, Default)]
+pub struct Samples(BTreeMap<String, BTreeMap<u64, usize>>);
+
+impl Samples {
+    /// Read samples from a `perf.data` file
+    ///
+    /// # Errors
+    /// If perf can't be started or fails
+    pub fn load(perf_data: &Path) -> anyhow::Result<Self> {
+        let mut cmd = Command::new("perf");
+        cmd.args(["script", "--no-demangle", "-F", "ip,sym,symoff", "-i"])
+            .arg(perf_data)
+            .stdin(Stdio::null());
+        let output = match cmd.output() {
+            Ok(output) => output,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                anyhow::bail!(
+                    "Failed to start perf, it needs to be installed and available in PATH"
+                )
+            }
+            Err(err) => return Err(err).context("Failed to start perf"),
+        };
+        if !output.status.success() {
+            anyhow::bail!(
+                "perf script failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(Self::parse(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Samples from `perf script` output: `    55d0c1a0b139 _ZN3foo4main17h0123456789abcdefE+0x9`
+    fn parse(script: &str) -> Self {
+        let mut res = Self::default();
+        for line in script.lines() {
+            let Some((sym, offset)) = line
+                .split_whitespace()
+                .last()
+                .and_then(|loc| loc.rsplit_once("+0x"))
+            else {
+                continue;
+            };
+            let Ok(offset) = u64::from_str_radix(offset, 16) else {
+                continue;
+            };
+            *res.0
+                .entry(sym.to_owned())
+                .or_default()
+                .entry(offset)
+                .or_default() += 1;
+        }
+        res
+    }
+
+    /// Sample counts for a function, `None` if it was never sampled
+    #[must_use]
+    pub fn heat(&self, mangled_name: &str) -> Option<Heat<'_>> {
+        let counts = self.0.get(mangled_name)?;
+        Some(Heat {
+            counts,
+            total: counts.values().sum(),
+        })
+    }
+}
+
+/// Sample counts for a single function
+pub struct Heat<'a> {
+    counts: &'a BTreeMap<u64, usize>,
+    /// Number of samples in the function
+    pub total: usize,
+}
+
+impl Heat<'_> {
+    /// Share of samples at an offset, `None` if there are none
+    fn percent(&self, offset: u64) -> Option<f64> {
+        let count = self.counts.get(&offset).copied().filter(|c| *c > 0)?;
+        #[allow(clippy::cast_precision_loss)]
+        Some(count as f64 * 100.0 / self.total as f64)
+    }
+
+    /// Share of samples at an offset as a prefix of fixed width
+    fn plain_prefix(&self, offset: u64) -> String {
+        self.percent(offset)
+            .map_or_else(|| " ".repeat(8), |percent| format!("{percent:>6.2}% "))
+    }
+
+    /// Share of samples at an offset as a colored prefix, hot instructions stand out
+    #[must_use]
+    pub fn prefix(&self, offset: u64) -> String {
+        let text = self.plain_prefix(offset);
+        let Some(percent) = self.percent(offset) else {
+            return text;
+        };
+        if percent >= 10.0 {
+            color!(text, OwoColorize::red).to_string()
+        } else if percent >= 1.0 {
+            color!(text, OwoColorize::yellow).to_string()
+        } else {
+            text
+        }
+    }
+
+    /// Print the share of samples for the hottest instructions
+    pub fn print_hottest(&self, start: u64, n: usize) {
+        let mut hottest = self.counts.iter().collect::<Vec<_>>();
+        hottest.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        safeprintln!("\n======================= Hottest instructions =======================");
+        for (offset, _) in hottest.into_iter().take(n) {
+            safeprintln!("{}{:x}", self.prefix(*offset), start + offset);
+        }
+        safeprintln!("{} samples in total", self.total);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn samples_are_counted() {
+        let script = "    55d0c1a0b139 _ZN3foo4main17h0123456789abcdefE+0x9\n\
+            \x20   55d0c1a0b139 _ZN3foo4main17h0123456789abcdefE+0x9\n\
+            \x20   55d0c1a0b130 _ZN3foo4main17h0123456789abcdefE+0x0\n\
+            \x20   7f0000001000 [unknown]\n";
+        let samples = Samples::parse(script);
+        let heat = samples.heat("_ZN3foo4main17h0123456789abcdefE").unwrap();
+        assert_eq!(heat.total, 3);
+        assert_eq!(heat.plain_prefix(9).trim(), "66.67%");
+        assert_eq!(heat.plain_prefix(1), "        ");
+        assert!(samples.heat("main").is_none());
+    }
+}
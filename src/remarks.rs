@@ -0,0 +1,112 @@
+//! LLVM optimization remarks reported by rustc with `-Cremark=all`, for `--remarks`
+//!
+//! Remarks come as notes in cargo messages: `src/lib.rs:3:5 loop-vectorize (missed): loop not
+//! vectorized`, they are matched to a function by source locations from its debug info.
+use crate::{color, safeprintln, theme};
+use once_cell::sync::Lazy;
+use std::path::{Path, PathBuf};
+
+static REMARK: Lazy<regex::Regex> = Lazy::new(|| {
+    regex::Regex::new(r"^(.+):(\d+):(\d+) (\S+) \((\w+)\): (.*)$").expect("regexp should be valid")
+});
+
+/// A single optimization remark
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remark {
+    pub file: PathBuf,
+    pub line: u64,
+    pub column: u64,
+    /// LLVM pass that made the remark: `inline`, `loop-vectorize`, etc.
+    pub pass: String,
+    /// `success`, `missed`, `analysis` or `failure`
+    pub kind: String,
+    pub message: String,
+}
+
+impl Remark {
+    /// Remark from the text of a rustc note, `None` if the note is something else
+    #[must_use]
+    pub fn parse(text: &str) -> Option<Self> {
+        let caps = REMARK.captures(text.trim_end())?;
+        Some(Self {
+            file: PathBuf::from(&caps[1]),
+            line: caps[2].parse().ok()?,
+            column: caps[3].parse().ok()?,
+            pass: caps[4].to_owned(),
+            kind: caps[5].to_owned(),
+            message: caps[6].to_owned(),
+        })
+    }
+
+    /// Check if the remark points to `line` in `file`, paths can be relative to different places
+    #[must_use]
+    pub fn is_at(&self, file: &Path, line: u64) -> bool {
+        self.line == line && (self.file.ends_with(file) || file.ends_with(&self.file))
+    }
+}
+
+impl std::fmt::Display for Remark {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{} {} ({}): {}",
+            self.file.display(),
+            self.line,
+            self.column,
+            self.pass,
+            self.kind,
+            self.message
+        )
+    }
+}
+
+/// Print remarks that point to any of the `locations`, in the order rustc reported them
+pub fn print_at<'a>(remarks: &[Remark], locations: impl Iterator<Item = (&'a Path, u64)> + Clone) {
+    let mut found = remarks
+        .iter()
+        .filter(|remark| {
+            locations
+                .clone()
+                .any(|(file, line)| remark.is_at(file, line))
+        })
+        .peekable();
+    safeprintln!("\n======================= Optimization remarks =======================");
+    if found.peek().is_none() {
+        safeprintln!("none for this function");
+    }
+    for remark in found {
+        let location = format!(
+            "{}:{}:{}",
+            remark.file.display(),
+            remark.line,
+            remark.column
+        );
+        safeprintln!(
+            "{} {} ({}): {}",
+            color!(location, theme::location),
+            color!(remark.pass, theme::name),
+            remark.kind,
+            remark.message
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn remarks_are_parsed() {
+        let remark =
+            Remark::parse("src/my lib.rs:12:5 loop-vectorize (missed): loop not vectorized\n")
+                .unwrap();
+        assert_eq!(remark.file, Path::new("src/my lib.rs"));
+        assert_eq!((remark.line, remark.column), (12, 5));
+        assert_eq!(remark.pass, "loop-vectorize");
+        assert_eq!(remark.kind, "missed");
+        assert_eq!(remark.message, "loop not vectorized");
+        assert!(remark.is_at(Path::new("/home/me/foo/src/my lib.rs"), 12));
+        assert!(!remark.is_at(Path::new("/home/me/foo/src/my lib.rs"), 13));
+        assert_eq!(Remark::parse("unused variable: `x`"), None);
+    }
+}
@@ -0,0 +1,228 @@
+//! Color schemes used to highlight the output
+//!
+//! Theme only picks the colors, whether colors are used at all is still decided by
+//! `--color` / `--no-color` and the output stream.
+use crate::safeprintln;
+use once_cell::sync::{Lazy, OnceCell};
+use owo_colors::{Style, Styled};
+use regex::Regex;
+use std::borrow::Cow;
+
+/// Styles for each kind of highlighted item
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    pub instruction: Style,
+    pub register: Style,
+    pub label: Style,
+    pub comment: Style,
+    pub directive: Style,
+    pub section: Style,
+    pub constant: Style,
+    pub name: Style,
+    pub source: Style,
+    pub location: Style,
+}
+
+fn dark() -> Palette {
+    let s = Style::new;
+    Palette {
+        instruction: s().bright_blue(),
+        register: s().bright_white(),
+        label: s().bright_yellow(),
+        comment: s().blue(),
+        directive: s().bright_magenta(),
+        section: s().bright_red(),
+        constant: s().bright_cyan(),
+        name: s().green(),
+        source: s().bright_red(),
+        location: s().cyan(),
+    }
+}
+
+fn light() -> Palette {
+    let s = Style::new;
+    Palette {
+        instruction: s().blue(),
+        register: s().magenta(),
+        label: s().red().bold(),
+        comment: s().bright_black(),
+        directive: s().cyan(),
+        section: s().red(),
+        constant: s().cyan().bold(),
+        name: s().green(),
+        source: s().black().bold(),
+        location: s().bright_black(),
+    }
+}
+
+fn mono() -> Palette {
+    let s = Style::new;
+    Palette {
+        instruction: s().bold(),
+        register: s(),
+        label: s().underline(),
+        comment: s().dimmed(),
+        directive: s().dimmed(),
+        section: s().bold(),
+        constant: s(),
+        name: s().bold(),
+        source: s().italic(),
+        location: s().dimmed(),
+    }
+}
+
+struct Theme {
+    name: &'static str,
+    description: &'static str,
+    palette: fn() -> Palette,
+}
+
+const THEMES: &[Theme] = &[
+    Theme {
+        name: "dark",
+        description: "bright colors for dark backgrounds (default)",
+        palette: dark,
+    },
+    Theme {
+        name: "light",
+        description: "darker colors for light backgrounds",
+        palette: light,
+    },
+    Theme {
+        name: "mono",
+        description: "no colors, only bold, underline and dimmed text",
+        palette: mono,
+    },
+];
+
+static PALETTE: OnceCell<Palette> = OnceCell::new();
+
+/// Pick a theme by name, returns `false` if there's no such theme
+///
+/// Theme can only be picked once, before anything is printed
+pub fn set_theme(name: &str) -> bool {
+    let Some(theme) = THEMES.iter().find(|t| t.name == name) else {
+        return false;
+    };
+    let _ = PALETTE.set((theme.palette)());
+    true
+}
+
+/// Print all the available themes along with their description
+pub fn list_themes() {
+    for theme in THEMES {
+        safeprintln!("{:6} {}", theme.name, theme.description);
+    }
+}
+
+fn palette() -> &'static Palette {
+    PALETTE.get_or_init(dark)
+}
+
+macro_rules! roles {
+    ($($role:ident),* $(,)?) => {
+        $(
+            #[doc = concat!("Highlight `item` as ", stringify!($role), " using the current theme")]
+            pub fn $role<T>(item: &T) -> Styled<&T> {
+                palette().$role.style(item)
+            }
+        )*
+    };
+}
+
+roles!(
+    instruction,
+    register,
+    label,
+    comment,
+    directive,
+    section,
+    constant,
+    name,
+    source,
+    location
+);
+
+static HIGHLIGHTS: OnceCell<Vec<(Regex, Style)>> = OnceCell::new();
+
+// escape sequences added by other highlighting are skipped, only words are matched
+static TOKENS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\x1b\[[0-9;]*m|[\w.$%]+").expect("regexp should be valid"));
+
+/// Emphasize tokens matching any of `patterns`, each pattern gets its own style
+///
+/// Emphasis is applied even if colors are disabled, with `color` set to `false` only bold,
+/// underline and reverse video are used.
+///
+/// # Errors
+/// If any of the patterns is not a valid regular expression
+pub fn set_highlights(patterns: &[String], color: bool) -> Result<(), regex::Error> {
+    let s = Style::new;
+    let styles = if color {
+        [
+            s().black().on_bright_yellow(),
+            s().black().on_bright_cyan(),
+            s().black().on_bright_magenta(),
+            s().black().on_bright_green(),
+        ]
+    } else {
+        [
+            s().bold().underline(),
+            s().reversed(),
+            s().bold().reversed(),
+            s().underline().reversed(),
+        ]
+    };
+    let highlights = patterns
+        .iter()
+        .zip(styles.iter().cycle())
+        .map(|(pattern, style)| Ok((Regex::new(pattern)?, *style)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let _ = HIGHLIGHTS.set(highlights);
+    Ok(())
+}
+
+/// Emphasize tokens in a line set up with [`set_highlights`]
+#[must_use]
+pub fn highlight(line: &str) -> Cow<'_, str> {
+    match HIGHLIGHTS.get() {
+        Some(highlights) if !highlights.is_empty() => highlight_with(line, highlights),
+        _ => Cow::Borrowed(line),
+    }
+}
+
+fn highlight_with<'a>(line: &'a str, highlights: &[(Regex, Style)]) -> Cow<'a, str> {
+    TOKENS.replace_all(line, |caps: &regex::Captures| {
+        let token = &caps[0];
+        match highlights
+            .iter()
+            .find(|(re, _)| !token.starts_with('\x1b') && re.is_match(token))
+        {
+            Some((_, style)) => style.style(token).to_string(),
+            None => token.to_owned(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn whole_tokens_are_highlighted() {
+        let highlights = [
+            (Regex::new("vmov").unwrap(), Style::new().bold()),
+            (Regex::new("^rax$").unwrap(), Style::new().underline()),
+        ];
+        assert_eq!(
+            highlight_with("vmovups ymm0, [rax + 8]", &highlights),
+            "\x1b[1mvmovups\x1b[0m ymm0, [\x1b[4mrax\x1b[0m + 8]"
+        );
+        // existing escape sequences are left alone
+        assert_eq!(
+            highlight_with("\x1b[97mrax\x1b[0m", &highlights[1..]),
+            "\x1b[97m\x1b[4mrax\x1b[0m\x1b[0m"
+        );
+        assert_eq!(highlight_with("mov eax, 1", &highlights), "mov eax, 1");
+    }
+}